@@ -0,0 +1,206 @@
+use super::settings::{BlindConfig, MqttQos, PayloadFormat};
+use crate::models::blind::Capabilities;
+use serde::{Deserialize, Serialize};
+
+/// Perfil de un tipo de dispositivo (`device_type`): qué comandos entiende y cómo
+/// se codifican y se interpretan sus mensajes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceProfile {
+    /// Acepta posiciones absolutas (`SetPosition`)
+    #[serde(default = "default_true")]
+    pub position: bool,
+    /// Acepta inclinación de lamas (además requiere la sección `tilt` de la persiana)
+    #[serde(default)]
+    pub tilt: bool,
+    #[serde(default = "default_true")]
+    pub stop: bool,
+    /// Formato de los comandos si la persiana no define `payload_format`
+    #[serde(default)]
+    pub payload_format: Option<PayloadFormat>,
+    #[serde(default)]
+    pub status_format: StatusFormat,
+    #[serde(default)]
+    pub battery_format: BatteryFormat,
+    /// QoS de los comandos si la persiana no define `qos`
+    #[serde(default)]
+    pub qos: Option<MqttQos>,
+    /// Retención de los comandos si la persiana no define `retain`
+    #[serde(default)]
+    pub retain: Option<bool>,
+    /// Límites para no sobrecalentar el motor; sin límites si falta
+    #[serde(default)]
+    pub protection: Option<MotorProtection>,
+}
+
+/// Protección del motor frente a órdenes repetidas. Los STOP y la inclinación de
+/// lamas no se limitan.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MotorProtection {
+    /// Tiempo mínimo entre un movimiento y otro en sentido contrario; 0 sin límite
+    #[serde(default)]
+    pub min_reversal_interval_ms: u64,
+    /// Movimientos permitidos en cada `window_secs`; 0 sin límite
+    #[serde(default)]
+    pub max_moves: u32,
+    #[serde(default = "default_protection_window_secs")]
+    pub window_secs: u64,
+    /// Reposo tras superar `max_moves` para que el motor se enfríe; 0 solo
+    /// espera a que haya sitio en la ventana
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+fn default_protection_window_secs() -> u64 {
+    60
+}
+
+/// Cómo interpretar los mensajes del `status_topic`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusFormat {
+    /// Detecta JSON, posición numérica o estado en texto
+    #[default]
+    Auto,
+    /// Objeto JSON con `state`, `position` y/o `tilt`
+    Json,
+    /// Solo la posición (`0`-`100`)
+    Position,
+    /// Solo el estado en texto (`OPEN`, `closed`, ...)
+    Text,
+}
+
+/// Cómo interpretar un número sin unidad en el `battery_topic`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatteryFormat {
+    /// Porcentaje, salvo que el payload indique otra unidad
+    #[default]
+    Auto,
+    Percent,
+    Voltage,
+    Millivolts,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        Self {
+            position: true,
+            tilt: true,
+            stop: true,
+            payload_format: None,
+            status_format: StatusFormat::Auto,
+            battery_format: BatteryFormat::Auto,
+            qos: None,
+            retain: None,
+            protection: None,
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// Perfiles incluidos; los tipos desconocidos usan el perfil por defecto
+    pub fn builtin(device_type: &str) -> Self {
+        match device_type {
+            "roller_shutter" => Self {
+                tilt: false,
+                ..Self::default()
+            },
+            "relay_blind" => Self {
+                position: false,
+                tilt: false,
+                status_format: StatusFormat::Text,
+                ..Self::default()
+            },
+            "zigbee_cover" => Self {
+                payload_format: Some(PayloadFormat::Zigbee2mqtt),
+                status_format: StatusFormat::Json,
+                battery_format: BatteryFormat::Percent,
+                ..Self::default()
+            },
+            // Cortinas Zigbee que solo aceptan abrir/cerrar/parar
+            "zigbee_relay_cover" => Self {
+                position: false,
+                tilt: false,
+                payload_format: Some(PayloadFormat::Zigbee2mqtt),
+                status_format: StatusFormat::Json,
+                battery_format: BatteryFormat::Percent,
+                ..Self::default()
+            },
+            "tasmota_shutter" => Self {
+                tilt: false,
+                payload_format: Some(PayloadFormat::Tasmota),
+                ..Self::default()
+            },
+            "shelly_cover" => Self {
+                tilt: false,
+                payload_format: Some(PayloadFormat::Shelly),
+                status_format: StatusFormat::Text,
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+
+    /// Capacidades efectivas de una persiana con este perfil
+    pub fn capabilities(&self, blind: &BlindConfig) -> Capabilities {
+        Capabilities {
+            // Sin posición nativa se emula con los tiempos de recorrido
+            position: self.position || blind.travel_times().is_some(),
+            tilt: self.tilt && blind.tilt.is_some(),
+            stop: self.stop,
+        }
+    }
+
+    pub fn payload_format(&self, blind: &BlindConfig) -> PayloadFormat {
+        blind
+            .payload_format
+            .or(self.payload_format)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_capabilities() {
+        let mut blind = BlindConfig {
+            id: "b1".to_string(),
+            device_type: "relay_blind".to_string(),
+            ..Default::default()
+        };
+
+        let profile = DeviceProfile::builtin(&blind.device_type);
+        assert_eq!(
+            profile.capabilities(&blind),
+            Capabilities {
+                position: false,
+                tilt: false,
+                stop: true,
+            }
+        );
+
+        blind.travel_time_open_secs = Some(20.0);
+        blind.travel_time_close_secs = Some(20.0);
+        assert!(profile.capabilities(&blind).position);
+
+        let profile = DeviceProfile::builtin("zigbee_cover");
+        assert_eq!(profile.payload_format(&blind), PayloadFormat::Zigbee2mqtt);
+        blind.payload_format = Some(PayloadFormat::Text);
+        assert_eq!(profile.payload_format(&blind), PayloadFormat::Text);
+    }
+
+    #[test]
+    fn test_profile_defaults_from_json() {
+        let profile: DeviceProfile = serde_json::from_str(r#"{"stop": false}"#).unwrap();
+        assert!(profile.position);
+        assert!(!profile.tilt);
+        assert!(!profile.stop);
+        assert_eq!(profile.status_format, StatusFormat::Auto);
+    }
+}
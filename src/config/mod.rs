@@ -1,3 +1,7 @@
+pub mod device_profile;
 pub mod settings;
+pub mod shared;
 
+pub use device_profile::*;
 pub use settings::*;
+pub use shared::SharedConfig;
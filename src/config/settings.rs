@@ -1,9 +1,10 @@
+use super::device_profile::DeviceProfile;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BlindConfig {
     pub id: String,
     pub name: String,
@@ -13,6 +14,102 @@ pub struct BlindConfig {
     pub enabled: bool,
     pub battery_topic: Option<String>,
     pub status_topic: Option<String>,
+    /// Formato de los comandos; por defecto el del perfil del `device_type`
+    #[serde(default)]
+    pub payload_format: Option<PayloadFormat>,
+    /// Control de inclinación de lamas (persianas venecianas)
+    #[serde(default)]
+    pub tilt: Option<TiltConfig>,
+    /// Segundos que tarda en abrirse por completo (motores sin reporte de posición)
+    #[serde(default)]
+    pub travel_time_open_secs: Option<f64>,
+    /// Segundos que tarda en cerrarse por completo
+    #[serde(default)]
+    pub travel_time_close_secs: Option<f64>,
+    /// QoS de los comandos; por defecto el del perfil o el de `mqtt`
+    #[serde(default)]
+    pub qos: Option<MqttQos>,
+    /// Publicar los comandos como retenidos; por defecto el del perfil o el de `mqtt`
+    #[serde(default)]
+    pub retain: Option<bool>,
+    /// Circuito eléctrico (`batch.power_circuits`) del que se alimenta el motor
+    #[serde(default)]
+    pub power_circuit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TiltConfig {
+    /// Tema de comandos de inclinación; por defecto el `mqtt_topic` de la persiana
+    #[serde(default)]
+    pub command_topic: Option<String>,
+    /// Tema donde el dispositivo reporta la inclinación actual
+    #[serde(default)]
+    pub status_topic: Option<String>,
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+    /// Inclinación enviada para `open` (lamas horizontales)
+    #[serde(default = "default_tilt_open_value")]
+    pub open_value: u8,
+    /// Inclinación enviada para `close`
+    #[serde(default)]
+    pub closed_value: u8,
+}
+
+fn default_tilt_open_value() -> u8 {
+    50
+}
+
+impl BlindConfig {
+    /// Tiempos de recorrido (apertura, cierre) si ambos están configurados; en ese
+    /// caso la posición se estima y `SetPosition` se emula con un STOP temporizado
+    pub fn travel_times(&self) -> Option<(f64, f64)> {
+        self.travel_time_open_secs.zip(self.travel_time_close_secs)
+    }
+
+    /// Tema al que se envían los comandos de inclinación
+    pub fn tilt_command_topic(&self) -> Option<&str> {
+        self.tilt.as_ref().map(|tilt| {
+            tilt.command_topic
+                .as_deref()
+                .unwrap_or(self.mqtt_topic.as_str())
+        })
+    }
+
+    /// Si `topic` pertenece a la persiana: sus temas de estado y batería, o sus
+    /// temas de comando y los subtemas que añaden los codificadores (`.../set`)
+    pub fn owns_topic(&self, topic: &str) -> bool {
+        let reports = [
+            self.status_topic.as_deref(),
+            self.battery_topic.as_deref(),
+            self.tilt
+                .as_ref()
+                .and_then(|tilt| tilt.status_topic.as_deref()),
+        ];
+        let commands = [Some(self.mqtt_topic.as_str()), self.tilt_command_topic()];
+        reports.into_iter().flatten().any(|report| report == topic)
+            || commands.into_iter().flatten().any(|command| {
+                topic
+                    .strip_prefix(command)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+    }
+}
+
+/// Formato de los payloads de comando que entiende el dispositivo
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// Texto plano: `OPEN`, `CLOSE`, `STOP` o la posición (`50`)
+    #[default]
+    Text,
+    /// JSON: `{"state": "OPEN"}` o `{"position": 50}`
+    Json,
+    /// Zigbee2MQTT: JSON en `<mqtt_topic>/set`
+    Zigbee2mqtt,
+    /// Tasmota: `cmnd/<dispositivo>/ShutterOpen1`, `ShutterPosition1`, ...
+    Tasmota,
+    /// Shelly: `open`/`close`/`stop` en `<mqtt_topic>/command`
+    Shelly,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +117,241 @@ pub struct AppConfig {
     pub mqtt: MqttConfig,
     pub server: ServerConfig,
     pub blinds: Vec<BlindConfig>,
+    #[serde(default)]
+    pub battery: BatteryConfig,
+    /// Perfiles de dispositivo propios, por `device_type`; tienen prioridad sobre los incluidos
+    #[serde(default)]
+    pub device_profiles: HashMap<String, DeviceProfile>,
+    #[serde(default)]
+    pub home_assistant: HomeAssistantConfig,
+    #[serde(default)]
+    pub zigbee2mqtt: Zigbee2MqttConfig,
+    #[serde(default)]
+    pub acknowledgement: AcknowledgementConfig,
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub command_ingress: CommandIngressConfig,
+    #[serde(default)]
+    pub mqtt_api: MqttApiConfig,
+}
+
+/// Control de persianas publicando en `<command_prefix>/blind/<id>`,
+/// `<command_prefix>/room/<habitación>` y `<command_prefix>/all`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandIngressConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_command_prefix")]
+    pub command_prefix: String,
+    /// El resultado se publica en el mismo subtema bajo este prefijo
+    #[serde(default = "default_result_prefix")]
+    pub result_prefix: String,
+}
+
+fn default_command_prefix() -> String {
+    "tabi/cmd".to_string()
+}
+
+fn default_result_prefix() -> String {
+    "tabi/result".to_string()
+}
+
+impl Default for CommandIngressConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            command_prefix: default_command_prefix(),
+            result_prefix: default_result_prefix(),
+        }
+    }
+}
+
+/// Consultas por MQTT publicando en `<request_prefix>/<cliente>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttApiConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_request_prefix")]
+    pub request_prefix: String,
+    /// Respuesta en `<response_prefix>/<cliente>` si la petición no indica tema de respuesta
+    #[serde(default = "default_response_prefix")]
+    pub response_prefix: String,
+}
+
+fn default_request_prefix() -> String {
+    "tabi/api/request".to_string()
+}
+
+fn default_response_prefix() -> String {
+    "tabi/api/response".to_string()
+}
+
+impl Default for MqttApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            request_prefix: default_request_prefix(),
+            response_prefix: default_response_prefix(),
+        }
+    }
+}
+
+/// Comandos por habitación y globales
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchConfig {
+    /// Máximo de comandos publicándose a la vez
+    #[serde(default = "default_batch_concurrency")]
+    pub concurrency: usize,
+    /// Espera entre el arranque de una persiana y la siguiente; 0 = sin escalonar
+    #[serde(default)]
+    pub stagger_delay_ms: u64,
+    /// Límite de motores en marcha a la vez por circuito eléctrico
+    #[serde(default)]
+    pub power_circuits: HashMap<String, PowerCircuitConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerCircuitConfig {
+    pub max_moving: usize,
+    /// Duración de un movimiento si la persiana no tiene tiempos de recorrido
+    #[serde(default = "default_move_time_secs")]
+    pub move_time_secs: f64,
+}
+
+fn default_batch_concurrency() -> usize {
+    8
+}
+
+fn default_move_time_secs() -> f64 {
+    30.0
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_batch_concurrency(),
+            stagger_delay_ms: 0,
+            power_circuits: HashMap::new(),
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Circuito de una persiana, si tiene uno configurado
+    pub fn power_circuit(&self, blind: &BlindConfig) -> Option<(&str, &PowerCircuitConfig)> {
+        let name = blind.power_circuit.as_deref()?;
+        self.power_circuits
+            .get_key_value(name)
+            .map(|(name, circuit)| (name.as_str(), circuit))
+    }
+
+    /// Los lotes con estas persianas se escalonan (y se ejecutan en segundo plano)
+    pub fn is_staggered(&self, blinds: &[&BlindConfig]) -> bool {
+        self.stagger_delay_ms > 0
+            || blinds
+                .iter()
+                .any(|blind| self.power_circuit(blind).is_some())
+    }
+}
+
+/// Cola persistente de comandos para cuando el broker no está disponible
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxConfig {
+    #[serde(default = "default_outbox_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_outbox_path")]
+    pub path: String,
+    /// Los comandos que llevan más de este tiempo en cola se descartan
+    #[serde(default = "default_outbox_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_outbox_enabled() -> bool {
+    true
+}
+
+fn default_outbox_path() -> String {
+    "outbox.json".to_string()
+}
+
+fn default_outbox_ttl_secs() -> u64 {
+    300
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_outbox_enabled(),
+            path: default_outbox_path(),
+            ttl_secs: default_outbox_ttl_secs(),
+        }
+    }
+}
+
+/// Modo confirmado (`?confirm=true`): espera el reporte del dispositivo tras cada comando
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcknowledgementConfig {
+    #[serde(default = "default_ack_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_ack_timeout_ms() -> u64 {
+    10_000
+}
+
+impl Default for AcknowledgementConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: default_ack_timeout_ms(),
+        }
+    }
+}
+
+/// Publicación de descubrimiento MQTT para Home Assistant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomeAssistantConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            discovery_prefix: default_discovery_prefix(),
+        }
+    }
+}
+
+/// Detección de cortinas publicadas por el puente Zigbee2MQTT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Zigbee2MqttConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_zigbee2mqtt_base_topic")]
+    pub base_topic: String,
+}
+
+fn default_zigbee2mqtt_base_topic() -> String {
+    "zigbee2mqtt".to_string()
+}
+
+impl Default for Zigbee2MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_topic: default_zigbee2mqtt_base_topic(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +362,328 @@ pub struct MqttConfig {
     pub keep_alive_secs: u64,
     pub username: Option<String>,
     pub password: Option<String>,
+    /// Espera inicial antes del primer reintento de conexión
+    #[serde(default = "default_reconnect_initial_delay_ms")]
+    pub reconnect_initial_delay_ms: u64,
+    /// Espera máxima entre reintentos de conexión
+    #[serde(default = "default_reconnect_max_delay_secs")]
+    pub reconnect_max_delay_secs: u64,
+    /// Transporte hacia el broker; por defecto `tls` si hay sección `tls`, si no `tcp`
+    #[serde(default)]
+    pub transport: Option<MqttTransport>,
+    /// Opciones TLS para los transportes `tls` y `wss`
+    #[serde(default)]
+    pub tls: Option<MqttTlsConfig>,
+    /// Ruta del endpoint WebSocket (transportes `ws` y `wss`)
+    #[serde(default = "default_websocket_path")]
+    pub websocket_path: String,
+    /// Cabeceras HTTP añadidas a la petición de upgrade WebSocket
+    #[serde(default)]
+    pub websocket_headers: HashMap<String, String>,
+    /// Versión del protocolo: `v4` (MQTT 3.1.1, por defecto) o `v5`
+    #[serde(default)]
+    pub protocol_version: MqttProtocolVersion,
+    /// Tema donde los dispositivos v5 responden a los comandos; por defecto
+    /// `tabi/responses/<client_id>`
+    #[serde(default)]
+    pub response_topic: Option<String>,
+    /// Caducidad (v5) de los comandos no entregados; 0 = sin caducidad
+    #[serde(default = "default_message_expiry_secs")]
+    pub message_expiry_secs: u32,
+    /// Tema de disponibilidad del backend (Last Will y mensaje de nacimiento)
+    #[serde(default)]
+    pub availability: AvailabilityConfig,
+    /// QoS por defecto de los comandos
+    #[serde(default)]
+    pub qos: MqttQos,
+    /// Publicar los comandos como retenidos por defecto
+    #[serde(default)]
+    pub retain: bool,
+    /// Arranca el broker integrado (requiere compilar con la feature `embedded-broker`)
+    #[serde(default)]
+    pub embedded: bool,
+    #[serde(default)]
+    pub embedded_broker: EmbeddedBrokerConfig,
+    /// Registro de los últimos mensajes de las persianas (`/mqtt/messages`)
+    #[serde(default)]
+    pub inspector: InspectorConfig,
+    /// Temas `$SYS` del broker mostrados en `/mqtt/info`; vacío para no suscribirse.
+    /// Por defecto solo los de carga, que son los que permite `mosquitto_acl`.
+    #[serde(default = "default_sys_topics")]
+    pub sys_topics: Vec<String>,
+}
+
+fn default_sys_topics() -> Vec<String> {
+    vec!["$SYS/broker/load/+".to_string()]
+}
+
+/// Registro en memoria del tráfico MQTT de los temas de las persianas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InspectorConfig {
+    #[serde(default = "default_inspector_enabled")]
+    pub enabled: bool,
+    /// Número de mensajes guardados; los más antiguos se descartan
+    #[serde(default = "default_inspector_capacity")]
+    pub capacity: usize,
+    /// Guarda solo el tamaño del payload, no su contenido
+    #[serde(default)]
+    pub redact_payloads: bool,
+}
+
+fn default_inspector_enabled() -> bool {
+    true
+}
+
+fn default_inspector_capacity() -> usize {
+    200
+}
+
+impl Default for InspectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_inspector_enabled(),
+            capacity: default_inspector_capacity(),
+            redact_payloads: false,
+        }
+    }
+}
+
+/// Broker MQTT integrado para instalaciones de un solo equipo y pruebas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddedBrokerConfig {
+    #[serde(default = "default_broker_listeners")]
+    pub listeners: Vec<BrokerListener>,
+    /// Usuarios y contraseñas; el usuario del propio backend se añade siempre
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+    #[serde(default)]
+    pub allow_anonymous: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrokerListener {
+    pub protocol: BrokerProtocol,
+    pub bind: std::net::SocketAddr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrokerProtocol {
+    /// MQTT 3.1.1 sobre TCP
+    Tcp,
+    /// MQTT v5 sobre TCP
+    Tcp5,
+    /// MQTT 3.1.1 sobre WebSocket
+    Ws,
+}
+
+/// Los mismos listeners que `mosquitto/config/mosquitto.conf`
+fn default_broker_listeners() -> Vec<BrokerListener> {
+    vec![
+        BrokerListener {
+            protocol: BrokerProtocol::Tcp,
+            bind: ([0, 0, 0, 0], 1883).into(),
+        },
+        BrokerListener {
+            protocol: BrokerProtocol::Ws,
+            bind: ([0, 0, 0, 0], 9001).into(),
+        },
+    ]
+}
+
+impl Default for EmbeddedBrokerConfig {
+    fn default() -> Self {
+        Self {
+            listeners: default_broker_listeners(),
+            users: HashMap::new(),
+            allow_anonymous: false,
+        }
+    }
+}
+
+/// Nivel de QoS MQTT; en la configuración se escribe como `0`, `1` o `2`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+#[allow(clippy::enum_variant_names)] // Mismos nombres que `rumqttc::QoS`
+pub enum MqttQos {
+    AtMostOnce,
+    #[default]
+    AtLeastOnce,
+    ExactlyOnce,
+}
+
+impl TryFrom<u8> for MqttQos {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MqttQos::AtMostOnce),
+            1 => Ok(MqttQos::AtLeastOnce),
+            2 => Ok(MqttQos::ExactlyOnce),
+            _ => Err(format!("invalid MQTT QoS {}, expected 0, 1 or 2", value)),
+        }
+    }
+}
+
+impl From<MqttQos> for u8 {
+    fn from(qos: MqttQos) -> Self {
+        match qos {
+            MqttQos::AtMostOnce => 0,
+            MqttQos::AtLeastOnce => 1,
+            MqttQos::ExactlyOnce => 2,
+        }
+    }
+}
+
+/// QoS y retención con que se publica un comando
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PublishOptions {
+    pub qos: MqttQos,
+    pub retain: bool,
+}
+
+/// Disponibilidad del backend: `online` retenido tras cada ConnAck y `offline`
+/// como Last Will y al apagar
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvailabilityConfig {
+    #[serde(default = "default_availability_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_availability_topic")]
+    pub topic: String,
+    #[serde(default = "default_online_payload")]
+    pub online_payload: String,
+    #[serde(default = "default_offline_payload")]
+    pub offline_payload: String,
+}
+
+fn default_availability_enabled() -> bool {
+    true
+}
+
+fn default_availability_topic() -> String {
+    "tabi/status".to_string()
+}
+
+fn default_online_payload() -> String {
+    "online".to_string()
+}
+
+fn default_offline_payload() -> String {
+    "offline".to_string()
+}
+
+impl Default for AvailabilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_availability_enabled(),
+            topic: default_availability_topic(),
+            online_payload: default_online_payload(),
+            offline_payload: default_offline_payload(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttProtocolVersion {
+    #[default]
+    V4,
+    V5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MqttTransport {
+    Tcp,
+    Tls,
+    Ws,
+    Wss,
+}
+
+impl MqttConfig {
+    /// Transporte efectivo (ver `transport`)
+    pub fn transport(&self) -> MqttTransport {
+        self.transport.unwrap_or(if self.tls.is_some() {
+            MqttTransport::Tls
+        } else {
+            MqttTransport::Tcp
+        })
+    }
+
+    /// Tema de respuesta efectivo (ver `response_topic`)
+    pub fn response_topic(&self) -> String {
+        self.response_topic
+            .clone()
+            .unwrap_or_else(|| format!("tabi/responses/{}", self.client_id))
+    }
+}
+
+fn default_message_expiry_secs() -> u32 {
+    60
+}
+
+fn default_websocket_path() -> String {
+    "/mqtt".to_string()
+}
+
+/// Opciones TLS de la conexión MQTT
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MqttTlsConfig {
+    /// Certificados de CA en PEM; por defecto los del sistema
+    #[serde(default)]
+    pub ca_path: Option<String>,
+    /// Certificado de cliente en PEM (TLS mutuo); requiere `client_key_path`
+    #[serde(default)]
+    pub client_cert_path: Option<String>,
+    #[serde(default)]
+    pub client_key_path: Option<String>,
+    /// Acepta cualquier certificado del broker. Solo para desarrollo
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    /// Nombre del broker para SNI y el certificado si difiere de `broker_host`;
+    /// con TLS se conecta a este nombre en lugar de a `broker_host`
+    #[serde(default)]
+    pub server_name: Option<String>,
+    /// Protocolos ALPN a negociar (p. ej. `["mqtt"]`)
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+fn default_reconnect_initial_delay_ms() -> u64 {
+    500
+}
+
+fn default_reconnect_max_delay_secs() -> u64 {
+    60
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker_host: "localhost".to_string(),
+            broker_port: 1883,
+            client_id: "tabi-backend".to_string(),
+            keep_alive_secs: 5,
+            username: None,
+            password: None,
+            reconnect_initial_delay_ms: default_reconnect_initial_delay_ms(),
+            reconnect_max_delay_secs: default_reconnect_max_delay_secs(),
+            transport: None,
+            tls: None,
+            websocket_path: default_websocket_path(),
+            websocket_headers: HashMap::new(),
+            protocol_version: MqttProtocolVersion::default(),
+            response_topic: None,
+            message_expiry_secs: default_message_expiry_secs(),
+            availability: AvailabilityConfig::default(),
+            qos: MqttQos::default(),
+            retain: false,
+            embedded: false,
+            embedded_broker: EmbeddedBrokerConfig::default(),
+            inspector: InspectorConfig::default(),
+            sys_topics: default_sys_topics(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,21 +692,142 @@ pub struct ServerConfig {
     pub port: u16,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryConfig {
+    /// Porcentaje por debajo del cual se emite el evento de batería baja
+    #[serde(default = "default_low_battery_threshold")]
+    pub low_threshold_percent: u8,
+    /// Margen sobre el umbral necesario para rearmar la alerta
+    #[serde(default = "default_battery_recovery_margin")]
+    pub recovery_margin_percent: u8,
+    /// Número de lecturas guardadas por persiana
+    #[serde(default = "default_battery_history_size")]
+    pub history_size: usize,
+    /// Tema donde se publican los eventos de batería baja
+    #[serde(default = "default_battery_alert_topic")]
+    pub alert_topic: Option<String>,
+    /// Curvas voltaje → porcentaje por `device_type` (la clave "default" aplica al resto)
+    #[serde(default)]
+    pub voltage_curves: HashMap<String, Vec<VoltagePoint>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct VoltagePoint {
+    pub voltage: f64,
+    pub percent: u8,
+}
+
+fn default_low_battery_threshold() -> u8 {
+    20
+}
+
+fn default_battery_recovery_margin() -> u8 {
+    5
+}
+
+fn default_battery_history_size() -> usize {
+    288
+}
+
+fn default_battery_alert_topic() -> Option<String> {
+    Some("tabi/events/battery".to_string())
+}
+
+/// Curva de una celda de litio (3.0 V - 4.2 V), usada si no hay otra configurada
+const DEFAULT_VOLTAGE_CURVE: [VoltagePoint; 6] = [
+    VoltagePoint {
+        voltage: 3.0,
+        percent: 0,
+    },
+    VoltagePoint {
+        voltage: 3.3,
+        percent: 5,
+    },
+    VoltagePoint {
+        voltage: 3.6,
+        percent: 40,
+    },
+    VoltagePoint {
+        voltage: 3.8,
+        percent: 70,
+    },
+    VoltagePoint {
+        voltage: 4.0,
+        percent: 90,
+    },
+    VoltagePoint {
+        voltage: 4.2,
+        percent: 100,
+    },
+];
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            low_threshold_percent: default_low_battery_threshold(),
+            recovery_margin_percent: default_battery_recovery_margin(),
+            history_size: default_battery_history_size(),
+            alert_topic: default_battery_alert_topic(),
+            voltage_curves: HashMap::new(),
+        }
+    }
+}
+
+impl BatteryConfig {
+    /// Curva voltaje → porcentaje aplicable a un tipo de dispositivo
+    pub fn voltage_curve(&self, device_type: &str) -> &[VoltagePoint] {
+        self.voltage_curves
+            .get(device_type)
+            .or_else(|| self.voltage_curves.get("default"))
+            .map(|curve| curve.as_slice())
+            .unwrap_or(&DEFAULT_VOLTAGE_CURVE)
+    }
+
+    /// Convierte un voltaje en porcentaje interpolando linealmente en la curva
+    pub fn voltage_to_percent(&self, device_type: &str, voltage: f64) -> u8 {
+        let curve = self.voltage_curve(device_type);
+        let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+            return 0;
+        };
+
+        if voltage <= first.voltage {
+            return first.percent;
+        }
+        if voltage >= last.voltage {
+            return last.percent;
+        }
+
+        for window in curve.windows(2) {
+            let (low, high) = (window[0], window[1]);
+            if voltage <= high.voltage {
+                let ratio = (voltage - low.voltage) / (high.voltage - low.voltage);
+                let percent =
+                    low.percent as f64 + ratio * (high.percent as f64 - low.percent as f64);
+                return percent.round().clamp(0.0, 100.0) as u8;
+            }
+        }
+
+        last.percent
+    }
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            mqtt: MqttConfig {
-                broker_host: "localhost".to_string(),
-                broker_port: 1883,
-                client_id: "tabi-backend".to_string(),
-                keep_alive_secs: 5,
-                username: None,
-                password: None,
-            },
+            mqtt: MqttConfig::default(),
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
             },
+            battery: BatteryConfig::default(),
+            device_profiles: HashMap::new(),
+            home_assistant: HomeAssistantConfig::default(),
+            zigbee2mqtt: Zigbee2MqttConfig::default(),
+            acknowledgement: AcknowledgementConfig::default(),
+            outbox: OutboxConfig::default(),
+            batch: BatchConfig::default(),
+            command_ingress: CommandIngressConfig::default(),
+            mqtt_api: MqttApiConfig::default(),
             blinds: vec![
                 BlindConfig {
                     id: "blind_001".to_string(),
@@ -63,6 +838,7 @@ impl Default for AppConfig {
                     enabled: true,
                     battery_topic: Some("home/blinds/bedroom/battery".to_string()),
                     status_topic: Some("home/blinds/bedroom/status".to_string()),
+                    ..Default::default()
                 },
                 BlindConfig {
                     id: "blind_002".to_string(),
@@ -73,6 +849,7 @@ impl Default for AppConfig {
                     enabled: true,
                     battery_topic: Some("home/blinds/living/battery".to_string()),
                     status_topic: Some("home/blinds/living/status".to_string()),
+                    ..Default::default()
                 },
                 BlindConfig {
                     id: "blind_003".to_string(),
@@ -83,6 +860,7 @@ impl Default for AppConfig {
                     enabled: true,
                     battery_topic: Some("home/blinds/kitchen/battery".to_string()),
                     status_topic: Some("home/blinds/kitchen/status".to_string()),
+                    ..Default::default()
                 },
             ],
         }
@@ -136,11 +914,34 @@ impl AppConfig {
         }
     }
 
+    /// Perfil del tipo de dispositivo: el configurado o, si no existe, el incluido
+    pub fn device_profile(&self, device_type: &str) -> DeviceProfile {
+        self.device_profiles
+            .get(device_type)
+            .cloned()
+            .unwrap_or_else(|| DeviceProfile::builtin(device_type))
+    }
+
+    /// QoS y retención de los comandos de una persiana: gana el valor más
+    /// específico (persiana, perfil del `device_type`, `mqtt`)
+    pub fn publish_options(&self, blind: &BlindConfig) -> PublishOptions {
+        let profile = self.device_profile(&blind.device_type);
+        PublishOptions {
+            qos: blind.qos.or(profile.qos).unwrap_or(self.mqtt.qos),
+            retain: blind.retain.or(profile.retain).unwrap_or(self.mqtt.retain),
+        }
+    }
+
     /// Obtiene una persiana por su ID
     pub fn get_blind_by_id(&self, id: &str) -> Option<&BlindConfig> {
         self.blinds.iter().find(|blind| blind.id == id)
     }
 
+    /// Persiana configurada a la que pertenece un tema MQTT
+    pub fn get_blind_by_topic(&self, topic: &str) -> Option<&BlindConfig> {
+        self.blinds.iter().find(|blind| blind.owns_topic(topic))
+    }
+
     /// Obtiene persianas por habitación
     pub fn get_blinds_by_room(&self, room: &str) -> Vec<&BlindConfig> {
         self.blinds
@@ -173,9 +974,7 @@ impl AppConfig {
 
         for blind in &self.blinds {
             if blind.enabled {
-                map.entry(blind.room.clone())
-                    .or_insert_with(Vec::new)
-                    .push(blind);
+                map.entry(blind.room.clone()).or_default().push(blind);
             }
         }
 
@@ -196,11 +995,200 @@ impl AppConfig {
             }
         }
 
+        if self.mqtt.reconnect_initial_delay_ms == 0 {
+            return Err("reconnect_initial_delay_ms debe ser mayor que 0".to_string());
+        }
+
+        match self.mqtt.transport() {
+            MqttTransport::Tcp | MqttTransport::Ws if self.mqtt.tls.is_some() => {
+                return Err("La sección tls requiere transport tls o wss".to_string());
+            }
+            MqttTransport::Ws | MqttTransport::Wss
+                if !self.mqtt.websocket_path.starts_with('/') =>
+            {
+                return Err("websocket_path debe empezar por '/'".to_string());
+            }
+            _ => {}
+        }
+
+        if let Some(tls) = &self.mqtt.tls {
+            if tls.insecure_skip_verify && tls.server_name.is_some() {
+                return Err("server_name no se puede combinar con insecure_skip_verify".to_string());
+            }
+            if tls.client_cert_path.is_some() != tls.client_key_path.is_some() {
+                return Err(
+                    "client_cert_path y client_key_path deben configurarse juntos".to_string(),
+                );
+            }
+            for path in [&tls.ca_path, &tls.client_cert_path, &tls.client_key_path]
+                .into_iter()
+                .flatten()
+            {
+                if !Path::new(path).is_file() {
+                    return Err(format!("Archivo TLS no encontrado: {}", path));
+                }
+            }
+        }
+
+        let response_topic = self.mqtt.response_topic();
+        if response_topic.is_empty() || response_topic.contains(['+', '#']) {
+            return Err(format!("response_topic inválido: {}", response_topic));
+        }
+
+        let availability = &self.mqtt.availability;
+        if availability.enabled
+            && (availability.topic.is_empty() || availability.topic.contains(['+', '#']))
+        {
+            return Err(format!(
+                "Tema de disponibilidad inválido: {}",
+                availability.topic
+            ));
+        }
+
+        if self.outbox.enabled && self.outbox.ttl_secs == 0 {
+            return Err("outbox.ttl_secs debe ser mayor que 0".to_string());
+        }
+
+        if self.mqtt.embedded {
+            let broker = &self.mqtt.embedded_broker;
+            if broker.listeners.is_empty() {
+                return Err("embedded_broker necesita al menos un listener".to_string());
+            }
+            if !broker.allow_anonymous && broker.users.is_empty() && self.mqtt.username.is_none() {
+                return Err(
+                    "embedded_broker sin usuarios: configure users o allow_anonymous".to_string(),
+                );
+            }
+        }
+
+        if self.command_ingress.enabled {
+            let ingress = &self.command_ingress;
+            for prefix in [&ingress.command_prefix, &ingress.result_prefix] {
+                if prefix.is_empty() || prefix.contains(['+', '#']) {
+                    return Err(format!("Prefijo de comandos MQTT no válido: '{}'", prefix));
+                }
+            }
+            // Results published under the command tree would be read back as commands
+            let command_tree = format!("{}/", ingress.command_prefix);
+            if ingress.result_prefix == ingress.command_prefix
+                || ingress.result_prefix.starts_with(&command_tree)
+            {
+                return Err("result_prefix no puede estar dentro de command_prefix".to_string());
+            }
+        }
+
+        if self.mqtt_api.enabled {
+            let api = &self.mqtt_api;
+            for prefix in [&api.request_prefix, &api.response_prefix] {
+                if prefix.is_empty() || prefix.contains(['+', '#']) {
+                    return Err(format!("Prefijo de la API MQTT no válido: '{}'", prefix));
+                }
+            }
+            // Replies published under the request tree would be read back as requests
+            let request_tree = format!("{}/", api.request_prefix);
+            if api.response_prefix == api.request_prefix
+                || api.response_prefix.starts_with(&request_tree)
+            {
+                return Err("response_prefix no puede estar dentro de request_prefix".to_string());
+            }
+        }
+
+        if let Some(topic) = self
+            .mqtt
+            .sys_topics
+            .iter()
+            .find(|topic| !topic.starts_with("$SYS/"))
+        {
+            return Err(format!("sys_topics solo admite temas $SYS: '{}'", topic));
+        }
+
+        if self.mqtt.inspector.enabled && self.mqtt.inspector.capacity == 0 {
+            return Err("mqtt.inspector.capacity debe ser mayor que 0".to_string());
+        }
+
+        if self.batch.concurrency == 0 {
+            return Err("batch.concurrency debe ser mayor que 0".to_string());
+        }
+
+        for (name, circuit) in &self.batch.power_circuits {
+            if circuit.max_moving == 0 || circuit.move_time_secs <= 0.0 {
+                return Err(format!(
+                    "El circuito '{}' necesita max_moving y move_time_secs mayores que 0",
+                    name
+                ));
+            }
+        }
+        for blind in &self.blinds {
+            if let Some(circuit) = &blind.power_circuit {
+                if !self.batch.power_circuits.contains_key(circuit) {
+                    return Err(format!(
+                        "La persiana '{}' usa el circuito no declarado '{}'",
+                        blind.id, circuit
+                    ));
+                }
+            }
+        }
+
+        for (device_type, profile) in &self.device_profiles {
+            if let Some(protection) = &profile.protection {
+                if protection.max_moves > 0 && protection.window_secs == 0 {
+                    return Err(format!(
+                        "El perfil '{}' necesita window_secs mayor que 0 para max_moves",
+                        device_type
+                    ));
+                }
+            }
+        }
+
+        if self.acknowledgement.timeout_ms == 0 {
+            return Err("acknowledgement.timeout_ms debe ser mayor que 0".to_string());
+        }
+
+        if self.battery.low_threshold_percent > 100 {
+            return Err("low_threshold_percent debe estar entre 0 y 100".to_string());
+        }
+
+        // Verificar que las curvas de voltaje estén ordenadas por voltaje
+        for (device_type, curve) in &self.battery.voltage_curves {
+            if curve.is_empty()
+                || curve
+                    .windows(2)
+                    .any(|points| points[0].voltage >= points[1].voltage)
+            {
+                return Err(format!(
+                    "Curva de voltaje inválida para el tipo de dispositivo: {}",
+                    device_type
+                ));
+            }
+        }
+
         // Verificar que los temas MQTT no estén vacíos
         for blind in &self.blinds {
             if blind.mqtt_topic.trim().is_empty() {
                 return Err(format!("Tema MQTT vacío para persiana: {}", blind.id));
             }
+            if blind.travel_time_open_secs.is_some() != blind.travel_time_close_secs.is_some() {
+                return Err(format!(
+                    "travel_time_open_secs y travel_time_close_secs deben configurarse juntos: {}",
+                    blind.id
+                ));
+            }
+            if let Some((open, close)) = blind.travel_times() {
+                if !(open.is_finite() && open > 0.0 && close.is_finite() && close > 0.0) {
+                    return Err(format!(
+                        "Tiempos de recorrido inválidos para persiana: {}",
+                        blind.id
+                    ));
+                }
+            }
+            if let Some(tilt) = &blind.tilt {
+                if tilt.open_value > 100 || tilt.closed_value > 100 {
+                    return Err(format!(
+                        "Valores de inclinación fuera de rango para persiana: {}",
+                        blind.id
+                    ));
+                }
+            }
         }
 
         Ok(())
@@ -288,10 +1276,140 @@ mod tests {
 
     #[test]
     fn test_validate() {
-        let config = AppConfig::default();
+        let mut config = AppConfig::default();
+        assert!(config.validate().is_ok());
+
+        // The embedded broker needs users unless anonymous access is allowed
+        config.mqtt.embedded = true;
+        assert!(config.validate().is_err());
+        config.mqtt.embedded_broker.allow_anonymous = true;
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn test_validate_travel_times() {
+        let mut config = AppConfig::default();
+        config.blinds[0].travel_time_open_secs = Some(20.0);
+        assert!(config.validate().is_err());
+
+        config.blinds[0].travel_time_close_secs = Some(18.5);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.blinds[0].travel_times(), Some((20.0, 18.5)));
+
+        config.blinds[0].travel_time_close_secs = Some(0.0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_transport() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.mqtt.transport(), MqttTransport::Tcp);
+
+        config.mqtt.tls = Some(MqttTlsConfig::default());
+        assert_eq!(config.mqtt.transport(), MqttTransport::Tls);
+        assert!(config.validate().is_ok());
+
+        config.mqtt.transport = Some(MqttTransport::Ws);
+        assert!(config.validate().is_err());
+
+        config.mqtt.tls = None;
+        config.mqtt.websocket_path = "mqtt".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_protocol_version_and_response_topic() {
+        let mqtt: MqttConfig = serde_json::from_value(serde_json::json!({
+            "broker_host": "localhost",
+            "broker_port": 1883,
+            "client_id": "tabi",
+            "keep_alive_secs": 5,
+            "username": null,
+            "password": null,
+            "protocol_version": "v5"
+        }))
+        .unwrap();
+        assert_eq!(mqtt.protocol_version, MqttProtocolVersion::V5);
+        assert_eq!(mqtt.response_topic(), "tabi/responses/tabi");
+        assert_eq!(mqtt.message_expiry_secs, 60);
+
+        let mut config = AppConfig {
+            mqtt,
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.mqtt.response_topic = Some("tabi/responses/#".to_string());
+        assert!(config.validate().is_err());
+
+        config.mqtt.response_topic = None;
+        config.mqtt.availability.topic = "tabi/+".to_string();
+        assert!(config.validate().is_err());
+        config.mqtt.availability.enabled = false;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_publish_options_most_specific_wins() {
+        let mut config = AppConfig::default();
+        config.mqtt.retain = true;
+        config.device_profiles.insert(
+            "motorized_blind".to_string(),
+            serde_json::from_value(serde_json::json!({ "qos": 2 })).unwrap(),
+        );
+
+        let blind = config.blinds[0].clone();
+        assert_eq!(
+            config.publish_options(&blind),
+            PublishOptions {
+                qos: MqttQos::ExactlyOnce,
+                retain: true,
+            }
+        );
+
+        let blind = BlindConfig {
+            qos: Some(MqttQos::AtMostOnce),
+            retain: Some(false),
+            ..blind
+        };
+        assert_eq!(
+            config.publish_options(&blind),
+            PublishOptions {
+                qos: MqttQos::AtMostOnce,
+                retain: false,
+            }
+        );
+
+        assert!(serde_json::from_value::<MqttQos>(serde_json::json!(3)).is_err());
+    }
+
+    #[test]
+    fn test_validate_tls_files() {
+        let ca_file = NamedTempFile::new().unwrap();
+        let mut config = AppConfig::default();
+        config.mqtt.tls = Some(MqttTlsConfig {
+            ca_path: Some(ca_file.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+        assert!(config.validate().is_ok());
+
+        let tls = config.mqtt.tls.as_mut().unwrap();
+        tls.client_cert_path = tls.ca_path.clone();
+        assert!(config.validate().is_err());
+
+        let tls = config.mqtt.tls.as_mut().unwrap();
+        tls.client_key_path = Some("/no/existe/client.key".to_string());
+        let error = config.validate().unwrap_err();
+        assert!(error.contains("/no/existe/client.key"));
+
+        config.mqtt.tls = Some(MqttTlsConfig {
+            insecure_skip_verify: true,
+            server_name: Some("broker.local".to_string()),
+            ..Default::default()
+        });
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_save_and_load() {
         let config = AppConfig::default();
@@ -320,9 +1438,47 @@ mod tests {
             enabled: true,
             battery_topic: None,
             status_topic: None,
+            ..Default::default()
         };
 
         assert!(config.add_blind(new_blind).is_ok());
         assert_eq!(config.blinds.len(), initial_count + 1);
     }
+
+    #[test]
+    fn test_voltage_to_percent() {
+        let mut battery = BatteryConfig::default();
+        assert_eq!(battery.voltage_to_percent("motorized_blind", 4.2), 100);
+        assert_eq!(battery.voltage_to_percent("motorized_blind", 2.5), 0);
+        assert_eq!(battery.voltage_to_percent("motorized_blind", 3.7), 55);
+
+        battery.voltage_curves.insert(
+            "outdoor_blind".to_string(),
+            vec![
+                VoltagePoint {
+                    voltage: 10.0,
+                    percent: 0,
+                },
+                VoltagePoint {
+                    voltage: 12.0,
+                    percent: 100,
+                },
+            ],
+        );
+        assert_eq!(battery.voltage_to_percent("outdoor_blind", 11.0), 50);
+        assert_eq!(battery.voltage_to_percent("motorized_blind", 4.0), 90);
+    }
+
+    #[test]
+    fn test_battery_config_defaults_when_missing() {
+        let json = r#"{
+            "mqtt": {"broker_host": "localhost", "broker_port": 1883, "client_id": "t",
+                     "keep_alive_secs": 5, "username": null, "password": null},
+            "server": {"host": "0.0.0.0", "port": 8080},
+            "blinds": []
+        }"#;
+        let config: AppConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.battery.low_threshold_percent, 20);
+        assert!(config.battery.voltage_curves.is_empty());
+    }
 }
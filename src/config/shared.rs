@@ -0,0 +1,130 @@
+use super::settings::AppConfig;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Configuración compartida que puede modificarse en caliente. Los lectores
+/// obtienen una instantánea inmutable; cada cambio se valida, se guarda en disco
+/// (si hay archivo) y se notifica a los suscriptores.
+pub struct SharedConfig {
+    current: Arc<watch::Sender<Arc<AppConfig>>>,
+    path: Option<Arc<PathBuf>>,
+    update_lock: Arc<Mutex<()>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            current: Arc::new(watch::channel(Arc::new(config)).0),
+            path: None,
+            update_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Igual que `new`, pero cada cambio se guarda en `path`
+    pub fn with_file(config: AppConfig, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(Arc::new(path.into())),
+            ..Self::new(config)
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<AppConfig> {
+        self.current.borrow().clone()
+    }
+
+    /// Receptor que se actualiza con cada cambio de configuración
+    pub fn subscribe(&self) -> watch::Receiver<Arc<AppConfig>> {
+        self.current.subscribe()
+    }
+
+    /// Aplica `change` sobre una copia de la configuración; si el resultado es
+    /// válido lo guarda y lo publica, si no deja la configuración intacta
+    pub fn update<T>(
+        &self,
+        change: impl FnOnce(&mut AppConfig) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let _guard = self
+            .update_lock
+            .lock()
+            .map_err(|_| "Bloqueo de configuración envenenado".to_string())?;
+
+        let mut config = (*self.snapshot()).clone();
+        let result = change(&mut config)?;
+        config.validate()?;
+        if let Some(path) = &self.path {
+            config
+                .save_to_file(path.as_path())
+                .map_err(|e| format!("Error guardando la configuración: {}", e))?;
+        }
+
+        self.current.send_replace(Arc::new(config));
+        Ok(result)
+    }
+}
+
+impl Clone for SharedConfig {
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+            path: self.path.clone(),
+            update_lock: Arc::clone(&self.update_lock),
+        }
+    }
+}
+
+impl From<AppConfig> for SharedConfig {
+    fn from(config: AppConfig) -> Self {
+        Self::new(config)
+    }
+}
+
+impl From<Arc<AppConfig>> for SharedConfig {
+    fn from(config: Arc<AppConfig>) -> Self {
+        Self::new(Arc::unwrap_or_clone(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BlindConfig;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_update_validates_saves_and_notifies() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let shared = SharedConfig::with_file(AppConfig::default(), temp_file.path());
+        let mut changes = shared.subscribe();
+
+        let result = shared.update(|config| {
+            config.add_blind(BlindConfig {
+                id: "blind_004".to_string(),
+                mqtt_topic: "home/blinds/new".to_string(),
+                ..Default::default()
+            })
+        });
+        assert!(result.is_ok());
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(changes.borrow_and_update().blinds.len(), 4);
+        assert_eq!(
+            AppConfig::load_from_file(temp_file.path())
+                .unwrap()
+                .blinds
+                .len(),
+            4
+        );
+
+        // Un cambio inválido no se aplica
+        let result = shared.update(|config| {
+            config.blinds[0].mqtt_topic = " ".to_string();
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert!(!changes.has_changed().unwrap());
+        assert_eq!(
+            shared.snapshot().blinds[0].mqtt_topic,
+            "home/blinds/bedroom/control"
+        );
+    }
+}
@@ -6,8 +6,16 @@ pub enum AppError {
     BlindNotFound(String),
     BlindDisabled(String),
     RoomNotFound(String),
+    DeviceNotFound(String),
+    BatchNotFound(String),
     InvalidAction(String),
-    MqttError(rumqttc::ClientError),
+    UnsupportedCommand(String, String),
+    MqttError(String),
+    /// El broker rechazó la operación (MQTT v5): tema y código de motivo
+    MqttRejected(String, String),
+    /// La protección del motor rechazó el comando: persiana, motivo y segundos
+    /// hasta poder reintentarlo
+    RateLimited(String, String, u64),
     ConfigError(String),
     ValidationError(String),
     InternalError(String),
@@ -19,8 +27,21 @@ impl fmt::Display for AppError {
             AppError::BlindNotFound(id) => write!(f, "Blind not found: {}", id),
             AppError::BlindDisabled(id) => write!(f, "Blind is disabled: {}", id),
             AppError::RoomNotFound(room) => write!(f, "Room not found: {}", room),
+            AppError::DeviceNotFound(id) => write!(f, "Device not found: {}", id),
+            AppError::BatchNotFound(id) => write!(f, "Batch not found: {}", id),
             AppError::InvalidAction(action) => write!(f, "Invalid action: {}", action),
+            AppError::UnsupportedCommand(id, command) => {
+                write!(f, "Blind {} does not support command: {}", id, command)
+            }
             AppError::MqttError(e) => write!(f, "MQTT error: {}", e),
+            AppError::MqttRejected(topic, reason) => {
+                write!(f, "MQTT broker rejected {}: {}", topic, reason)
+            }
+            AppError::RateLimited(id, reason, retry_after) => write!(
+                f,
+                "Blind {} is rate limited ({}), retry after {}s",
+                id, reason, retry_after
+            ),
             AppError::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
             AppError::ValidationError(msg) => write!(f, "Validation error: {}", msg),
             AppError::InternalError(msg) => write!(f, "Internal error: {}", msg),
@@ -48,16 +69,51 @@ impl ResponseError for AppError {
                 "room": room,
                 "error_code": "ROOM_NOT_FOUND"
             })),
+            AppError::DeviceNotFound(id) => HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Device not found or already configured",
+                "device_id": id,
+                "error_code": "DEVICE_NOT_FOUND"
+            })),
+            AppError::BatchNotFound(id) => HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Batch not found or expired",
+                "batch_id": id,
+                "error_code": "BATCH_NOT_FOUND"
+            })),
             AppError::InvalidAction(action) => HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Invalid action. Use: OPEN, CLOSE, or STOP",
                 "received": action,
                 "error_code": "INVALID_ACTION"
             })),
+            AppError::UnsupportedCommand(id, command) => {
+                HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Command not supported by this blind",
+                    "blind_id": id,
+                    "command": command,
+                    "error_code": "UNSUPPORTED_COMMAND"
+                }))
+            }
             AppError::MqttError(e) => HttpResponse::InternalServerError().json(serde_json::json!({
                 "error": "MQTT communication failed",
-                "details": e.to_string(),
+                "details": e,
                 "error_code": "MQTT_ERROR"
             })),
+            AppError::MqttRejected(topic, reason) => {
+                HttpResponse::BadGateway().json(serde_json::json!({
+                    "error": "MQTT broker rejected the message",
+                    "topic": topic,
+                    "reason_code": reason,
+                    "error_code": "MQTT_REJECTED"
+                }))
+            }
+            AppError::RateLimited(id, reason, retry_after) => HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({
+                    "error": "Command rejected to protect the motor",
+                    "blind_id": id,
+                    "reason": reason,
+                    "retry_after_secs": retry_after,
+                    "error_code": "RATE_LIMITED"
+                })),
             AppError::ConfigError(msg) => {
                 HttpResponse::InternalServerError().json(serde_json::json!({
                     "error": "Configuration error",
@@ -83,7 +139,13 @@ impl ResponseError for AppError {
 
 impl From<rumqttc::ClientError> for AppError {
     fn from(error: rumqttc::ClientError) -> Self {
-        AppError::MqttError(error)
+        AppError::MqttError(error.to_string())
+    }
+}
+
+impl From<rumqttc::v5::ClientError> for AppError {
+    fn from(error: rumqttc::v5::ClientError) -> Self {
+        AppError::MqttError(error.to_string())
     }
 }
 
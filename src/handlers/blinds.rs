@@ -1,40 +1,166 @@
 use crate::errors::AppError;
+use crate::services::staggered_batch::BatchOutcome;
 use crate::services::BlindService;
-use actix_web::{post, web, HttpResponse, Result};
+use actix_web::{get, post, web, HttpResponse, Result};
+use serde::Deserialize;
+
+/// `?confirm=true` espera el reporte de estado del dispositivo antes de responder
+#[derive(Debug, Default, Deserialize)]
+pub struct ControlQuery {
+    #[serde(default)]
+    pub confirm: bool,
+}
 
 #[post("/blinds/id/{blind_id}/{action}")]
 pub async fn control_blind_by_id(
     path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
     blind_service: web::Data<BlindService>,
 ) -> Result<HttpResponse, AppError> {
     let (blind_id, action) = path.into_inner();
 
     let result = blind_service
+        .with_confirmation(query.confirm)
         .control_blind_by_id(&blind_id, &action)
         .await?;
     Ok(HttpResponse::Ok().json(result))
 }
 
+#[post("/blinds/id/{blind_id}/position/{percent}")]
+pub async fn set_blind_position(
+    path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let (blind_id, percent) = path.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_position_by_id(&blind_id, &percent)
+        .await?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[post("/blinds/id/{blind_id}/tilt/{value}")]
+pub async fn set_blind_tilt(
+    path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let (blind_id, value) = path.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_tilt_by_id(&blind_id, &value)
+        .await?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
 #[post("/blinds/room/{room}/{action}")]
 pub async fn control_blinds_by_room(
     path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
     blind_service: web::Data<BlindService>,
 ) -> Result<HttpResponse, AppError> {
     let (room, action) = path.into_inner();
 
-    let result = blind_service.control_blinds_by_room(&room, &action).await?;
-    Ok(HttpResponse::Ok().json(result))
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .control_blinds_by_room(&room, &action)
+        .await?;
+    Ok(batch_response(result))
+}
+
+#[post("/blinds/room/{room}/position/{percent}")]
+pub async fn set_room_position(
+    path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let (room, percent) = path.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_position_by_room(&room, &percent)
+        .await?;
+    Ok(batch_response(result))
+}
+
+#[post("/blinds/room/{room}/tilt/{value}")]
+pub async fn set_room_tilt(
+    path: web::Path<(String, String)>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let (room, value) = path.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_tilt_by_room(&room, &value)
+        .await?;
+    Ok(batch_response(result))
 }
 
 #[post("/blinds/all/{action}")]
 pub async fn control_all_blinds(
     action: web::Path<String>,
+    query: web::Query<ControlQuery>,
     blind_service: web::Data<BlindService>,
 ) -> Result<HttpResponse, AppError> {
     let action = action.into_inner();
 
-    let result = blind_service.control_all_blinds(&action).await?;
-    Ok(HttpResponse::Ok().json(result))
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .control_all_blinds(&action)
+        .await?;
+    Ok(batch_response(result))
+}
+
+#[post("/blinds/all/position/{percent}")]
+pub async fn set_all_position(
+    percent: web::Path<String>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let percent = percent.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_position_all(&percent)
+        .await?;
+    Ok(batch_response(result))
+}
+
+#[post("/blinds/all/tilt/{value}")]
+pub async fn set_all_tilt(
+    value: web::Path<String>,
+    query: web::Query<ControlQuery>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let value = value.into_inner();
+
+    let result = blind_service
+        .with_confirmation(query.confirm)
+        .set_tilt_all(&value)
+        .await?;
+    Ok(batch_response(result))
+}
+
+#[get("/blinds/batches/{batch_id}")]
+pub async fn get_batch_progress(
+    batch_id: web::Path<String>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let progress = blind_service.get_batch_progress(&batch_id).await?;
+    Ok(HttpResponse::Ok().json(progress))
+}
+
+/// Los lotes escalonados responden `202 Accepted` con el identificador del lote
+fn batch_response(outcome: BatchOutcome) -> HttpResponse {
+    match outcome {
+        BatchOutcome::Completed(response) => HttpResponse::Ok().json(response),
+        BatchOutcome::Started(handle) => HttpResponse::Accepted().json(handle),
+    }
 }
 
 #[cfg(test)]
@@ -43,19 +169,15 @@ mod tests {
     use crate::config::{AppConfig, BlindConfig, MqttConfig, ServerConfig};
     use crate::services::{BlindService, MqttService};
     use actix_web::{test, App};
-    use rumqttc::{AsyncClient, MqttOptions};
+    use rumqttc::{AsyncClient, EventLoop, MqttOptions};
     use std::sync::Arc;
-    use tokio::sync::Mutex;
 
     fn create_test_config() -> AppConfig {
         AppConfig {
             mqtt: MqttConfig {
-                broker_host: "localhost".to_string(),
-                broker_port: 1883,
                 client_id: "test".to_string(),
                 keep_alive_secs: 60,
-                username: None,
-                password: None,
+                ..MqttConfig::default()
             },
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
@@ -71,6 +193,7 @@ mod tests {
                     enabled: true,
                     battery_topic: None,
                     status_topic: None,
+                    ..Default::default()
                 },
                 BlindConfig {
                     id: "blind_002".to_string(),
@@ -81,23 +204,26 @@ mod tests {
                     enabled: true,
                     battery_topic: None,
                     status_topic: None,
+                    ..Default::default()
                 },
             ],
+            ..AppConfig::default()
         }
     }
 
-    async fn create_test_service() -> web::Data<BlindService> {
+    // The event loop is returned so the request channel stays open during the test
+    async fn create_test_service() -> (web::Data<BlindService>, EventLoop) {
         let config = Arc::new(create_test_config());
         let mqtt_options = MqttOptions::new("test", "localhost", 1883);
-        let (client, _) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let (client, eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
         let blind_service = BlindService::new(mqtt_service, config);
-        web::Data::new(blind_service)
+        (web::Data::new(blind_service), eventloop)
     }
 
     #[actix_web::test]
     async fn test_control_blind_by_id() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_blind_by_id)).await;
 
@@ -110,7 +236,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_control_blind_by_id_invalid_action() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_blind_by_id)).await;
 
@@ -123,7 +249,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_control_blind_by_id_not_found() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_blind_by_id)).await;
 
@@ -134,9 +260,78 @@ mod tests {
         assert!(resp.status().is_client_error());
     }
 
+    #[actix_web::test]
+    async fn test_set_blind_position() {
+        let (service, _eventloop) = create_test_service().await;
+        let app =
+            test::init_service(App::new().app_data(service).service(set_blind_position)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/blinds/id/blind_001/position/50")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["command"], "SET_POSITION");
+        assert_eq!(resp["value"], 50);
+    }
+
+    #[actix_web::test]
+    async fn test_set_blind_position_out_of_range() {
+        let (service, _eventloop) = create_test_service().await;
+        let app =
+            test::init_service(App::new().app_data(service).service(set_blind_position)).await;
+
+        for uri in [
+            "/blinds/id/blind_001/position/101",
+            "/blinds/id/blind_001/position/abc",
+        ] {
+            let req = test::TestRequest::post().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_set_room_and_all_position() {
+        let (service, _eventloop) = create_test_service().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(service)
+                .service(set_room_position)
+                .service(set_all_position),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/blinds/room/living_room/position/25")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["total_blinds"], 2);
+        assert_eq!(resp["value"], 25);
+
+        let req = test::TestRequest::post()
+            .uri("/blinds/all/position/150")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_set_blind_tilt_unsupported() {
+        let (service, _eventloop) = create_test_service().await;
+        let app = test::init_service(App::new().app_data(service).service(set_blind_tilt)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/blinds/id/blind_001/tilt/open")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error_code"], "UNSUPPORTED_COMMAND");
+    }
+
     #[actix_web::test]
     async fn test_control_blinds_by_room() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_blinds_by_room)).await;
 
@@ -149,7 +344,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_control_blinds_by_room_not_found() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_blinds_by_room)).await;
 
@@ -162,7 +357,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_control_all_blinds() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_all_blinds)).await;
 
@@ -175,7 +370,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_control_all_blinds_invalid_action() {
-        let service = create_test_service().await;
+        let (service, _eventloop) = create_test_service().await;
         let app =
             test::init_service(App::new().app_data(service).service(control_all_blinds)).await;
 
@@ -1,4 +1,6 @@
 use crate::errors::AppError;
+use crate::models::responses::BlindsStatusResponse;
+use crate::services::mqtt_inspector::TrafficFilter;
 use crate::services::BlindService;
 use actix_web::{get, web, HttpResponse, Result};
 
@@ -6,10 +8,8 @@ use actix_web::{get, web, HttpResponse, Result};
 pub async fn get_blinds_status(
     blind_service: web::Data<BlindService>,
 ) -> Result<HttpResponse, AppError> {
-    let status = blind_service.get_blinds_status();
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "rooms": status
-    })))
+    let rooms = blind_service.get_blinds_status().await;
+    Ok(HttpResponse::Ok().json(BlindsStatusResponse { rooms }))
 }
 
 #[get("/blinds/rooms")]
@@ -20,10 +20,19 @@ pub async fn get_rooms(blind_service: web::Data<BlindService>) -> Result<HttpRes
 
 #[get("/blinds/config")]
 pub async fn get_config(blind_service: web::Data<BlindService>) -> Result<HttpResponse, AppError> {
-    let config_response = blind_service.get_config();
+    let config_response = blind_service.get_config().await;
     Ok(HttpResponse::Ok().json(config_response))
 }
 
+#[get("/blinds/id/{blind_id}/battery")]
+pub async fn get_battery_status(
+    blind_id: web::Path<String>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let battery_response = blind_service.get_battery_status(&blind_id).await?;
+    Ok(HttpResponse::Ok().json(battery_response))
+}
+
 #[get("/status")]
 pub async fn get_system_status(
     blind_service: web::Data<BlindService>,
@@ -43,25 +52,48 @@ pub async fn get_mqtt_info(
     })))
 }
 
+#[get("/mqtt/outbox")]
+pub async fn get_mqtt_outbox(
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let outbox = blind_service.get_outbox().await;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "total": outbox.len(),
+        "commands": outbox,
+        "timestamp": chrono::Utc::now()
+    })))
+}
+
+#[get("/mqtt/messages")]
+pub async fn get_mqtt_messages(
+    query: web::Query<TrafficFilter>,
+    blind_service: web::Data<BlindService>,
+) -> Result<HttpResponse, AppError> {
+    let messages = blind_service.get_mqtt_messages(&query).await;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "total": messages.len(),
+        "messages": messages,
+        "timestamp": chrono::Utc::now()
+    })))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::{AppConfig, BlindConfig, MqttConfig, ServerConfig};
+    use crate::services::mqtt_inspector::MqttInspector;
+    use crate::services::mqtt_service::MqttMessage;
     use crate::services::{BlindService, MqttService};
     use actix_web::{test, App};
     use rumqttc::{AsyncClient, MqttOptions};
     use std::sync::Arc;
-    use tokio::sync::Mutex;
 
     fn create_test_config() -> AppConfig {
         AppConfig {
             mqtt: MqttConfig {
-                broker_host: "localhost".to_string(),
-                broker_port: 1883,
                 client_id: "test".to_string(),
                 keep_alive_secs: 60,
-                username: None,
-                password: None,
+                ..MqttConfig::default()
             },
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
@@ -76,7 +108,9 @@ mod tests {
                 enabled: true,
                 battery_topic: None,
                 status_topic: None,
+                ..Default::default()
             }],
+            ..AppConfig::default()
         }
     }
 
@@ -84,7 +118,7 @@ mod tests {
         let config = Arc::new(create_test_config());
         let mqtt_options = MqttOptions::new("test", "localhost", 1883);
         let (client, _) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
         let blind_service = BlindService::new(mqtt_service, config);
         web::Data::new(blind_service)
     }
@@ -119,6 +153,25 @@ mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_get_battery_status_without_topic() {
+        let service = create_test_service().await;
+        let app =
+            test::init_service(App::new().app_data(service).service(get_battery_status)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/blinds/id/test_blind/battery")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get()
+            .uri("/blinds/id/nonexistent/battery")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
     #[actix_web::test]
     async fn test_get_system_status() {
         let service = create_test_service().await;
@@ -128,4 +181,36 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_get_mqtt_messages() {
+        let config = create_test_config();
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service =
+            MqttService::new(client).with_inspector(MqttInspector::new(config.clone()));
+        let message = |topic: &str| MqttMessage {
+            topic: topic.to_string(),
+            payload: b"open".to_vec(),
+            ..MqttMessage::default()
+        };
+        mqtt_service.record_incoming(&message("test/topic")).await;
+        mqtt_service.record_incoming(&message("other/topic")).await;
+        let service = web::Data::new(BlindService::new(mqtt_service, Arc::new(config)));
+        let app = test::init_service(App::new().app_data(service).service(get_mqtt_messages)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/mqtt/messages?blind_id=test_blind&direction=inbound")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["messages"][0]["topic"], "test/topic");
+        assert_eq!(body["messages"][0]["payload"], "open");
+
+        let req = test::TestRequest::get()
+            .uri("/mqtt/messages?direction=outbound")
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["total"], 0);
+    }
 }
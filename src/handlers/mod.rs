@@ -1,7 +1,9 @@
 pub mod blinds;
 pub mod health;
 pub mod info;
+pub mod zigbee2mqtt;
 
 pub use blinds::*;
 pub use health::*;
 pub use info::*;
+pub use zigbee2mqtt::*;
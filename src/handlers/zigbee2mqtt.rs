@@ -0,0 +1,60 @@
+use crate::errors::AppError;
+use crate::services::zigbee2mqtt_bridge::{AdoptRequest, Zigbee2MqttBridge};
+use actix_web::{get, post, web, HttpResponse, Result};
+
+#[get("/zigbee2mqtt/candidates")]
+pub async fn get_zigbee2mqtt_candidates(
+    bridge: web::Data<Zigbee2MqttBridge>,
+) -> Result<HttpResponse, AppError> {
+    let candidates = bridge.candidates().await;
+    Ok(HttpResponse::Ok().json(candidates))
+}
+
+#[post("/zigbee2mqtt/candidates/{ieee_address}/adopt")]
+pub async fn adopt_zigbee2mqtt_candidate(
+    path: web::Path<String>,
+    request: Option<web::Json<AdoptRequest>>,
+    bridge: web::Data<Zigbee2MqttBridge>,
+) -> Result<HttpResponse, AppError> {
+    let ieee_address = path.into_inner();
+    let request = request.map(web::Json::into_inner).unwrap_or_default();
+
+    let blind = bridge.adopt(&ieee_address, request).await?;
+    Ok(HttpResponse::Created().json(blind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::services::MqttService;
+    use actix_web::{http::StatusCode, test, App};
+    use rumqttc::{AsyncClient, MqttOptions};
+
+    #[actix_web::test]
+    async fn test_adopt_unknown_device() {
+        let (client, _) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 10);
+        let mqtt_service = MqttService::new(client);
+        let bridge = Zigbee2MqttBridge::new(mqtt_service, AppConfig::default());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(bridge))
+                .service(get_zigbee2mqtt_candidates)
+                .service(adopt_zigbee2mqtt_candidate),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/zigbee2mqtt/candidates")
+            .to_request();
+        let candidates: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        assert!(candidates.is_empty());
+
+        let req = test::TestRequest::post()
+            .uri("/zigbee2mqtt/candidates/0x00158d0002/adopt")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,6 @@
+// Library target: the binary in main.rs wires these modules together
+pub mod config;
+pub mod errors;
+pub mod handlers;
+pub mod models;
+pub mod services;
@@ -1,23 +1,28 @@
 use std::time::Duration;
 
 use actix_web::{middleware::Logger, web, App, HttpServer};
-use rumqttc::{AsyncClient, MqttOptions};
-use std::sync::Arc;
-use tokio::{sync::Mutex, task};
-
-// Import our modules
-mod config;
-mod errors;
-mod handlers;
-mod models;
-mod services;
-
-use config::AppConfig;
+use rumqttc::{v5, AsyncClient};
+
+use tabi_backend::{config, errors, handlers, services};
+
+use config::{AppConfig, MqttProtocolVersion, SharedConfig};
+use services::availability::Availability;
+use services::broker_stats::BrokerStats;
+use services::command_ingress::CommandIngress;
+use services::home_assistant::HomeAssistantDiscovery;
+use services::mqtt_api::MqttApi;
+use services::mqtt_event_loop::{self, MqttEventLoop, ReconnectPolicy};
+use services::mqtt_inspector::MqttInspector;
+use services::mqtt_service::MqttClient;
+use services::mqtt_transport;
+use services::outbox::Outbox;
+use services::zigbee2mqtt_bridge::Zigbee2MqttBridge;
 use services::{BlindService, MqttService};
 
 #[derive(Clone)]
 struct AppState {
     blind_service: BlindService,
+    zigbee2mqtt_bridge: Zigbee2MqttBridge,
 }
 
 #[actix_web::main]
@@ -45,19 +50,133 @@ async fn main() -> std::io::Result<()> {
         config.mqtt.broker_host, config.mqtt.broker_port
     );
 
+    // Start the built-in broker before connecting to it
+    if config.mqtt.embedded {
+        start_embedded_broker(&config).await;
+    }
+
     // Setup MQTT client
-    let mqtt_client = setup_mqtt_client(&config).await;
-    let config_arc = Arc::new(config.clone());
+    let (mqtt_client, eventloop) = setup_mqtt_client(&config).await;
+    // Blinds adopted at runtime are saved back to config.json
+    let shared_config = SharedConfig::with_file(config.clone(), "config.json");
 
     // Create services
-    let mqtt_service = MqttService::new(mqtt_client.clone());
-    let blind_service = BlindService::new(mqtt_service.clone(), config_arc);
+    let broker_stats = BrokerStats::new();
+    let mqtt_service = MqttService::new(mqtt_client)
+        .with_inspector(MqttInspector::new(shared_config.clone()))
+        .with_broker_stats(broker_stats.clone());
+    let mut blind_service = BlindService::new(mqtt_service.clone(), shared_config.clone());
+    if config.outbox.enabled {
+        // Commands sent while the broker is down survive restarts in the outbox
+        let outbox = Outbox::with_file(
+            mqtt_service.clone(),
+            config.outbox.ttl_secs,
+            &config.outbox.path,
+        );
+        outbox.start();
+        blind_service = blind_service.with_outbox(outbox);
+    }
+    let availability = Availability::new(mqtt_service.clone(), config.mqtt.availability.clone());
+    let discovery = HomeAssistantDiscovery::new(mqtt_service.clone(), shared_config.clone());
+    let zigbee2mqtt_bridge = Zigbee2MqttBridge::new(mqtt_service.clone(), shared_config);
+    let command_ingress = CommandIngress::new(
+        blind_service.clone(),
+        mqtt_service.clone(),
+        config.command_ingress.clone(),
+    );
+    let mqtt_api = MqttApi::new(
+        blind_service.clone(),
+        mqtt_service.clone(),
+        config.mqtt_api.clone(),
+    );
 
     // Start MQTT event loop
-    start_mqtt_event_loop(mqtt_client, mqtt_service.clone()).await;
+    mqtt_event_loop::spawn_event_loop(
+        eventloop,
+        mqtt_service.clone(),
+        ReconnectPolicy::from_config(&config.mqtt),
+    );
+
+    // Feed the live state store and battery history from the blinds' topics
+    if let Err(e) = blind_service.start_telemetry_ingestion().await {
+        eprintln!(
+            "❌ Error suscribiendo a los temas de estado y batería: {}",
+            e
+        );
+    }
+
+    // Surface the broker's $SYS statistics in /mqtt/info where the ACL allows
+    if !config.mqtt.sys_topics.is_empty() {
+        if let Err(e) = broker_stats
+            .start(&mqtt_service, config.mqtt.sys_topics.clone())
+            .await
+        {
+            eprintln!("❌ Error suscribiendo a las estadísticas $SYS: {}", e);
+        }
+    }
+
+    // Announce the backend as online after every ConnAck
+    if config.mqtt.availability.enabled {
+        availability.start();
+        println!(
+            "📣 Disponibilidad publicada en {}",
+            config.mqtt.availability.topic
+        );
+    }
+
+    // Publish Home Assistant discovery documents on every connection
+    if config.home_assistant.enabled {
+        if let Err(e) = discovery.start().await {
+            eprintln!(
+                "❌ Error iniciando el descubrimiento de Home Assistant: {}",
+                e
+            );
+        } else {
+            println!(
+                "🏡 Descubrimiento de Home Assistant activo (prefijo: {})",
+                config.home_assistant.discovery_prefix
+            );
+        }
+    }
+
+    // Offer the bridge's cover devices as candidate blinds
+    if config.zigbee2mqtt.enabled {
+        if let Err(e) = zigbee2mqtt_bridge.start().await {
+            eprintln!("❌ Error suscribiendo al puente Zigbee2MQTT: {}", e);
+        } else {
+            println!(
+                "🐝 Detección de cortinas Zigbee2MQTT activa (tema base: {})",
+                config.zigbee2mqtt.base_topic
+            );
+        }
+    }
+
+    // Accept blind commands published by wall switches and automations
+    if config.command_ingress.enabled {
+        if let Err(e) = command_ingress.start().await {
+            eprintln!("❌ Error suscribiendo a los comandos MQTT: {}", e);
+        } else {
+            println!(
+                "🎛️ Comandos MQTT activos en {}/# (resultados en {})",
+                config.command_ingress.command_prefix, config.command_ingress.result_prefix
+            );
+        }
+    }
+
+    // Answer status queries from dashboards that only speak MQTT
+    if config.mqtt_api.enabled {
+        if let Err(e) = mqtt_api.start().await {
+            eprintln!("❌ Error suscribiendo a la API MQTT: {}", e);
+        } else {
+            println!("🔁 API MQTT activa en {}/+", config.mqtt_api.request_prefix);
+        }
+    }
 
     // Create application state
-    let app_state = AppState { blind_service };
+    let app_state = AppState {
+        blind_service,
+        zigbee2mqtt_bridge,
+    };
 
     println!(
         "🚀 Iniciando servidor HTTP en {}:{}",
@@ -65,9 +184,10 @@ async fn main() -> std::io::Result<()> {
     );
 
     // Start HTTP server
-    HttpServer::new(move || {
+    let result = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(app_state.blind_service.clone()))
+            .app_data(web::Data::new(app_state.zigbee2mqtt_bridge.clone()))
             .wrap(Logger::default())
             // Health endpoints
             .service(handlers::hello_world)
@@ -79,71 +199,114 @@ async fn main() -> std::io::Result<()> {
             .service(handlers::get_config)
             .service(handlers::get_system_status)
             .service(handlers::get_mqtt_info)
+            .service(handlers::get_mqtt_outbox)
+            .service(handlers::get_mqtt_messages)
+            .service(handlers::get_battery_status)
             // Blind control endpoints
             .service(handlers::control_blind_by_id)
             .service(handlers::control_blinds_by_room)
             .service(handlers::control_all_blinds)
+            .service(handlers::set_blind_position)
+            .service(handlers::set_room_position)
+            .service(handlers::set_all_position)
+            .service(handlers::set_blind_tilt)
+            .service(handlers::set_room_tilt)
+            .service(handlers::set_all_tilt)
+            .service(handlers::get_batch_progress)
+            // Zigbee2MQTT adoption endpoints
+            .service(handlers::get_zigbee2mqtt_candidates)
+            .service(handlers::adopt_zigbee2mqtt_candidate)
     })
     .bind((config.server.host.as_str(), config.server.port))?
     .run()
-    .await
-}
-
-async fn setup_mqtt_client(config: &AppConfig) -> Arc<Mutex<AsyncClient>> {
-    // Configure MQTT options
-    let mut mqttoptions = MqttOptions::new(
-        &config.mqtt.client_id,
-        &config.mqtt.broker_host,
-        config.mqtt.broker_port,
-    );
-    mqttoptions.set_keep_alive(Duration::from_secs(config.mqtt.keep_alive_secs));
+    .await;
 
-    // Configure authentication if available
-    if let (Some(username), Some(password)) = (&config.mqtt.username, &config.mqtt.password) {
-        mqttoptions.set_credentials(username, password);
-        println!("🔐 Autenticación MQTT configurada");
+    // Announce the backend as offline before exiting
+    if config.mqtt.availability.enabled {
+        if let Err(e) = availability.shutdown().await {
+            eprintln!("❌ Error publicando la desconexión del backend: {}", e);
+        }
     }
 
-    // Create async client
-    let (client, _eventloop) = AsyncClient::new(mqttoptions, 10);
-    Arc::new(Mutex::new(client))
+    result
 }
 
-async fn start_mqtt_event_loop(_client: Arc<Mutex<AsyncClient>>, mqtt_service: MqttService) {
-    // We need to recreate the eventloop because we can't move it from setup_mqtt_client
-    // Note: In a full implementation, you would handle the actual MQTT eventloop here
-    // For now, we'll use a simplified monitoring approach
-
-    // Start MQTT event handling task
-    task::spawn(async move {
-        println!("🔄 Iniciando gestor de eventos MQTT...");
+#[cfg(feature = "embedded-broker")]
+async fn start_embedded_broker(config: &AppConfig) {
+    match services::embedded_broker::start(&config.mqtt).await {
+        Ok(()) => println!("📡 Broker MQTT integrado iniciado"),
+        Err(e) => {
+            eprintln!("❌ Error iniciando el broker MQTT integrado: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-        // Set initial connection status
-        mqtt_service.set_connected(true).await;
+#[cfg(not(feature = "embedded-broker"))]
+async fn start_embedded_broker(_config: &AppConfig) {
+    eprintln!("❌ mqtt.embedded requiere compilar con: cargo build --features embedded-broker");
+    std::process::exit(1);
+}
 
-        loop {
-            // Basic connection monitoring
-            // In a full implementation, you'd handle the actual eventloop here
-            tokio::time::sleep(Duration::from_secs(30)).await;
+async fn setup_mqtt_client(config: &AppConfig) -> (MqttClient, MqttEventLoop) {
+    println!(
+        "🔌 Transporte MQTT: {:?} ({}), protocolo {:?}",
+        config.mqtt.transport(),
+        mqtt_transport::broker_address(&config.mqtt),
+        config.mqtt.protocol_version
+    );
+    let keep_alive = Duration::from_secs(config.mqtt.keep_alive_secs);
+    let credentials = match (&config.mqtt.username, &config.mqtt.password) {
+        (Some(username), Some(password)) => {
+            println!("🔐 Autenticación MQTT configurada");
+            Some((username, password))
+        }
+        _ => None,
+    };
 
-            // You could add connection health checks here
-            if !mqtt_service.is_connected().await {
-                log::warn!("MQTT connection lost, attempting to reconnect...");
-                // Add reconnection logic here
+    // Configure MQTT options and transport (tcp, tls, ws, wss); the event loop
+    // is driven by mqtt_event_loop
+    match config.mqtt.protocol_version {
+        MqttProtocolVersion::V4 => {
+            let mut mqttoptions =
+                exit_on_transport_error(mqtt_transport::mqtt_options(&config.mqtt));
+            mqttoptions.set_keep_alive(keep_alive);
+            if let Some((username, password)) = credentials {
+                mqttoptions.set_credentials(username, password);
+            }
+            let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+            (client.into(), eventloop.into())
+        }
+        MqttProtocolVersion::V5 => {
+            let mut mqttoptions =
+                exit_on_transport_error(mqtt_transport::mqtt_options_v5(&config.mqtt));
+            mqttoptions.set_keep_alive(keep_alive);
+            if let Some((username, password)) = credentials {
+                mqttoptions.set_credentials(username, password);
             }
+            let (client, eventloop) = v5::AsyncClient::new(mqttoptions, 10);
+            (client.into(), eventloop.into())
         }
-    });
+    }
+}
+
+fn exit_on_transport_error<T>(result: Result<T, errors::AppError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("❌ Error en la configuración del transporte MQTT: {}", e);
+        std::process::exit(1);
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use actix_web::{test, App};
+    use std::sync::Arc;
 
     #[actix_web::test]
     async fn test_app_creation() {
         let config = AppConfig::default();
-        let mqtt_client = setup_mqtt_client(&config).await;
+        let (mqtt_client, _eventloop) = setup_mqtt_client(&config).await;
         let config_arc = Arc::new(config);
         let mqtt_service = MqttService::new(mqtt_client);
         let blind_service = BlindService::new(mqtt_service, config_arc);
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BatterySample {
+    pub percent: u8,
+    pub voltage: Option<f64>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryTrend {
+    Charging,
+    Discharging,
+    Stable,
+    Unknown,
+}
+
+/// Evento emitido cuando una persiana cruza el umbral de batería baja
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LowBatteryEvent {
+    pub event: String,
+    pub blind_id: String,
+    pub blind_name: String,
+    pub room: String,
+    pub percent: u8,
+    pub threshold_percent: u8,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
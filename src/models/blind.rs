@@ -2,11 +2,21 @@ use crate::errors::AppError;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+/// Valor máximo de posición (0 = cerrada, 100 = abierta)
+pub const MAX_POSITION: u8 = 100;
+
+/// Valor máximo de inclinación de las lamas (0 = cerradas)
+pub const MAX_TILT: u8 = 100;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum BlindCommand {
     Open,
     Close,
     Stop,
+    SetPosition(u8),
+    SetTilt(u8),
+    OpenTilt,
+    CloseTilt,
 }
 
 impl BlindCommand {
@@ -15,10 +25,84 @@ impl BlindCommand {
             BlindCommand::Open => "OPEN",
             BlindCommand::Close => "CLOSE",
             BlindCommand::Stop => "STOP",
+            BlindCommand::SetPosition(_) => "SET_POSITION",
+            BlindCommand::SetTilt(_) => "SET_TILT",
+            BlindCommand::OpenTilt => "OPEN_TILT",
+            BlindCommand::CloseTilt => "CLOSE_TILT",
+        }
+    }
+
+    /// Indica si el comando actúa sobre la inclinación de las lamas
+    pub fn is_tilt(&self) -> bool {
+        matches!(
+            self,
+            BlindCommand::SetTilt(_) | BlindCommand::OpenTilt | BlindCommand::CloseTilt
+        )
+    }
+
+    /// Crea un comando de posición a partir del segmento de la URL
+    pub fn set_position(percent: &str) -> Result<Self, AppError> {
+        let command = percent
+            .trim()
+            .parse::<u8>()
+            .map(BlindCommand::SetPosition)
+            .map_err(|_| position_out_of_range(percent))?;
+        command.validate()?;
+        Ok(command)
+    }
+
+    /// Crea un comando de inclinación: `open`, `close` o un valor entre 0 y 100
+    pub fn tilt(value: &str) -> Result<Self, AppError> {
+        match value.trim().to_lowercase().as_str() {
+            "open" => return Ok(BlindCommand::OpenTilt),
+            "close" | "closed" => return Ok(BlindCommand::CloseTilt),
+            _ => {}
+        }
+
+        let command = value
+            .trim()
+            .parse::<u8>()
+            .map(BlindCommand::SetTilt)
+            .map_err(|_| tilt_out_of_range(value))?;
+        command.validate()?;
+        Ok(command)
+    }
+
+    /// Comprueba que los valores del comando estén dentro de rango
+    pub fn validate(&self) -> Result<(), AppError> {
+        match self {
+            BlindCommand::SetPosition(position) if *position > MAX_POSITION => {
+                Err(position_out_of_range(position))
+            }
+            BlindCommand::SetTilt(tilt) if *tilt > MAX_TILT => Err(tilt_out_of_range(tilt)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Valor numérico asociado al comando, si lo tiene
+    pub fn value(&self) -> Option<u8> {
+        match self {
+            BlindCommand::SetPosition(position) => Some(*position),
+            BlindCommand::SetTilt(tilt) => Some(*tilt),
+            _ => None,
         }
     }
 }
 
+fn position_out_of_range(value: impl std::fmt::Display) -> AppError {
+    AppError::ValidationError(format!(
+        "Position must be an integer between 0 and {}, got '{}'",
+        MAX_POSITION, value
+    ))
+}
+
+fn tilt_out_of_range(value: impl std::fmt::Display) -> AppError {
+    AppError::ValidationError(format!(
+        "Tilt must be 'open', 'close' or an integer between 0 and {}, got '{}'",
+        MAX_TILT, value
+    ))
+}
+
 impl FromStr for BlindCommand {
     type Err = AppError;
 
@@ -27,6 +111,8 @@ impl FromStr for BlindCommand {
             "OPEN" => Ok(BlindCommand::Open),
             "CLOSE" => Ok(BlindCommand::Close),
             "STOP" => Ok(BlindCommand::Stop),
+            "OPEN_TILT" => Ok(BlindCommand::OpenTilt),
+            "CLOSE_TILT" => Ok(BlindCommand::CloseTilt),
             _ => Err(AppError::InvalidAction(s.to_string())),
         }
     }
@@ -42,10 +128,86 @@ pub struct BlindStatus {
     pub status_topic: Option<String>,
     pub battery_topic: Option<String>,
     pub enabled: bool,
+    pub state: Option<String>,
+    pub position: Option<u8>,
+    pub tilt: Option<u8>,
+    /// `true` si `state` y `position` se estiman por tiempo de recorrido
+    pub estimated: bool,
+    pub capabilities: Capabilities,
     pub last_command: Option<BlindCommand>,
     pub last_update: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// Reporte recibido del dispositivo, ya interpretado (solo los campos presentes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusUpdate {
+    pub blind_id: String,
+    pub state: Option<String>,
+    pub position: Option<u8>,
+    pub tilt: Option<u8>,
+}
+
+/// Resultado de esperar la confirmación de un comando por parte del dispositivo
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckStatus {
+    /// El dispositivo reportó un estado coherente con el comando
+    Acknowledged,
+    /// No llegó ningún reporte coherente antes del timeout
+    TimedOut,
+    /// El dispositivo reportó un error
+    Rejected,
+    /// La persiana no tiene tema de estado del que esperar confirmación
+    Unsupported,
+}
+
+/// Último estado conocido de una persiana (reportado por el dispositivo o
+/// deducido de los comandos enviados)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlindState {
+    pub state: Option<String>,
+    pub position: Option<u8>,
+    pub tilt: Option<u8>,
+    pub last_command: Option<BlindCommand>,
+    pub last_command_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_report_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl BlindState {
+    /// Marca de tiempo del último comando o reporte, la más reciente
+    pub fn last_update(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.last_command_at.max(self.last_report_at)
+    }
+}
+
+/// Comandos que admite una persiana, además de abrir y cerrar
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub position: bool,
+    pub tilt: bool,
+    pub stop: bool,
+}
+
+impl Capabilities {
+    pub fn supports(&self, command: &BlindCommand) -> bool {
+        match command {
+            BlindCommand::Open | BlindCommand::Close => true,
+            BlindCommand::Stop => self.stop,
+            BlindCommand::SetPosition(_) => self.position,
+            BlindCommand::SetTilt(_) | BlindCommand::OpenTilt | BlindCommand::CloseTilt => {
+                self.tilt
+            }
+        }
+    }
+}
+
+/// Posición estimada de un motor sin reporte de posición
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionEstimate {
+    pub position: Option<u8>,
+    pub state: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlindControlRequest {
     pub action: BlindCommand,
@@ -69,8 +231,13 @@ impl BlindControlRequest {
     }
 }
 
-impl From<&crate::config::BlindConfig> for BlindStatus {
-    fn from(blind_config: &crate::config::BlindConfig) -> Self {
+impl BlindStatus {
+    /// Estado estático de configuración, con las capacidades del perfil de
+    /// `device_type` (incluidos los definidos en `device_profiles`)
+    pub fn new(
+        blind_config: &crate::config::BlindConfig,
+        config: &crate::config::AppConfig,
+    ) -> Self {
         Self {
             id: blind_config.id.clone(),
             name: blind_config.name.clone(),
@@ -80,10 +247,39 @@ impl From<&crate::config::BlindConfig> for BlindStatus {
             status_topic: blind_config.status_topic.clone(),
             battery_topic: blind_config.battery_topic.clone(),
             enabled: blind_config.enabled,
+            state: None,
+            position: None,
+            tilt: None,
+            estimated: false,
+            capabilities: config
+                .device_profile(&blind_config.device_type)
+                .capabilities(blind_config),
             last_command: None,
             last_update: None,
         }
     }
+
+    /// Completa el estado estático de configuración con el estado en vivo
+    pub fn with_state(mut self, state: Option<&BlindState>) -> Self {
+        if let Some(state) = state {
+            self.state = state.state.clone();
+            self.position = state.position;
+            self.tilt = state.tilt;
+            self.last_command = state.last_command.clone();
+            self.last_update = state.last_update();
+        }
+        self
+    }
+
+    /// Sustituye estado y posición por la estimación basada en tiempos de recorrido
+    pub fn with_estimate(mut self, estimate: Option<PositionEstimate>) -> Self {
+        if let Some(estimate) = estimate {
+            self.state = Some(estimate.state);
+            self.position = estimate.position;
+            self.estimated = true;
+        }
+        self
+    }
 }
 
 #[cfg(test)]
@@ -113,6 +309,91 @@ mod tests {
         assert_eq!(BlindCommand::Open.as_str(), "OPEN");
         assert_eq!(BlindCommand::Close.as_str(), "CLOSE");
         assert_eq!(BlindCommand::Stop.as_str(), "STOP");
+        assert_eq!(BlindCommand::SetPosition(50).as_str(), "SET_POSITION");
+    }
+
+    #[test]
+    fn test_set_position_validation() {
+        assert_eq!(
+            BlindCommand::set_position("0").unwrap(),
+            BlindCommand::SetPosition(0)
+        );
+        assert_eq!(
+            BlindCommand::set_position("100").unwrap(),
+            BlindCommand::SetPosition(100)
+        );
+        assert!(matches!(
+            BlindCommand::set_position("101"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            BlindCommand::set_position("300"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            BlindCommand::set_position("-1"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            BlindCommand::set_position("half"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(BlindCommand::SetPosition(101).validate().is_err());
+    }
+
+    #[test]
+    fn test_tilt_commands() {
+        assert_eq!(BlindCommand::tilt("open").unwrap(), BlindCommand::OpenTilt);
+        assert_eq!(
+            BlindCommand::tilt("CLOSE").unwrap(),
+            BlindCommand::CloseTilt
+        );
+        assert_eq!(BlindCommand::tilt("30").unwrap(), BlindCommand::SetTilt(30));
+        assert!(matches!(
+            BlindCommand::tilt("101"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            BlindCommand::tilt("sideways"),
+            Err(AppError::ValidationError(_))
+        ));
+        assert_eq!(
+            BlindCommand::from_str("open_tilt").unwrap(),
+            BlindCommand::OpenTilt
+        );
+        assert!(BlindCommand::SetTilt(30).is_tilt());
+        assert!(!BlindCommand::SetPosition(30).is_tilt());
+    }
+
+    #[test]
+    fn test_blind_status_with_state() {
+        let config = crate::config::AppConfig::default();
+        let blind = config.get_blind_by_id("blind_001").unwrap();
+        let state = BlindState {
+            state: Some("open".to_string()),
+            position: Some(100),
+            tilt: None,
+            last_command: Some(BlindCommand::Open),
+            last_command_at: None,
+            last_report_at: Some(chrono::Utc::now()),
+        };
+
+        let status = BlindStatus::new(blind, &config).with_state(Some(&state));
+        assert_eq!(status.state.as_deref(), Some("open"));
+        assert_eq!(status.position, Some(100));
+        assert_eq!(status.last_command, Some(BlindCommand::Open));
+        assert_eq!(status.last_update, state.last_report_at);
+
+        let status = BlindStatus::new(blind, &config).with_state(None);
+        assert!(status.last_update.is_none());
+
+        // Capabilities come from the configured profile, not only the built-in one
+        let mut config = config.clone();
+        config.device_profiles.insert(
+            blind.device_type.clone(),
+            serde_json::from_str(r#"{"position": false}"#).unwrap(),
+        );
+        assert!(!BlindStatus::new(blind, &config).capabilities.position);
     }
 
     #[test]
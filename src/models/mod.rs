@@ -1,3 +1,4 @@
+pub mod battery;
 pub mod blind;
 pub mod responses;
 
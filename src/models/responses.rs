@@ -1,5 +1,8 @@
-use crate::models::blind::{BlindCommand, BlindStatus, RoomInfo};
+use crate::config::MqttProtocolVersion;
+use crate::models::battery::{BatterySample, BatteryTrend};
+use crate::models::blind::{AckStatus, BlindCommand, BlindStatus, RoomInfo};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
@@ -36,7 +39,12 @@ pub struct BlindControlResponse {
     pub blind_name: String,
     pub room: String,
     pub command: String,
+    pub value: Option<u8>,
     pub topic: String,
+    /// Confirmación del dispositivo; solo en modo confirmado
+    pub acknowledgement: Option<AckStatus>,
+    /// El broker no estaba disponible y el comando quedó en la cola
+    pub queued: bool,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
@@ -54,10 +62,23 @@ impl BlindControlResponse {
             blind_name,
             room,
             command: command.as_str().to_string(),
+            value: command.value(),
             topic,
+            acknowledgement: None,
+            queued: false,
             timestamp: chrono::Utc::now(),
         }
     }
+
+    pub fn with_acknowledgement(mut self, acknowledgement: Option<AckStatus>) -> Self {
+        self.acknowledgement = acknowledgement;
+        self
+    }
+
+    pub fn with_queued(mut self, queued: bool) -> Self {
+        self.queued = queued;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,15 +88,22 @@ pub struct BatchControlResult {
     pub status: String,
     pub topic: Option<String>,
     pub error: Option<String>,
+    /// Confirmación del dispositivo; solo en modo confirmado y si se publicó el comando
+    pub acknowledgement: Option<AckStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchControlResponse {
     pub command: String,
+    pub value: Option<u8>,
     pub target: String, // room name or "all"
     pub total_blinds: usize,
     pub successful: usize,
     pub failed: usize,
+    /// Aceptados pero en cola hasta que vuelva el broker (incluidos en `successful`)
+    pub queued: usize,
+    /// No enviados porque un `STOP` canceló el lote escalonado
+    pub cancelled: usize,
     pub results: Vec<BatchControlResult>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
@@ -84,10 +112,13 @@ impl BatchControlResponse {
     pub fn new(command: BlindCommand, target: String) -> Self {
         Self {
             command: command.as_str().to_string(),
+            value: command.value(),
             target,
             total_blinds: 0,
             successful: 0,
             failed: 0,
+            queued: 0,
+            cancelled: 0,
             results: Vec::new(),
             timestamp: chrono::Utc::now(),
         }
@@ -100,11 +131,20 @@ impl BatchControlResponse {
             status: "success".to_string(),
             topic: Some(topic),
             error: None,
+            acknowledgement: None,
         });
         self.successful += 1;
         self.total_blinds += 1;
     }
 
+    pub fn add_queued(&mut self, blind_id: String, blind_name: String, topic: String) {
+        self.add_success(blind_id, blind_name, topic);
+        if let Some(result) = self.results.last_mut() {
+            result.status = "queued".to_string();
+        }
+        self.queued += 1;
+    }
+
     pub fn add_failure(&mut self, blind_id: String, blind_name: String, error: String) {
         self.results.push(BatchControlResult {
             blind_id,
@@ -112,10 +152,81 @@ impl BatchControlResponse {
             status: "error".to_string(),
             topic: None,
             error: Some(error),
+            acknowledgement: None,
         });
         self.failed += 1;
         self.total_blinds += 1;
     }
+
+    pub fn add_cancelled(&mut self, blind_id: String, blind_name: String) {
+        self.results.push(BatchControlResult {
+            blind_id,
+            blind_name,
+            status: "cancelled".to_string(),
+            topic: None,
+            error: None,
+            acknowledgement: None,
+        });
+        self.cancelled += 1;
+        self.total_blinds += 1;
+    }
+}
+
+/// Resultado de un comando recibido por MQTT, publicado bajo `result_prefix`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResultMessage {
+    /// El `request_id` del comando, si lo indicó
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub response: ApiResponse<serde_json::Value>,
+}
+
+/// Respuesta a una consulta de la API MQTT, publicada en el tema de respuesta
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiReplyMessage {
+    /// El `correlation_id` de la petición, si lo indicó
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    pub response: ApiResponse<serde_json::Value>,
+}
+
+/// Respuesta `202` de un lote escalonado, que sigue ejecutándose en segundo plano
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchHandleResponse {
+    pub batch_id: String,
+    pub status_url: String,
+    pub command: String,
+    pub target: String,
+    pub total_blinds: usize,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Running,
+    Completed,
+    /// Detenido por un `STOP` antes de arrancar todas las persianas
+    Cancelled,
+}
+
+/// Progreso de un lote escalonado
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchProgressResponse {
+    pub batch_id: String,
+    pub status: BatchStatus,
+    pub total_blinds: usize,
+    /// Persianas a las que ya se envió (o intentó enviar) el comando
+    pub dispatched: usize,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub result: BatchControlResponse,
+}
+
+/// Estado de las persianas agrupado por habitación
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlindsStatusResponse {
+    pub rooms: HashMap<String, Vec<BlindStatus>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -148,12 +259,76 @@ pub struct MqttConfigResponse {
     // Note: password is intentionally omitted for security
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MqttConnectionStats {
+    pub total_connections: u64,
+    pub reconnects: u64,
+    pub reconnect_attempts: u64,
+    pub consecutive_failures: u32,
+    pub connected_since: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_disconnect: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+    /// Último código de motivo de error recibido del broker (MQTT v5)
+    pub last_reason_code: Option<String>,
+    pub next_retry_delay_ms: Option<u64>,
+}
+
+/// Contadores de mensajes desde el arranque
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MqttTrafficStats {
+    pub messages_published: u64,
+    pub messages_received: u64,
+    pub publish_errors: u64,
+    /// Publicaciones QoS 1/2 pendientes de confirmación del broker
+    pub in_flight: u16,
+}
+
+/// Estadísticas `$SYS` publicadas por el broker, si el ACL permite leerlas
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BrokerSysStats {
+    pub version: Option<String>,
+    pub uptime: Option<String>,
+    pub clients_connected: Option<u64>,
+    /// Valores de `$SYS/broker/load/...` (`messages/received/1min`, ...)
+    pub load: BTreeMap<String, f64>,
+    /// Otros temas `$SYS/broker/...` suscritos
+    pub metrics: BTreeMap<String, String>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttInfoResponse {
+    pub connected: bool,
+    pub protocol_version: MqttProtocolVersion,
+    pub connection: MqttConnectionStats,
+    pub traffic: MqttTrafficStats,
+    pub subscriptions: Vec<String>,
+    /// `None` hasta recibir algún tema `$SYS`
+    pub broker: Option<BrokerSysStats>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfigResponse {
     pub host: String,
     pub port: u16,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryResponse {
+    pub blind_id: String,
+    pub blind_name: String,
+    pub battery_topic: String,
+    pub percent: Option<u8>,
+    pub voltage: Option<f64>,
+    pub last_update: Option<chrono::DateTime<chrono::Utc>>,
+    pub trend: BatteryTrend,
+    pub rate_percent_per_hour: Option<f64>,
+    pub low_battery: bool,
+    pub low_threshold_percent: u8,
+    pub history: Vec<BatterySample>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomsResponse {
     pub rooms: Vec<String>,
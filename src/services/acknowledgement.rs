@@ -0,0 +1,249 @@
+use crate::models::blind::{AckStatus, BlindCommand, StatusUpdate};
+use crate::services::mqtt_service::MqttMessage;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep_until, Instant};
+
+/// Margen (en %) al comparar la posición o inclinación reportada con la pedida
+const ACK_TOLERANCE_PERCENT: u8 = 2;
+
+/// Estados reportados que indican que el dispositivo no ejecutó el comando
+const REJECTED_STATES: [&str; 4] = ["error", "rejected", "obstructed", "blocked"];
+
+/// Espera el reporte del dispositivo que confirma un comando. Se crea antes de
+/// publicar para no perder un reporte rápido.
+pub struct AckWaiter {
+    blind_id: String,
+    command: BlindCommand,
+    /// `None` si la persiana no reporta estado
+    updates: Option<broadcast::Receiver<StatusUpdate>>,
+    /// Respuesta MQTT v5 al comando, si se pidió
+    response: Option<ExpectedResponse>,
+}
+
+/// Respuesta MQTT v5 esperada: mensaje en `topic` con los datos de correlación del comando
+pub struct ExpectedResponse {
+    pub topic: String,
+    pub correlation_data: Vec<u8>,
+    pub messages: broadcast::Receiver<MqttMessage>,
+}
+
+impl ExpectedResponse {
+    fn matches(&self, message: &MqttMessage) -> bool {
+        message.topic == self.topic
+            && message.correlation_data.as_deref() == Some(self.correlation_data.as_slice())
+    }
+}
+
+impl AckWaiter {
+    pub fn new(
+        blind_id: &str,
+        command: BlindCommand,
+        updates: Option<broadcast::Receiver<StatusUpdate>>,
+    ) -> Self {
+        Self {
+            blind_id: blind_id.to_string(),
+            command,
+            updates,
+            response: None,
+        }
+    }
+
+    /// Acepta también la respuesta v5 del dispositivo como confirmación
+    pub fn with_response(mut self, response: Option<ExpectedResponse>) -> Self {
+        self.response = response;
+        self
+    }
+
+    pub async fn wait(mut self, deadline: Instant) -> AckStatus {
+        if self.updates.is_none() && self.response.is_none() {
+            return AckStatus::Unsupported;
+        }
+
+        while self.updates.is_some() || self.response.is_some() {
+            let messages = self
+                .response
+                .as_mut()
+                .map(|response| &mut response.messages);
+            tokio::select! {
+                _ = sleep_until(deadline) => return AckStatus::TimedOut,
+                update = recv(self.updates.as_mut()) => match update {
+                    Ok(update) if update.blind_id == self.blind_id => {
+                        if let Some(status) = evaluate(&self.command, &update) {
+                            return status;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => self.log_lagged(skipped),
+                    Err(RecvError::Closed) => self.updates = None,
+                },
+                message = recv(messages) => match message {
+                    Ok(message) => {
+                        if self.response.as_ref().is_some_and(|response| response.matches(&message)) {
+                            return evaluate_response(&message);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => self.log_lagged(skipped),
+                    Err(RecvError::Closed) => self.response = None,
+                },
+            }
+        }
+        AckStatus::TimedOut
+    }
+
+    fn log_lagged(&self, skipped: u64) {
+        log::warn!(
+            "Acknowledgement for blind '{}' lagged, {} messages skipped",
+            self.blind_id,
+            skipped
+        );
+    }
+}
+
+/// Recibe del canal, o espera indefinidamente si no hay canal
+async fn recv<T: Clone>(receiver: Option<&mut broadcast::Receiver<T>>) -> Result<T, RecvError> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Una respuesta v5 confirma el comando salvo que indique un error
+pub fn evaluate_response(message: &MqttMessage) -> AckStatus {
+    let payload = message.payload_str();
+    let state = match serde_json::from_str::<serde_json::Value>(&payload) {
+        Ok(value) if value.get("error").is_some() => return AckStatus::Rejected,
+        Ok(value) => ["state", "status"]
+            .iter()
+            .find_map(|key| value.get(key).and_then(|v| v.as_str()).map(str::to_string))
+            .unwrap_or_default(),
+        Err(_) => payload.trim().to_string(),
+    };
+
+    if REJECTED_STATES.contains(&state.to_lowercase().as_str()) {
+        AckStatus::Rejected
+    } else {
+        AckStatus::Acknowledged
+    }
+}
+
+/// Decide si un reporte confirma (o rechaza) el comando; `None` si no es concluyente
+pub fn evaluate(command: &BlindCommand, update: &StatusUpdate) -> Option<AckStatus> {
+    let state = update.state.as_deref();
+    if state.is_some_and(|state| REJECTED_STATES.contains(&state)) {
+        return Some(AckStatus::Rejected);
+    }
+
+    let near = |reported: Option<u8>, target: u8| {
+        reported.is_some_and(|reported| reported.abs_diff(target) <= ACK_TOLERANCE_PERCENT)
+    };
+    let moving = matches!(state, Some("opening" | "closing"));
+
+    let acknowledged = match command {
+        BlindCommand::Open => matches!(state, Some("open" | "opening")),
+        BlindCommand::Close => matches!(state, Some("closed" | "closing")),
+        BlindCommand::Stop => state.is_some() || update.position.is_some(),
+        BlindCommand::SetPosition(target) => moving || near(update.position, *target),
+        BlindCommand::SetTilt(target) => near(update.tilt, *target),
+        BlindCommand::OpenTilt | BlindCommand::CloseTilt => update.tilt.is_some(),
+    };
+    acknowledged.then_some(AckStatus::Acknowledged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn update(state: Option<&str>, position: Option<u8>, tilt: Option<u8>) -> StatusUpdate {
+        StatusUpdate {
+            blind_id: "b1".to_string(),
+            state: state.map(str::to_string),
+            position,
+            tilt,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let opening = update(Some("opening"), None, None);
+        assert_eq!(
+            evaluate(&BlindCommand::Open, &opening),
+            Some(AckStatus::Acknowledged)
+        );
+        assert_eq!(evaluate(&BlindCommand::Close, &opening), None);
+        assert_eq!(
+            evaluate(
+                &BlindCommand::SetPosition(50),
+                &update(None, Some(49), None)
+            ),
+            Some(AckStatus::Acknowledged)
+        );
+        assert_eq!(
+            evaluate(
+                &BlindCommand::SetPosition(50),
+                &update(None, Some(20), None)
+            ),
+            None
+        );
+        assert_eq!(
+            evaluate(&BlindCommand::SetTilt(30), &update(None, None, Some(30))),
+            Some(AckStatus::Acknowledged)
+        );
+        assert_eq!(
+            evaluate(&BlindCommand::Open, &update(Some("obstructed"), None, None)),
+            Some(AckStatus::Rejected)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_matching_report() {
+        let (sender, receiver) = broadcast::channel(8);
+        let waiter = AckWaiter::new("b1", BlindCommand::Close, Some(receiver));
+
+        let other = StatusUpdate {
+            blind_id: "b2".to_string(),
+            ..update(Some("closed"), None, None)
+        };
+        sender.send(other).unwrap();
+        sender.send(update(Some("closing"), None, None)).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(waiter.wait(deadline).await, AckStatus::Acknowledged);
+
+        let waiter = AckWaiter::new("b1", BlindCommand::Close, Some(sender.subscribe()));
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(waiter.wait(deadline).await, AckStatus::TimedOut);
+
+        let waiter = AckWaiter::new("b1", BlindCommand::Close, None);
+        assert_eq!(waiter.wait(deadline).await, AckStatus::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_v5_response() {
+        let (sender, receiver) = broadcast::channel(8);
+        let waiter =
+            AckWaiter::new("b1", BlindCommand::Open, None).with_response(Some(ExpectedResponse {
+                topic: "tabi/responses/test".to_string(),
+                correlation_data: b"req:b1".to_vec(),
+                messages: receiver,
+            }));
+
+        let response = |correlation: &[u8], payload: &str| MqttMessage {
+            topic: "tabi/responses/test".to_string(),
+            payload: payload.as_bytes().to_vec(),
+            correlation_data: Some(correlation.to_vec()),
+            ..MqttMessage::default()
+        };
+        sender.send(response(b"other:b1", "ok")).unwrap();
+        sender
+            .send(response(b"req:b1", r#"{"status":"blocked"}"#))
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(waiter.wait(deadline).await, AckStatus::Rejected);
+        assert_eq!(
+            evaluate_response(&response(b"req:b1", "ok")),
+            AckStatus::Acknowledged
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::config::AvailabilityConfig;
+use crate::errors::AppError;
+use crate::services::mqtt_service::MqttService;
+use std::time::Duration;
+
+/// Espera máxima para que el mensaje `offline` salga antes de cerrar
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Publica la disponibilidad del backend en su tema. El `offline` del Last Will
+/// lo publica el broker si la conexión se pierde sin DISCONNECT.
+#[derive(Clone)]
+pub struct Availability {
+    mqtt_service: MqttService,
+    config: AvailabilityConfig,
+}
+
+impl Availability {
+    pub fn new(mqtt_service: MqttService, config: AvailabilityConfig) -> Self {
+        Self {
+            mqtt_service,
+            config,
+        }
+    }
+
+    /// Publica `online` (retenido) tras cada ConnAck, también si la conexión ya
+    /// estaba establecida al arrancar
+    pub fn start(&self) {
+        let availability = self.clone();
+        let mut connection = self.mqtt_service.watch_connection();
+        tokio::spawn(async move {
+            loop {
+                if *connection.borrow_and_update() {
+                    availability.publish_online().await;
+                }
+                if connection.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    async fn publish_online(&self) {
+        if let Err(e) = self
+            .mqtt_service
+            .publish_retained(&self.config.topic, &self.config.online_payload)
+            .await
+        {
+            log::error!("Failed to publish availability: {}", e);
+        }
+    }
+
+    /// Publica `offline` y desconecta, esperando a que el DISCONNECT se envíe
+    pub async fn shutdown(&self) -> Result<(), AppError> {
+        if !self.mqtt_service.is_connected().await {
+            return Ok(());
+        }
+
+        let mut connection = self.mqtt_service.watch_connection();
+        self.mqtt_service
+            .publish_retained(&self.config.topic, &self.config.offline_payload)
+            .await?;
+        self.mqtt_service.disconnect().await?;
+
+        let disconnected = connection.wait_for(|connected| !connected);
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, disconnected)
+            .await
+            .is_err()
+        {
+            log::warn!(
+                "MQTT disconnect not confirmed within {:?}",
+                SHUTDOWN_TIMEOUT
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::{AsyncClient, MqttOptions};
+
+    #[tokio::test]
+    async fn test_online_when_started_after_connack() {
+        tokio::time::pause();
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let published = || async {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            mqtt_service
+                .get_client_info()
+                .await
+                .traffic
+                .messages_published
+        };
+
+        mqtt_service.set_connected(true).await;
+        Availability::new(mqtt_service.clone(), AvailabilityConfig::default()).start();
+        assert_eq!(published().await, 1);
+
+        // And again after every reconnect
+        mqtt_service.set_connected(false).await;
+        mqtt_service.set_connected(true).await;
+        assert_eq!(published().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_disconnect() {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let availability = Availability::new(mqtt_service.clone(), AvailabilityConfig::default());
+        availability.start();
+
+        // Nothing to do when the broker is not connected
+        availability.shutdown().await.unwrap();
+
+        mqtt_service.set_connected(true).await;
+        // The event loop does not run here, so report the DISCONNECT as sent
+        let service = mqtt_service.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            service.record_disconnected("Client disconnected").await;
+        });
+
+        let started = tokio::time::Instant::now();
+        availability.shutdown().await.unwrap();
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT);
+        assert!(!mqtt_service.is_connected().await);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::config::{BatteryConfig, BatteryFormat};
+use crate::models::battery::{BatterySample, BatteryTrend};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Variación mínima (en % por hora) para considerar que la batería cambia
+const TREND_THRESHOLD_PERCENT_PER_HOUR: f64 = 0.1;
+
+/// Lectura extraída de un payload de batería
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatteryReading {
+    Percent(f64),
+    Voltage(f64),
+}
+
+impl BatteryReading {
+    /// Acepta JSON (`{"battery": 85}`, `{"voltage": 3.7}` o `{"voltage": 3700}` en mV),
+    /// un porcentaje (`85`, `85%`) o un voltaje con unidad (`3.7V`, `3700mV`).
+    /// `format` decide cómo se interpreta un número sin unidad.
+    pub fn parse(payload: &str, format: BatteryFormat) -> Option<Self> {
+        let payload = payload.trim();
+
+        if let Ok(serde_json::Value::Object(object)) =
+            serde_json::from_str::<serde_json::Value>(payload)
+        {
+            let percent = ["battery", "battery_level", "percent", "percentage"]
+                .iter()
+                .find_map(|key| object.get(*key).and_then(|value| value.as_f64()));
+            if let Some(percent) = percent {
+                return Self::percent(percent);
+            }
+
+            let voltage = ["voltage", "battery_voltage"]
+                .iter()
+                .find_map(|key| object.get(*key).and_then(|value| value.as_f64()))?;
+            // Zigbee2MQTT y otros reportan el voltaje en milivoltios
+            return Self::voltage(if voltage > 100.0 {
+                voltage / 1000.0
+            } else {
+                voltage
+            });
+        }
+
+        let lower = payload.to_lowercase();
+        if let Some(millivolts) = lower.strip_suffix("mv") {
+            return Self::voltage(millivolts.trim().parse::<f64>().ok()? / 1000.0);
+        }
+        if let Some(volts) = lower.strip_suffix('v') {
+            return Self::voltage(volts.trim().parse().ok()?);
+        }
+        if let Some(percent) = lower.strip_suffix('%') {
+            return Self::percent(percent.trim().parse().ok()?);
+        }
+
+        let value: f64 = lower.parse().ok()?;
+        match format {
+            BatteryFormat::Auto | BatteryFormat::Percent => Self::percent(value),
+            BatteryFormat::Voltage => Self::voltage(value),
+            BatteryFormat::Millivolts => Self::voltage(value / 1000.0),
+        }
+    }
+
+    fn percent(value: f64) -> Option<Self> {
+        (0.0..=100.0)
+            .contains(&value)
+            .then_some(BatteryReading::Percent(value))
+    }
+
+    fn voltage(value: f64) -> Option<Self> {
+        (value.is_finite() && value > 0.0).then_some(BatteryReading::Voltage(value))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct BatteryHistory {
+    samples: VecDeque<BatterySample>,
+    low_alert_active: bool,
+}
+
+/// Resultado de registrar una lectura de batería
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedReading {
+    pub sample: BatterySample,
+    /// `true` si la lectura acaba de cruzar el umbral de batería baja
+    pub crossed_low_threshold: bool,
+}
+
+/// Historial de batería por persiana con detección de batería baja
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    histories: Arc<RwLock<HashMap<String, BatteryHistory>>>,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            histories: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &BatteryConfig {
+        &self.config
+    }
+
+    /// Guarda una lectura. Los voltajes se convierten con la curva del `device_type`.
+    pub async fn record(
+        &self,
+        blind_id: &str,
+        device_type: &str,
+        reading: BatteryReading,
+    ) -> RecordedReading {
+        let (percent, voltage) = match reading {
+            BatteryReading::Percent(percent) => (percent.round() as u8, None),
+            BatteryReading::Voltage(voltage) => (
+                self.config.voltage_to_percent(device_type, voltage),
+                Some(voltage),
+            ),
+        };
+        let sample = BatterySample {
+            percent,
+            voltage,
+            timestamp: Utc::now(),
+        };
+
+        let mut histories = self.histories.write().await;
+        let history = histories.entry(blind_id.to_string()).or_default();
+        history.samples.push_back(sample.clone());
+        while history.samples.len() > self.config.history_size.max(1) {
+            history.samples.pop_front();
+        }
+
+        let threshold = self.config.low_threshold_percent;
+        let rearm_level = threshold.saturating_add(self.config.recovery_margin_percent);
+        let mut crossed_low_threshold = false;
+        if percent < threshold && !history.low_alert_active {
+            history.low_alert_active = true;
+            crossed_low_threshold = true;
+        } else if percent >= rearm_level && history.low_alert_active {
+            history.low_alert_active = false;
+        }
+
+        RecordedReading {
+            sample,
+            crossed_low_threshold,
+        }
+    }
+
+    pub async fn history(&self, blind_id: &str) -> Vec<BatterySample> {
+        self.histories
+            .read()
+            .await
+            .get(blind_id)
+            .map(|history| history.samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Clone for BatteryMonitor {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            histories: Arc::clone(&self.histories),
+        }
+    }
+}
+
+/// Pendiente (en % por hora) de la regresión lineal sobre las lecturas
+pub fn rate_percent_per_hour(samples: &[BatterySample]) -> Option<f64> {
+    if samples.len() < 2 {
+        return None;
+    }
+
+    let origin = samples[0].timestamp;
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|sample| {
+            let hours = (sample.timestamp - origin).num_milliseconds() as f64 / 3_600_000.0;
+            (hours, sample.percent as f64)
+        })
+        .collect();
+
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+
+    if variance == 0.0 {
+        return None;
+    }
+    Some(covariance / variance)
+}
+
+pub fn trend(rate_percent_per_hour: Option<f64>) -> BatteryTrend {
+    match rate_percent_per_hour {
+        None => BatteryTrend::Unknown,
+        Some(rate) if rate > TREND_THRESHOLD_PERCENT_PER_HOUR => BatteryTrend::Charging,
+        Some(rate) if rate < -TREND_THRESHOLD_PERCENT_PER_HOUR => BatteryTrend::Discharging,
+        Some(_) => BatteryTrend::Stable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_parse_battery_reading() {
+        assert_eq!(
+            BatteryReading::parse("85", BatteryFormat::Auto),
+            Some(BatteryReading::Percent(85.0))
+        );
+        assert_eq!(
+            BatteryReading::parse("85 %", BatteryFormat::Auto),
+            Some(BatteryReading::Percent(85.0))
+        );
+        assert_eq!(
+            BatteryReading::parse("3.7V", BatteryFormat::Auto),
+            Some(BatteryReading::Voltage(3.7))
+        );
+        assert_eq!(
+            BatteryReading::parse("3700mV", BatteryFormat::Auto),
+            Some(BatteryReading::Voltage(3.7))
+        );
+        assert_eq!(
+            BatteryReading::parse(r#"{"battery": 40, "voltage": 3000}"#, BatteryFormat::Auto),
+            Some(BatteryReading::Percent(40.0))
+        );
+        assert_eq!(
+            BatteryReading::parse(r#"{"voltage": 3000}"#, BatteryFormat::Auto),
+            Some(BatteryReading::Voltage(3.0))
+        );
+        assert_eq!(BatteryReading::parse("120", BatteryFormat::Auto), None);
+        assert_eq!(
+            BatteryReading::parse("3650", BatteryFormat::Millivolts),
+            Some(BatteryReading::Voltage(3.65))
+        );
+        assert_eq!(
+            BatteryReading::parse("3.65", BatteryFormat::Voltage),
+            Some(BatteryReading::Voltage(3.65))
+        );
+        assert_eq!(BatteryReading::parse("low", BatteryFormat::Auto), None);
+        assert_eq!(
+            BatteryReading::parse(r#"{"linkquality": 90}"#, BatteryFormat::Auto),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_low_battery_crossing_and_rearm() {
+        let monitor = BatteryMonitor::new(BatteryConfig::default());
+
+        let reading = monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(50.0))
+            .await;
+        assert!(!reading.crossed_low_threshold);
+
+        let reading = monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(19.0))
+            .await;
+        assert!(reading.crossed_low_threshold);
+
+        // Sigue baja: no se repite el evento
+        let reading = monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(18.0))
+            .await;
+        assert!(!reading.crossed_low_threshold);
+
+        // Por encima del umbral pero dentro del margen: la alerta sigue activa
+        monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(22.0))
+            .await;
+        let reading = monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(19.0))
+            .await;
+        assert!(!reading.crossed_low_threshold);
+
+        // Recarga completa: se rearma
+        monitor
+            .record("b1", "motorized_blind", BatteryReading::Percent(90.0))
+            .await;
+        let reading = monitor
+            .record("b1", "motorized_blind", BatteryReading::Voltage(3.1))
+            .await;
+        assert!(reading.crossed_low_threshold);
+        assert_eq!(reading.sample.voltage, Some(3.1));
+    }
+
+    #[tokio::test]
+    async fn test_history_is_bounded() {
+        let monitor = BatteryMonitor::new(BatteryConfig {
+            history_size: 3,
+            ..BatteryConfig::default()
+        });
+
+        for percent in [90.0, 80.0, 70.0, 60.0] {
+            monitor
+                .record("b1", "motorized_blind", BatteryReading::Percent(percent))
+                .await;
+        }
+
+        let history = monitor.history("b1").await;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].percent, 80);
+        assert!(monitor.history("b2").await.is_empty());
+    }
+
+    #[test]
+    fn test_trend() {
+        let now = Utc::now();
+        let samples: Vec<BatterySample> = [100, 98, 96]
+            .iter()
+            .enumerate()
+            .map(|(hour, percent)| BatterySample {
+                percent: *percent,
+                voltage: None,
+                timestamp: now + Duration::hours(hour as i64),
+            })
+            .collect();
+
+        let rate = rate_percent_per_hour(&samples).unwrap();
+        assert!((rate + 2.0).abs() < 1e-9);
+        assert_eq!(trend(Some(rate)), BatteryTrend::Discharging);
+        assert_eq!(trend(Some(0.0)), BatteryTrend::Stable);
+        assert_eq!(
+            trend(rate_percent_per_hour(&samples[..1])),
+            BatteryTrend::Unknown
+        );
+    }
+}
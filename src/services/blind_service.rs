@@ -1,31 +1,647 @@
-use crate::config::{AppConfig, BlindConfig};
+use crate::config::MqttProtocolVersion;
+use crate::config::{BlindConfig, PayloadFormat, PublishOptions, SharedConfig};
 use crate::errors::AppError;
-use crate::models::blind::{BlindCommand, BlindStatus, RoomInfo};
+use crate::models::battery::LowBatteryEvent;
+use crate::models::blind::{BlindCommand, BlindState, BlindStatus, RoomInfo};
 use crate::models::responses::{
-    BatchControlResponse, BlindControlResponse, ConfigResponse, MqttConfigResponse, RoomsResponse,
+    BatchControlResponse, BatchHandleResponse, BatchProgressResponse, BatteryResponse,
+    BlindControlResponse, ConfigResponse, MqttConfigResponse, MqttInfoResponse, RoomsResponse,
     ServerConfigResponse, SystemStatusResponse,
 };
-use crate::services::mqtt_service::MqttService;
+use crate::services::acknowledgement::{AckWaiter, ExpectedResponse};
+use crate::services::battery_monitor::{self, BatteryMonitor, BatteryReading};
+use crate::services::motor_protection::MotorGuard;
+use crate::services::mqtt_inspector::{TrafficEntry, TrafficFilter};
+use crate::services::mqtt_service::{CommandProperties, MqttMessage, MqttService};
+use crate::services::outbox::{Delivery, Outbox, OutboxEntry};
+use crate::services::payload_encoder::{encoder_for, EncodedCommand};
+use crate::services::position_estimator::{
+    self, Direction, MovePlan, PositionEstimator, TravelTimes,
+};
+use crate::services::staggered_batch::{
+    move_duration, BatchCancellation, BatchOutcome, BatchTracker, PowerCircuits,
+};
+use crate::services::state_store::BlindStateStore;
+use futures::stream::{self, StreamExt};
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::sync::Arc;
 use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Emisor de los comandos enviados por la API HTTP
+const HTTP_ISSUER: &str = "api";
 
 pub struct BlindService {
     mqtt_service: MqttService,
-    config: Arc<AppConfig>,
+    config: SharedConfig,
+    state_store: BlindStateStore,
+    battery_monitor: BatteryMonitor,
+    position_estimator: PositionEstimator,
     start_time: Instant,
+    /// Modo confirmado: espera el reporte del dispositivo tras cada comando
+    confirm: bool,
+    /// Cola persistente para comandos enviados sin conexión con el broker
+    outbox: Option<Outbox>,
+    /// Origen de los comandos (propiedad `issuer` en MQTT v5)
+    issuer: &'static str,
+    /// Progreso de los lotes escalonados
+    batches: BatchTracker,
+    power_circuits: PowerCircuits,
+    /// Límites de movimientos por persiana (`protection` del perfil)
+    motor_guard: MotorGuard,
 }
 
 impl BlindService {
-    pub fn new(mqtt_service: MqttService, config: Arc<AppConfig>) -> Self {
+    pub fn new(mqtt_service: MqttService, config: impl Into<SharedConfig>) -> Self {
+        let config = config.into();
         Self {
             mqtt_service,
+            battery_monitor: BatteryMonitor::new(config.snapshot().battery.clone()),
             config,
+            state_store: BlindStateStore::new(),
+            position_estimator: PositionEstimator::new(),
             start_time: Instant::now(),
+            confirm: false,
+            outbox: None,
+            issuer: HTTP_ISSUER,
+            batches: BatchTracker::new(),
+            power_circuits: PowerCircuits::new(),
+            motor_guard: MotorGuard::new(),
+        }
+    }
+
+    /// Guarda en `outbox` los comandos enviados mientras el broker no está disponible.
+    /// Los que se envían al vaciarla se registran entonces en el estado, la
+    /// estimación de posición y la protección del motor.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        let mut sent = outbox.subscribe_sent();
+        self.outbox = Some(outbox);
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match sent.recv().await {
+                    Ok(entry) => service.record_flushed(&entry).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!(
+                            "Outbox tracking lagged, {} sent commands not recorded",
+                            skipped
+                        );
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        self
+    }
+
+    /// Registra un comando de la cola que acaba de llegar al broker, igual que
+    /// si se hubiera enviado al momento
+    async fn record_flushed(&self, entry: &OutboxEntry) {
+        let config = self.config.snapshot();
+        let (Some(blind), Some(command)) = (
+            config
+                .blinds
+                .iter()
+                .find(|blind| blind.id == entry.blind_id),
+            entry.blind_command(),
+        ) else {
+            return;
+        };
+        let profile = config.device_profile(&blind.device_type);
+        let moves = command != BlindCommand::Stop && !command.is_tilt();
+        let direction = if moves {
+            self.move_direction(blind, &command).await
+        } else {
+            None
+        };
+
+        if let (Some(travel), false) = (blind.travel_times(), command.is_tilt()) {
+            let _sending = self.position_estimator.lock(&blind.id).await;
+            let now = position_estimator::now();
+            match direction {
+                Some(direction) => {
+                    self.position_estimator
+                        .start(&blind.id, direction, travel.into(), now)
+                        .await;
+                }
+                None => {
+                    self.position_estimator.stop(&blind.id, now).await;
+                }
+            }
+        }
+        self.state_store.record_command(&blind.id, &command).await;
+        if let (Some(_), Some(direction)) = (&profile.protection, direction) {
+            self.motor_guard
+                .record(&blind.id, direction, chrono::Utc::now())
+                .await;
+        }
+    }
+
+    /// Copia del servicio (comparte estado) con el modo confirmado activado o no
+    pub fn with_confirmation(&self, confirm: bool) -> Self {
+        Self {
+            confirm,
+            ..self.clone()
+        }
+    }
+
+    /// Copia del servicio (comparte estado) que firma los comandos con otro emisor
+    pub fn with_issuer(&self, issuer: &'static str) -> Self {
+        Self {
+            issuer,
+            ..self.clone()
+        }
+    }
+
+    /// Suscribe a los temas de estado y batería de las persianas habilitadas y
+    /// lanza la tarea que procesa los mensajes recibidos
+    pub async fn start_telemetry_ingestion(&self) -> Result<(), AppError> {
+        // Register the receiver before subscribing so no retained message is missed
+        let mut receiver = self.mqtt_service.subscribe_messages();
+        self.subscribe_telemetry_topics().await?;
+
+        // Blinds added or enabled at runtime get their topics subscribed too
+        let service = self.clone();
+        let mut config_changes = self.config.subscribe();
+        tokio::spawn(async move {
+            while config_changes.changed().await.is_ok() {
+                if let Err(e) = service.subscribe_telemetry_topics().await {
+                    log::error!("Failed to subscribe to new telemetry topics: {}", e);
+                }
+            }
+        });
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => service.handle_message(&message).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Status ingestion lagged, {} MQTT messages skipped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn subscribe_telemetry_topics(&self) -> Result<(), AppError> {
+        let config = self.config.snapshot();
+        let subscribed = self.mqtt_service.get_subscriptions().await;
+
+        for blind in config.get_enabled_blinds() {
+            // Telemetry uses the same QoS as the blind's commands
+            let qos = config.publish_options(blind).qos;
+            let tilt_status_topic = blind
+                .tilt
+                .as_ref()
+                .and_then(|tilt| tilt.status_topic.clone());
+            for topic in [
+                &blind.status_topic,
+                &blind.battery_topic,
+                &tilt_status_topic,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if !subscribed.contains(topic) {
+                    self.mqtt_service.subscribe_with_qos(topic, qos).await?;
+                }
+            }
+        }
+
+        // MQTT v5 devices answer commands on the response topic
+        let response_topic = config.mqtt.response_topic();
+        if self.mqtt_service.protocol_version() == MqttProtocolVersion::V5
+            && !subscribed.contains(&response_topic)
+        {
+            self.mqtt_service
+                .subscribe_to_topic(&response_topic)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Procesa un mensaje MQTT entrante dirigido a alguna persiana
+    pub async fn handle_message(&self, message: &MqttMessage) {
+        let config = self.config.snapshot();
+        let topic = Some(message.topic.as_str());
+        let payload = message.payload_str();
+
+        for blind in config.get_enabled_blinds() {
+            if blind.status_topic.as_deref() == topic {
+                let format = config.device_profile(&blind.device_type).status_format;
+                self.state_store
+                    .apply_status_report(&blind.id, &payload, format)
+                    .await;
+            }
+            if blind
+                .tilt
+                .as_ref()
+                .and_then(|tilt| tilt.status_topic.as_deref())
+                == topic
+            {
+                self.state_store
+                    .apply_tilt_report(&blind.id, &payload)
+                    .await;
+            }
+            if blind.battery_topic.as_deref() == topic {
+                self.handle_battery_report(blind, &payload).await;
+            }
+        }
+    }
+
+    async fn handle_battery_report(&self, blind: &BlindConfig, payload: &str) {
+        let config = self.config.snapshot();
+        let format = config.device_profile(&blind.device_type).battery_format;
+        let Some(reading) = BatteryReading::parse(payload, format) else {
+            log::warn!(
+                "Unrecognised battery payload for blind '{}': {}",
+                blind.id,
+                payload
+            );
+            return;
+        };
+
+        let recorded = self
+            .battery_monitor
+            .record(&blind.id, &blind.device_type, reading)
+            .await;
+        if !recorded.crossed_low_threshold {
+            return;
+        }
+
+        let event = LowBatteryEvent {
+            event: "low_battery".to_string(),
+            blind_id: blind.id.clone(),
+            blind_name: blind.name.clone(),
+            room: blind.room.clone(),
+            percent: recorded.sample.percent,
+            threshold_percent: self.battery_monitor.config().low_threshold_percent,
+            timestamp: recorded.sample.timestamp,
+        };
+        log::warn!(
+            "Low battery on blind '{}' ({}): {}%",
+            blind.id,
+            blind.name,
+            event.percent
+        );
+
+        if let Some(alert_topic) = &self.battery_monitor.config().alert_topic {
+            let result = match serde_json::to_string(&event) {
+                Ok(payload) => {
+                    self.mqtt_service
+                        .publish_command(alert_topic, &payload)
+                        .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to publish low battery event: {}", e);
+            }
+        }
+    }
+
+    pub async fn get_battery_status(&self, blind_id: &str) -> Result<BatteryResponse, AppError> {
+        let blind = self.validate_blind_id(blind_id)?;
+        let battery_topic = blind.battery_topic.clone().ok_or_else(|| {
+            AppError::ValidationError(format!("Blind '{}' has no battery_topic", blind_id))
+        })?;
+
+        let history = self.battery_monitor.history(&blind.id).await;
+        let rate = battery_monitor::rate_percent_per_hour(&history);
+        let latest = history.last();
+        let threshold = self.battery_monitor.config().low_threshold_percent;
+
+        Ok(BatteryResponse {
+            blind_id: blind.id.clone(),
+            blind_name: blind.name.clone(),
+            battery_topic,
+            percent: latest.map(|sample| sample.percent),
+            voltage: latest.and_then(|sample| sample.voltage),
+            last_update: latest.map(|sample| sample.timestamp),
+            trend: battery_monitor::trend(rate),
+            rate_percent_per_hour: rate,
+            low_battery: latest.is_some_and(|sample| sample.percent < threshold),
+            low_threshold_percent: threshold,
+            history,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+
+    /// Publica el comando en el tema de la persiana y lo registra en el estado
+    /// Devuelve el tema utilizado y si se envió o quedó en la cola.
+    async fn send_command(
+        &self,
+        blind: &BlindConfig,
+        command: &BlindCommand,
+        properties: CommandProperties,
+    ) -> Result<(String, Delivery), AppError> {
+        let config = self.config.snapshot();
+        let profile = config.device_profile(&blind.device_type);
+        if !profile.capabilities(blind).supports(command) {
+            return Err(AppError::UnsupportedCommand(
+                blind.id.clone(),
+                command.as_str().to_string(),
+            ));
+        }
+        // STOP, slat tilting and moves to the current position never overheat the motor
+        let mut reservation = None;
+        if let Some(protection) = &profile.protection {
+            if *command != BlindCommand::Stop && !command.is_tilt() {
+                if let Some(direction) = self.move_direction(blind, command).await {
+                    reservation = Some(
+                        self.motor_guard
+                            .reserve(&blind.id, direction, protection, chrono::Utc::now())
+                            .await?,
+                    );
+                }
+            }
+        }
+        let payload_format = profile.payload_format(blind);
+        let options = config.publish_options(blind);
+
+        let result = match blind.travel_times() {
+            Some(travel) if !command.is_tilt() => {
+                self.send_estimated_command(
+                    blind,
+                    command,
+                    travel.into(),
+                    payload_format,
+                    options,
+                    properties,
+                )
+                .await
+            }
+            _ => {
+                self.send_plain_command(blind, command, payload_format, options, properties)
+                    .await
+            }
+        };
+        // Only a move that reached the broker counts against the limits; a queued
+        // one is recorded when the outbox flushes it
+        if let Some(reservation) = reservation {
+            if !matches!(result, Ok((_, Delivery::Sent))) {
+                self.motor_guard.release(&blind.id, reservation).await;
+            }
+        }
+        result
+    }
+
+    /// Publica el comando tal cual y lo registra en el estado si se envió
+    async fn send_plain_command(
+        &self,
+        blind: &BlindConfig,
+        command: &BlindCommand,
+        payload_format: PayloadFormat,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<(String, Delivery), AppError> {
+        let encoded = Self::encode_command(blind, command, payload_format)?;
+        let delivery = self
+            .deliver(&blind.id, command, &encoded, options, properties)
+            .await?;
+        if delivery == Delivery::Sent {
+            self.state_store.record_command(&blind.id, command).await;
+        }
+        Ok((encoded.topic, delivery))
+    }
+
+    /// Sentido en que el comando mueve la persiana, si se conoce
+    async fn move_direction(
+        &self,
+        blind: &BlindConfig,
+        command: &BlindCommand,
+    ) -> Option<Direction> {
+        let target = match command {
+            BlindCommand::Open => return Some(Direction::Opening),
+            BlindCommand::Close => return Some(Direction::Closing),
+            BlindCommand::SetPosition(target) => f64::from(*target),
+            _ => return None,
+        };
+        let current = if blind.travel_times().is_some() {
+            self.position_estimator
+                .position(&blind.id, position_estimator::now())
+                .await
+        } else {
+            self.state_store
+                .snapshot()
+                .await
+                .get(&blind.id)
+                .and_then(|state| state.position)
+                .map(f64::from)
+        };
+        // Fully open or closed is reached in that direction from anywhere
+        let current = current.unwrap_or(100.0 - target);
+        match target.partial_cmp(&current)? {
+            std::cmp::Ordering::Greater => Some(Direction::Opening),
+            std::cmp::Ordering::Less => Some(Direction::Closing),
+            std::cmp::Ordering::Equal => None,
         }
     }
 
+    /// Envía un comando a un motor sin reporte de posición: `SetPosition` se emula
+    /// con OPEN/CLOSE y un STOP programado según los tiempos de recorrido
+    async fn send_estimated_command(
+        &self,
+        blind: &BlindConfig,
+        command: &BlindCommand,
+        travel: TravelTimes,
+        payload_format: PayloadFormat,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<(String, Delivery), AppError> {
+        let _sending = self.position_estimator.lock(&blind.id).await;
+        let now = position_estimator::now();
+        let plan = match command {
+            BlindCommand::Open => MovePlan::full(Direction::Opening),
+            BlindCommand::Close => MovePlan::full(Direction::Closing),
+            BlindCommand::SetPosition(target) => {
+                let current = self.position_estimator.position(&blind.id, now).await;
+                MovePlan::to_position(current, *target, travel).ok_or_else(|| {
+                    AppError::ValidationError(format!(
+                        "Position of blind '{}' is unknown; send OPEN or CLOSE first",
+                        blind.id
+                    ))
+                })?
+            }
+            _ => MovePlan::stop(),
+        };
+
+        let wire_command = match plan.direction {
+            Some(Direction::Opening) => BlindCommand::Open,
+            Some(Direction::Closing) => BlindCommand::Close,
+            None => BlindCommand::Stop,
+        };
+        let encoded = Self::encode_command(blind, &wire_command, payload_format)?;
+        let delivery = match (plan.stop_after, &self.outbox) {
+            // A partial move is useless without its timed STOP, so it is never queued
+            (Some(_), Some(outbox)) => {
+                if !self.mqtt_service.is_connected().await {
+                    return Err(AppError::MqttError(format!(
+                        "Broker unavailable, partial move of blind '{}' not queued",
+                        blind.id
+                    )));
+                }
+                outbox.discard(&blind.id).await;
+                self.publish_now(&encoded, options, properties.clone())
+                    .await?
+            }
+            // Queued under the requested command, so the flush can record it
+            _ => {
+                self.deliver(&blind.id, command, &encoded, options, properties.clone())
+                    .await?
+            }
+        };
+        // A queued command has not moved the motor yet
+        if delivery == Delivery::Queued {
+            return Ok((encoded.topic, delivery));
+        }
+
+        let generation = match plan.direction {
+            Some(direction) => {
+                self.position_estimator
+                    .start(&blind.id, direction, travel, now)
+                    .await
+            }
+            None => self.position_estimator.stop(&blind.id, now).await,
+        };
+        self.state_store.record_command(&blind.id, command).await;
+
+        if let Some(delay) = plan.stop_after {
+            let stop = Self::encode_command(blind, &BlindCommand::Stop, payload_format)?;
+            self.schedule_stop(
+                blind.id.clone(),
+                stop,
+                options,
+                properties,
+                generation,
+                delay,
+            );
+        }
+        Ok((encoded.topic, delivery))
+    }
+
+    /// Publica un comando. Con cola, el comando se guarda antes de publicarlo y
+    /// se reenvía al reconectar si el broker no lo llega a aceptar.
+    async fn deliver(
+        &self,
+        blind_id: &str,
+        command: &BlindCommand,
+        encoded: &EncodedCommand,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<Delivery, AppError> {
+        match &self.outbox {
+            Some(outbox) => {
+                outbox
+                    .deliver(
+                        blind_id,
+                        command,
+                        &encoded.topic,
+                        &encoded.payload,
+                        options,
+                        properties,
+                    )
+                    .await
+            }
+            None => self.publish_now(encoded, options, properties).await,
+        }
+    }
+
+    /// Publica un comando sin pasar por la cola
+    async fn publish_now(
+        &self,
+        encoded: &EncodedCommand,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<Delivery, AppError> {
+        self.mqtt_service
+            .publish_command_with_properties(&encoded.topic, &encoded.payload, options, properties)
+            .await?;
+        Ok(Delivery::Sent)
+    }
+
+    /// Comandos en cola a la espera de conexión con el broker
+    pub async fn get_outbox(&self) -> Vec<OutboxEntry> {
+        match &self.outbox {
+            Some(outbox) => outbox.entries().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Últimos mensajes MQTT de las persianas que cumplen el filtro
+    pub async fn get_mqtt_messages(&self, filter: &TrafficFilter) -> Vec<TrafficEntry> {
+        match self.mqtt_service.inspector() {
+            Some(inspector) => inspector.entries(filter).await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Envía STOP tras `delay` salvo que entretanto se haya enviado otro comando
+    fn schedule_stop(
+        &self,
+        blind_id: String,
+        stop: EncodedCommand,
+        options: PublishOptions,
+        properties: CommandProperties,
+        generation: u64,
+        delay: std::time::Duration,
+    ) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _sending = service.position_estimator.lock(&blind_id).await;
+            if !service
+                .position_estimator
+                .is_current(&blind_id, generation)
+                .await
+            {
+                return;
+            }
+
+            // Like the partial move it ends, the timed STOP is never queued
+            if let Err(e) = service.publish_now(&stop, options, properties).await {
+                log::error!("Failed to send timed STOP to blind '{}': {}", blind_id, e);
+            }
+            service
+                .position_estimator
+                .stop(&blind_id, position_estimator::now())
+                .await;
+        });
+    }
+
+    /// Resuelve el tema y el payload de un comando para una persiana concreta
+    fn encode_command(
+        blind: &BlindConfig,
+        command: &BlindCommand,
+        payload_format: PayloadFormat,
+    ) -> Result<EncodedCommand, AppError> {
+        let unsupported =
+            || AppError::UnsupportedCommand(blind.id.clone(), command.as_str().to_string());
+
+        if !command.is_tilt() {
+            return encoder_for(payload_format)
+                .encode(&blind.mqtt_topic, command)
+                .ok_or_else(unsupported);
+        }
+
+        let (Some(tilt), Some(topic)) = (&blind.tilt, blind.tilt_command_topic()) else {
+            return Err(unsupported());
+        };
+        let tilt_command = match command {
+            BlindCommand::OpenTilt => BlindCommand::SetTilt(tilt.open_value),
+            BlindCommand::CloseTilt => BlindCommand::SetTilt(tilt.closed_value),
+            other => other.clone(),
+        };
+        encoder_for(tilt.payload_format)
+            .encode(topic, &tilt_command)
+            .ok_or_else(unsupported)
+    }
+
     pub async fn control_blind_by_id(
         &self,
         blind_id: &str,
@@ -33,22 +649,53 @@ impl BlindService {
     ) -> Result<BlindControlResponse, AppError> {
         // Parse and validate action
         let command = BlindCommand::from_str(action)?;
+        self.control_blind(blind_id, command).await
+    }
+
+    pub async fn set_position_by_id(
+        &self,
+        blind_id: &str,
+        percent: &str,
+    ) -> Result<BlindControlResponse, AppError> {
+        let command = BlindCommand::set_position(percent)?;
+        self.control_blind(blind_id, command).await
+    }
+
+    pub async fn set_tilt_by_id(
+        &self,
+        blind_id: &str,
+        value: &str,
+    ) -> Result<BlindControlResponse, AppError> {
+        let command = BlindCommand::tilt(value)?;
+        self.control_blind(blind_id, command).await
+    }
+
+    pub async fn control_blind(
+        &self,
+        blind_id: &str,
+        command: BlindCommand,
+    ) -> Result<BlindControlResponse, AppError> {
+        command.validate()?;
 
         // Find blind configuration
-        let blind = self
-            .config
-            .get_blind_by_id(blind_id)
-            .ok_or_else(|| AppError::BlindNotFound(blind_id.to_string()))?;
+        let blind = &self.validate_blind_id(blind_id)?;
 
         // Check if blind is enabled
         if !blind.enabled {
             return Err(AppError::BlindDisabled(blind_id.to_string()));
         }
 
-        // Send MQTT command
-        self.mqtt_service
-            .publish_command(&blind.mqtt_topic, command.as_str())
-            .await?;
+        // Send MQTT command, then wait for the device if confirmation was requested
+        let properties = self.command_properties(&new_request_id(), blind);
+        let waiter = self.ack_waiter(blind, &command, &properties);
+        let (topic, delivery) = self.send_command(blind, &command, properties).await?;
+        // A queued command cannot be acknowledged until the broker is back
+        let acknowledgement = match waiter {
+            Some(waiter) if delivery == Delivery::Sent => {
+                Some(waiter.wait(self.ack_deadline()).await)
+            }
+            _ => None,
+        };
 
         // Create response
         Ok(BlindControlResponse::new(
@@ -56,83 +703,337 @@ impl BlindService {
             blind.name.clone(),
             blind.room.clone(),
             command,
-            blind.mqtt_topic.clone(),
-        ))
+            topic,
+        )
+        .with_acknowledgement(acknowledgement)
+        .with_queued(delivery == Delivery::Queued))
     }
 
     pub async fn control_blinds_by_room(
         &self,
         room: &str,
         action: &str,
-    ) -> Result<BatchControlResponse, AppError> {
+    ) -> Result<BatchOutcome, AppError> {
         let command = BlindCommand::from_str(action)?;
-        let room_blinds = self.config.get_blinds_by_room(room);
+        self.control_room(room, command).await
+    }
 
-        if room_blinds.is_empty() {
-            return Err(AppError::RoomNotFound(room.to_string()));
-        }
+    pub async fn set_position_by_room(
+        &self,
+        room: &str,
+        percent: &str,
+    ) -> Result<BatchOutcome, AppError> {
+        let command = BlindCommand::set_position(percent)?;
+        self.control_room(room, command).await
+    }
 
-        let mut response = BatchControlResponse::new(command.clone(), room.to_string());
+    pub async fn set_tilt_by_room(
+        &self,
+        room: &str,
+        value: &str,
+    ) -> Result<BatchOutcome, AppError> {
+        let command = BlindCommand::tilt(value)?;
+        self.control_room(room, command).await
+    }
 
-        // Send commands to all blinds in the room
-        for blind in room_blinds {
-            match self
-                .mqtt_service
-                .publish_command(&blind.mqtt_topic, command.as_str())
-                .await
-            {
-                Ok(_) => {
-                    response.add_success(
-                        blind.id.clone(),
-                        blind.name.clone(),
-                        blind.mqtt_topic.clone(),
-                    );
-                }
-                Err(e) => {
-                    response.add_failure(blind.id.clone(), blind.name.clone(), e.to_string());
-                }
-            }
-        }
+    pub async fn control_room(
+        &self,
+        room: &str,
+        command: BlindCommand,
+    ) -> Result<BatchOutcome, AppError> {
+        command.validate()?;
+        let room_blinds = self.validate_room(room)?;
 
-        Ok(response)
+        // Send commands to all blinds in the room
+        Ok(self
+            .execute_batch(room_blinds.iter().collect(), command, room.to_string())
+            .await)
     }
 
-    pub async fn control_all_blinds(&self, action: &str) -> Result<BatchControlResponse, AppError> {
+    pub async fn control_all_blinds(&self, action: &str) -> Result<BatchOutcome, AppError> {
         let command = BlindCommand::from_str(action)?;
-        let all_blinds = self.config.get_enabled_blinds();
+        self.control_all(command).await
+    }
+
+    pub async fn set_position_all(&self, percent: &str) -> Result<BatchOutcome, AppError> {
+        let command = BlindCommand::set_position(percent)?;
+        self.control_all(command).await
+    }
+
+    pub async fn set_tilt_all(&self, value: &str) -> Result<BatchOutcome, AppError> {
+        let command = BlindCommand::tilt(value)?;
+        self.control_all(command).await
+    }
+
+    pub async fn control_all(&self, command: BlindCommand) -> Result<BatchOutcome, AppError> {
+        let config = self.config.snapshot();
+        command.validate()?;
+        let all_blinds = config.get_enabled_blinds();
 
         if all_blinds.is_empty() {
             return Err(AppError::ConfigError("No enabled blinds found".to_string()));
         }
 
-        let mut response = BatchControlResponse::new(command.clone(), "all".to_string());
-
         // Send commands to all enabled blinds
-        for blind in all_blinds {
-            match self
-                .mqtt_service
-                .publish_command(&blind.mqtt_topic, command.as_str())
+        Ok(self
+            .execute_batch(all_blinds, command, "all".to_string())
+            .await)
+    }
+
+    /// Ejecuta el comando en cada persiana. Si el lote se escalona (ver
+    /// `batch.stagger_delay_ms` y `batch.power_circuits`) se ejecuta en segundo
+    /// plano y se devuelve el identificador para consultar su progreso.
+    async fn execute_batch(
+        &self,
+        blinds: Vec<&BlindConfig>,
+        command: BlindCommand,
+        target: String,
+    ) -> BatchOutcome {
+        // STOP must reach every motor at once, and no batch may start them again
+        if command == BlindCommand::Stop {
+            let blind_ids: Vec<_> = blinds.iter().map(|blind| blind.id.as_str()).collect();
+            self.batches.cancel_including(&blind_ids).await;
+        }
+        let config = self.config.snapshot();
+        if command != BlindCommand::Stop && config.batch.is_staggered(&blinds) {
+            let blinds = blinds.into_iter().cloned().collect();
+            return BatchOutcome::Started(
+                self.start_staggered_batch(blinds, command, target).await,
+            );
+        }
+        BatchOutcome::Completed(self.execute_concurrent_batch(blinds, command, target).await)
+    }
+
+    async fn execute_concurrent_batch(
+        &self,
+        blinds: Vec<&BlindConfig>,
+        command: BlindCommand,
+        target: String,
+    ) -> BatchControlResponse {
+        let mut response = BatchControlResponse::new(command.clone(), target);
+        let mut waiters = Vec::new();
+        let request_id = new_request_id();
+        let concurrency = self.config.snapshot().batch.concurrency.max(1);
+
+        // Collected up front so the batch future stays `Send` when spawned
+        let sends: Vec<_> = blinds
+            .into_iter()
+            .map(|blind| {
+                let (command, request_id) = (&command, &request_id);
+                async move {
+                    let properties = self.command_properties(request_id, blind);
+                    let waiter = self.ack_waiter(blind, command, &properties);
+                    let result = self.send_command(blind, command, properties).await;
+                    (blind, result, waiter)
+                }
+            })
+            .collect();
+        // `buffered` keeps the results in the order of `blinds`
+        let outcomes: Vec<_> = stream::iter(sends).buffered(concurrency).collect().await;
+
+        for (blind, result, waiter) in outcomes {
+            if let Some(index) = record_batch_result(&mut response, blind, result) {
+                waiters.extend(waiter.map(|waiter| (index, waiter)));
+            }
+        }
+
+        // Every blind shares the same deadline, so waiting in order adds no delay
+        let deadline = self.ack_deadline();
+        for (index, waiter) in waiters {
+            response.results[index].acknowledgement = Some(waiter.wait(deadline).await);
+        }
+
+        response
+    }
+
+    /// Registra el lote y lo lanza en segundo plano
+    async fn start_staggered_batch(
+        &self,
+        blinds: Vec<BlindConfig>,
+        command: BlindCommand,
+        target: String,
+    ) -> BatchHandleResponse {
+        let batch_id = new_request_id();
+        let response = BatchControlResponse::new(command.clone(), target.clone());
+        let cancellation = self.batches.start(&batch_id, &blinds, response).await;
+
+        let handle = BatchHandleResponse {
+            batch_id: batch_id.clone(),
+            status_url: format!("/blinds/batches/{}", batch_id),
+            command: command.as_str().to_string(),
+            target,
+            total_blinds: blinds.len(),
+            timestamp: chrono::Utc::now(),
+        };
+        let service = self.clone();
+        tokio::spawn(async move {
+            service
+                .run_staggered_batch(&batch_id, blinds, command, cancellation)
                 .await
-            {
-                Ok(_) => {
-                    response.add_success(
-                        blind.id.clone(),
-                        blind.name.clone(),
-                        blind.mqtt_topic.clone(),
-                    );
+        });
+        handle
+    }
+
+    /// Arranca las persianas en orden, esperando `stagger_delay_ms` entre una y
+    /// otra y a que haya plaza en su circuito eléctrico. Si se cancela, las
+    /// persianas pendientes quedan como canceladas.
+    async fn run_staggered_batch(
+        &self,
+        batch_id: &str,
+        blinds: Vec<BlindConfig>,
+        command: BlindCommand,
+        cancellation: BatchCancellation,
+    ) {
+        let config = self.config.snapshot();
+        let delay = std::time::Duration::from_millis(config.batch.stagger_delay_ms);
+        let mut acknowledgements = Vec::new();
+
+        for (position, blind) in blinds.iter().enumerate() {
+            let circuit = config.batch.power_circuit(blind);
+            let ready = async {
+                if position > 0 && !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
                 }
-                Err(e) => {
-                    response.add_failure(blind.id.clone(), blind.name.clone(), e.to_string());
+                match circuit {
+                    Some((name, circuit)) => {
+                        Some(self.power_circuits.acquire(name, circuit.max_moving).await)
+                    }
+                    None => None,
                 }
+            };
+            let permit = tokio::select! {
+                biased;
+                _ = cancellation.cancelled() => None,
+                permit = ready => permit,
+            };
+            if cancellation.is_cancelled() {
+                self.batches
+                    .update(batch_id, |progress| {
+                        for blind in &blinds[position..] {
+                            progress
+                                .result
+                                .add_cancelled(blind.id.clone(), blind.name.clone());
+                        }
+                    })
+                    .await;
+                break;
+            }
+
+            let properties = self.command_properties(batch_id, blind);
+            let waiter = self.ack_waiter(blind, &command, &properties);
+            let result = self.send_command(blind, &command, properties).await;
+
+            // The motor keeps its slot on the circuit while it moves
+            if let (Some(permit), Some((_, circuit)), Ok(_)) = (permit, circuit, &result) {
+                let moving = move_duration(blind, &command, circuit);
+                tokio::spawn(async move {
+                    tokio::time::sleep(moving).await;
+                    drop(permit);
+                });
+            }
+
+            let index = self
+                .batches
+                .update(batch_id, |progress| {
+                    progress.dispatched += 1;
+                    record_batch_result(&mut progress.result, blind, result)
+                })
+                .await
+                .flatten();
+            if let (Some(index), Some(waiter)) = (index, waiter) {
+                let (service, batch_id) = (self.clone(), batch_id.to_string());
+                let deadline = self.ack_deadline();
+                acknowledgements.push(tokio::spawn(async move {
+                    let acknowledgement = waiter.wait(deadline).await;
+                    service
+                        .batches
+                        .update(&batch_id, |progress| {
+                            progress.result.results[index].acknowledgement = Some(acknowledgement)
+                        })
+                        .await;
+                }));
             }
         }
 
-        Ok(response)
+        for acknowledgement in acknowledgements {
+            let _ = acknowledgement.await;
+        }
+        self.batches.finish(batch_id).await;
+        if cancellation.is_cancelled() {
+            log::info!("Staggered batch {} cancelled by STOP", batch_id);
+        } else {
+            log::info!("Staggered batch {} completed", batch_id);
+        }
+    }
+
+    /// Progreso de un lote escalonado
+    pub async fn get_batch_progress(
+        &self,
+        batch_id: &str,
+    ) -> Result<BatchProgressResponse, AppError> {
+        self.batches
+            .get(batch_id)
+            .await
+            .ok_or_else(|| AppError::BatchNotFound(batch_id.to_string()))
+    }
+
+    /// Prepara la espera de confirmación antes de publicar, para no perder un
+    /// reporte rápido. `None` fuera del modo confirmado.
+    fn ack_waiter(
+        &self,
+        blind: &BlindConfig,
+        command: &BlindCommand,
+        properties: &CommandProperties,
+    ) -> Option<AckWaiter> {
+        if !self.confirm {
+            return None;
+        }
+
+        let status_topic = if command.is_tilt() {
+            blind
+                .tilt
+                .as_ref()
+                .and_then(|tilt| tilt.status_topic.as_ref())
+        } else {
+            blind.status_topic.as_ref()
+        };
+        let updates = status_topic.map(|_| self.state_store.subscribe_updates());
+        let response = match (
+            self.mqtt_service.protocol_version(),
+            &properties.response_topic,
+        ) {
+            (MqttProtocolVersion::V5, Some(topic)) => Some(ExpectedResponse {
+                topic: topic.clone(),
+                correlation_data: properties.correlation_data.clone(),
+                messages: self.mqtt_service.subscribe_messages(),
+            }),
+            _ => None,
+        };
+        Some(AckWaiter::new(&blind.id, command.clone(), updates).with_response(response))
+    }
+
+    /// Metadatos MQTT v5 del comando: la correlación identifica a la persiana
+    /// dentro de la petición
+    fn command_properties(&self, request_id: &str, blind: &BlindConfig) -> CommandProperties {
+        let mqtt = &self.config.snapshot().mqtt;
+        CommandProperties {
+            request_id: request_id.to_string(),
+            issuer: self.issuer.to_string(),
+            correlation_data: format!("{}:{}", request_id, blind.id).into_bytes(),
+            response_topic: Some(mqtt.response_topic()),
+            message_expiry_secs: (mqtt.message_expiry_secs > 0).then_some(mqtt.message_expiry_secs),
+        }
+    }
+
+    fn ack_deadline(&self) -> tokio::time::Instant {
+        let timeout_ms = self.config.snapshot().acknowledgement.timeout_ms;
+        tokio::time::Instant::now() + std::time::Duration::from_millis(timeout_ms)
     }
 
     pub async fn get_system_status(&self) -> SystemStatusResponse {
+        let config = self.config.snapshot();
         let rooms = self.get_room_info();
-        let enabled_blinds = self.config.get_enabled_blinds();
+        let enabled_blinds = config.get_enabled_blinds();
         let mqtt_connected = self.mqtt_service.is_connected().await;
 
         let uptime = {
@@ -160,7 +1061,7 @@ impl BlindService {
                 "degraded".to_string()
             },
             mqtt_connected,
-            total_blinds: self.config.blinds.len(),
+            total_blinds: config.blinds.len(),
             enabled_blinds: enabled_blinds.len(),
             rooms,
             uptime,
@@ -169,15 +1070,17 @@ impl BlindService {
         }
     }
 
-    pub fn get_blinds_status(&self) -> HashMap<String, Vec<BlindStatus>> {
+    pub async fn get_blinds_status(&self) -> HashMap<String, Vec<BlindStatus>> {
+        let config = self.config.snapshot();
         let mut rooms_map: HashMap<String, Vec<BlindStatus>> = HashMap::new();
+        let states = self.state_store.snapshot().await;
 
-        for blind in &self.config.blinds {
+        for blind in &config.blinds {
             if blind.enabled {
-                let blind_status = BlindStatus::from(blind);
+                let blind_status = self.blind_status(blind, &states).await;
                 rooms_map
                     .entry(blind.room.clone())
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(blind_status);
             }
         }
@@ -185,8 +1088,26 @@ impl BlindService {
         rooms_map
     }
 
+    async fn blind_status(
+        &self,
+        blind: &BlindConfig,
+        states: &HashMap<String, BlindState>,
+    ) -> BlindStatus {
+        let config = self.config.snapshot();
+        let status = BlindStatus::new(blind, &config).with_state(states.get(&blind.id));
+        if blind.travel_times().is_none() {
+            return status;
+        }
+        status.with_estimate(
+            self.position_estimator
+                .estimate(&blind.id, position_estimator::now())
+                .await,
+        )
+    }
+
     pub fn get_rooms(&self) -> RoomsResponse {
-        let rooms = self.config.get_rooms();
+        let config = self.config.snapshot();
+        let rooms = config.get_rooms();
         RoomsResponse {
             rooms: rooms.clone(),
             total_rooms: rooms.len(),
@@ -194,25 +1115,25 @@ impl BlindService {
         }
     }
 
-    pub fn get_config(&self) -> ConfigResponse {
-        let enabled_blinds: Vec<BlindStatus> = self
-            .config
-            .get_enabled_blinds()
-            .into_iter()
-            .map(|blind| BlindStatus::from(blind))
-            .collect();
+    pub async fn get_config(&self) -> ConfigResponse {
+        let config = self.config.snapshot();
+        let states = self.state_store.snapshot().await;
+        let mut enabled_blinds = Vec::new();
+        for blind in config.get_enabled_blinds() {
+            enabled_blinds.push(self.blind_status(blind, &states).await);
+        }
 
         ConfigResponse {
             mqtt: MqttConfigResponse {
-                broker_host: self.config.mqtt.broker_host.clone(),
-                broker_port: self.config.mqtt.broker_port,
-                client_id: self.config.mqtt.client_id.clone(),
-                username: self.config.mqtt.username.clone(),
+                broker_host: config.mqtt.broker_host.clone(),
+                broker_port: config.mqtt.broker_port,
+                client_id: config.mqtt.client_id.clone(),
+                username: config.mqtt.username.clone(),
                 // Password intentionally omitted for security
             },
             server: ServerConfigResponse {
-                host: self.config.server.host.clone(),
-                port: self.config.server.port,
+                host: config.server.host.clone(),
+                port: config.server.port,
             },
             blinds: enabled_blinds.clone(),
             total_blinds: enabled_blinds.len(),
@@ -221,7 +1142,8 @@ impl BlindService {
     }
 
     fn get_room_info(&self) -> Vec<RoomInfo> {
-        let blinds_by_room = self.config.get_blinds_map();
+        let config = self.config.snapshot();
+        let blinds_by_room = config.get_blinds_map();
         let mut rooms = Vec::new();
 
         for (room_name, room_blinds) in blinds_by_room {
@@ -245,32 +1167,72 @@ impl BlindService {
         rooms
     }
 
-    pub fn validate_blind_id(&self, blind_id: &str) -> Result<&BlindConfig, AppError> {
+    pub fn validate_blind_id(&self, blind_id: &str) -> Result<BlindConfig, AppError> {
         self.config
+            .snapshot()
             .get_blind_by_id(blind_id)
+            .cloned()
             .ok_or_else(|| AppError::BlindNotFound(blind_id.to_string()))
     }
 
-    pub fn validate_room(&self, room: &str) -> Result<Vec<&BlindConfig>, AppError> {
-        let room_blinds = self.config.get_blinds_by_room(room);
+    pub fn validate_room(&self, room: &str) -> Result<Vec<BlindConfig>, AppError> {
+        let config = self.config.snapshot();
+        let room_blinds = config.get_blinds_by_room(room);
         if room_blinds.is_empty() {
             Err(AppError::RoomNotFound(room.to_string()))
         } else {
-            Ok(room_blinds)
+            Ok(room_blinds.into_iter().cloned().collect())
         }
     }
 
-    pub async fn get_mqtt_info(&self) -> String {
+    pub async fn get_mqtt_info(&self) -> MqttInfoResponse {
         self.mqtt_service.get_client_info().await
     }
 }
 
+/// Añade el resultado de una persiana a la respuesta del lote. Devuelve la
+/// posición del resultado si el comando se publicó (y puede confirmarse).
+fn record_batch_result(
+    response: &mut BatchControlResponse,
+    blind: &BlindConfig,
+    result: Result<(String, Delivery), AppError>,
+) -> Option<usize> {
+    match result {
+        Ok((topic, Delivery::Sent)) => {
+            response.add_success(blind.id.clone(), blind.name.clone(), topic);
+            Some(response.results.len() - 1)
+        }
+        Ok((topic, Delivery::Queued)) => {
+            response.add_queued(blind.id.clone(), blind.name.clone(), topic);
+            None
+        }
+        Err(e) => {
+            response.add_failure(blind.id.clone(), blind.name.clone(), e.to_string());
+            None
+        }
+    }
+}
+
+/// Identificador aleatorio de una petición de control
+fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
 impl Clone for BlindService {
     fn clone(&self) -> Self {
         Self {
             mqtt_service: self.mqtt_service.clone(),
-            config: Arc::clone(&self.config),
+            config: self.config.clone(),
+            state_store: self.state_store.clone(),
+            battery_monitor: self.battery_monitor.clone(),
+            position_estimator: self.position_estimator.clone(),
             start_time: self.start_time,
+            confirm: self.confirm,
+            outbox: self.outbox.clone(),
+            issuer: self.issuer,
+            batches: self.batches.clone(),
+            power_circuits: self.power_circuits.clone(),
+            motor_guard: self.motor_guard.clone(),
         }
     }
 }
@@ -278,20 +1240,18 @@ impl Clone for BlindService {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{MqttConfig, ServerConfig};
+    use crate::config::{AppConfig, MqttConfig, ServerConfig};
+    use crate::models::blind::AckStatus;
+    use crate::models::responses::BatchStatus;
     use rumqttc::{AsyncClient, MqttOptions};
     use std::sync::Arc;
-    use tokio::sync::Mutex;
 
     fn create_test_config() -> AppConfig {
         AppConfig {
             mqtt: MqttConfig {
-                broker_host: "localhost".to_string(),
-                broker_port: 1883,
                 client_id: "test".to_string(),
                 keep_alive_secs: 60,
-                username: None,
-                password: None,
+                ..MqttConfig::default()
             },
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
@@ -304,9 +1264,11 @@ mod tests {
                 mqtt_topic: "test/topic".to_string(),
                 device_type: "test".to_string(),
                 enabled: true,
-                battery_topic: None,
-                status_topic: None,
+                battery_topic: Some("test/battery".to_string()),
+                status_topic: Some("test/status".to_string()),
+                ..Default::default()
             }],
+            ..AppConfig::default()
         }
     }
 
@@ -315,7 +1277,7 @@ mod tests {
         let config = Arc::new(create_test_config());
         let mqtt_options = MqttOptions::new("test", "localhost", 1883);
         let (client, _) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
         let blind_service = BlindService::new(mqtt_service, config);
 
         // Valid blind ID
@@ -330,7 +1292,7 @@ mod tests {
         let config = Arc::new(create_test_config());
         let mqtt_options = MqttOptions::new("test", "localhost", 1883);
         let (client, _) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
         let blind_service = BlindService::new(mqtt_service, config);
 
         // Valid room
@@ -340,12 +1302,601 @@ mod tests {
         assert!(blind_service.validate_room("nonexistent_room").is_err());
     }
 
+    #[tokio::test]
+    async fn test_status_reports_and_commands_update_state() {
+        let config = Arc::new(create_test_config());
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, config);
+
+        blind_service
+            .control_blind_by_id("test_blind", "close")
+            .await
+            .unwrap();
+        blind_service
+            .handle_message(&MqttMessage::from(rumqttc::Publish::new(
+                "test/status",
+                rumqttc::QoS::AtMostOnce,
+                r#"{"state":"closed","position":0}"#,
+            )))
+            .await;
+
+        let status = blind_service.get_blinds_status().await;
+        let blind = &status["test_room"][0];
+        assert_eq!(blind.state.as_deref(), Some("closed"));
+        assert_eq!(blind.position, Some(0));
+        assert_eq!(blind.last_command, Some(BlindCommand::Close));
+        assert!(blind.last_update.is_some());
+
+        let config = blind_service.get_config().await;
+        assert_eq!(config.blinds[0].position, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_battery_reports() {
+        let config = Arc::new(create_test_config());
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, config);
+
+        for payload in ["80", r#"{"voltage": 3.1}"#] {
+            blind_service
+                .handle_message(&MqttMessage::from(rumqttc::Publish::new(
+                    "test/battery",
+                    rumqttc::QoS::AtMostOnce,
+                    payload,
+                )))
+                .await;
+        }
+
+        let battery = blind_service
+            .get_battery_status("test_blind")
+            .await
+            .unwrap();
+        assert_eq!(battery.history.len(), 2);
+        assert_eq!(battery.voltage, Some(3.1));
+        assert!(battery.low_battery);
+
+        assert!(matches!(
+            blind_service.get_battery_status("nonexistent").await,
+            Err(AppError::BlindNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tilt_requires_tilt_support() {
+        let mut config = create_test_config();
+        let mut venetian = config.blinds[0].clone();
+        venetian.id = "venetian".to_string();
+        venetian.tilt = Some(crate::config::TiltConfig {
+            command_topic: Some("test/venetian/tilt/set".to_string()),
+            status_topic: Some("test/venetian/tilt".to_string()),
+            payload_format: crate::config::PayloadFormat::Text,
+            open_value: 50,
+            closed_value: 0,
+        });
+        config.blinds.push(venetian);
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        assert!(matches!(
+            blind_service.set_tilt_by_id("test_blind", "open").await,
+            Err(AppError::UnsupportedCommand(_, _))
+        ));
+
+        let response = blind_service
+            .set_tilt_by_id("venetian", "30")
+            .await
+            .unwrap();
+        assert_eq!(response.topic, "test/venetian/tilt/set");
+        assert_eq!(response.value, Some(30));
+
+        let BatchOutcome::Completed(batch) = blind_service
+            .set_tilt_by_room("test_room", "close")
+            .await
+            .unwrap()
+        else {
+            panic!("batch without stagger should complete");
+        };
+        assert_eq!(batch.successful, 1);
+        assert_eq!(batch.failed, 1);
+
+        blind_service
+            .handle_message(&MqttMessage::from(rumqttc::Publish::new(
+                "test/venetian/tilt",
+                rumqttc::QoS::AtMostOnce,
+                "0",
+            )))
+            .await;
+        let status = blind_service.get_blinds_status().await;
+        let venetian = status["test_room"]
+            .iter()
+            .find(|blind| blind.id == "venetian")
+            .unwrap();
+        assert_eq!(venetian.tilt, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_set_position_emulated_with_travel_times() {
+        // Con el reloj pausado `sleep` avanza el tiempo sin esperar
+        tokio::time::pause();
+        let mut config = create_test_config();
+        config.blinds[0].travel_time_open_secs = Some(20.0);
+        config.blinds[0].travel_time_close_secs = Some(10.0);
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        // Posición desconocida: solo se aceptan los extremos
+        assert!(matches!(
+            blind_service.set_position_by_id("test_blind", "50").await,
+            Err(AppError::ValidationError(_))
+        ));
+
+        blind_service
+            .control_blind_by_id("test_blind", "close")
+            .await
+            .unwrap();
+        let status = blind_service.get_blinds_status().await;
+        assert!(status["test_room"][0].estimated);
+        assert_eq!(status["test_room"][0].state.as_deref(), Some("closing"));
+
+        tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+        let response = blind_service
+            .set_position_by_id("test_blind", "50")
+            .await
+            .unwrap();
+        assert_eq!(response.value, Some(50));
+
+        // El STOP programado detiene la persiana a mitad de recorrido
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        let status = blind_service.get_blinds_status().await;
+        let blind = &status["test_room"][0];
+        assert_eq!(blind.state.as_deref(), Some("open"));
+        assert_eq!(blind.position, Some(50));
+    }
+
+    #[tokio::test]
+    async fn test_device_profile_rejects_unsupported_commands() {
+        let mut config = create_test_config();
+        config.blinds[0].device_type = "relay_blind".to_string();
+        config.device_profiles.insert(
+            "no_stop".to_string(),
+            serde_json::from_str(r#"{"stop": false}"#).unwrap(),
+        );
+        let mut no_stop = config.blinds[0].clone();
+        no_stop.id = "no_stop_blind".to_string();
+        no_stop.device_type = "no_stop".to_string();
+        config.blinds.push(no_stop);
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        assert!(matches!(
+            blind_service.set_position_by_id("test_blind", "40").await,
+            Err(AppError::UnsupportedCommand(_, _))
+        ));
+        assert!(blind_service
+            .control_blind_by_id("test_blind", "stop")
+            .await
+            .is_ok());
+        assert!(matches!(
+            blind_service
+                .control_blind_by_id("no_stop_blind", "stop")
+                .await,
+            Err(AppError::UnsupportedCommand(_, _))
+        ));
+
+        let config = blind_service.get_config().await;
+        let relay = config.blinds.iter().find(|b| b.id == "test_blind").unwrap();
+        assert!(!relay.capabilities.position);
+        assert!(relay.capabilities.stop);
+        let no_stop = config
+            .blinds
+            .iter()
+            .find(|b| b.id == "no_stop_blind")
+            .unwrap();
+        assert!(no_stop.capabilities.position);
+        assert!(!no_stop.capabilities.stop);
+    }
+
+    #[tokio::test]
+    async fn test_motor_protection_limits_moves() {
+        let mut config = create_test_config();
+        config.device_profiles.insert(
+            "test".to_string(),
+            serde_json::from_str(r#"{"protection": {"max_moves": 1, "cooldown_secs": 30}}"#)
+                .unwrap(),
+        );
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        assert!(blind_service
+            .control_blind_by_id("test_blind", "open")
+            .await
+            .is_ok());
+        match blind_service
+            .control_blind_by_id("test_blind", "close")
+            .await
+        {
+            Err(AppError::RateLimited(blind_id, _, retry_after)) => {
+                assert_eq!(blind_id, "test_blind");
+                assert_eq!(retry_after, 30);
+            }
+            other => panic!("expected RateLimited, got {:?}", other.map(|_| ())),
+        }
+        // STOP is never limited
+        assert!(blind_service
+            .control_blind_by_id("test_blind", "stop")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_publish_does_not_count_as_move() {
+        let mut config = create_test_config();
+        config.device_profiles.insert(
+            "test".to_string(),
+            serde_json::from_str(r#"{"protection": {"max_moves": 1, "cooldown_secs": 30}}"#)
+                .unwrap(),
+        );
+
+        // Without an event loop every publish fails
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(mqtt_options, 10);
+        drop(eventloop);
+        let blind_service = BlindService::new(MqttService::new(client), Arc::new(config));
+
+        for action in ["open", "close"] {
+            match blind_service
+                .control_blind_by_id("test_blind", action)
+                .await
+            {
+                Err(AppError::MqttError(_)) => {}
+                other => panic!("expected MqttError, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_moves_share_motor_limits() {
+        let mut config = create_test_config();
+        config.device_profiles.insert(
+            "test".to_string(),
+            serde_json::from_str(r#"{"protection": {"max_moves": 1, "cooldown_secs": 30}}"#)
+                .unwrap(),
+        );
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let blind_service = BlindService::new(MqttService::new(client), Arc::new(config));
+        let blind = blind_service.validate_blind_id("test_blind").unwrap();
+        let properties = || blind_service.command_properties(&new_request_id(), &blind);
+
+        let (first, second) = tokio::join!(
+            blind_service.send_command(&blind, &BlindCommand::Open, properties()),
+            blind_service.send_command(&blind, &BlindCommand::Open, properties()),
+        );
+        let rate_limited = [&first, &second]
+            .iter()
+            .filter(|result| matches!(result, Err(AppError::RateLimited(..))))
+            .count();
+        assert!(first.is_ok() || second.is_ok());
+        assert_eq!(rate_limited, 1);
+    }
+
+    #[tokio::test]
+    async fn test_payload_encoder_selects_topic() {
+        let mut config = create_test_config();
+        config.blinds[0].mqtt_topic = "cmnd/test_shutter".to_string();
+        config.blinds[0].payload_format = Some(PayloadFormat::Tasmota);
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        let response = blind_service
+            .set_position_by_id("test_blind", "30")
+            .await
+            .unwrap();
+        assert_eq!(response.topic, "cmnd/test_shutter/ShutterPosition1");
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_commands_wait_for_device() {
+        let mut config = create_test_config();
+        config.acknowledgement.timeout_ms = 1_000;
+        let mut silent = config.blinds[0].clone();
+        silent.id = "silent_blind".to_string();
+        silent.status_topic = None;
+        config.blinds.push(silent);
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service, Arc::new(config));
+
+        let reporter = blind_service.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            reporter
+                .handle_message(&MqttMessage::from(rumqttc::Publish::new(
+                    "test/status",
+                    rumqttc::QoS::AtMostOnce,
+                    r#"{"state":"opening","position":10}"#,
+                )))
+                .await;
+        });
+
+        let confirmed = blind_service.with_confirmation(true);
+        let response = confirmed
+            .control_blind_by_id("test_blind", "open")
+            .await
+            .unwrap();
+        assert_eq!(response.acknowledgement, Some(AckStatus::Acknowledged));
+
+        let response = confirmed
+            .control_blind_by_id("silent_blind", "open")
+            .await
+            .unwrap();
+        assert_eq!(response.acknowledgement, Some(AckStatus::Unsupported));
+
+        // Without ?confirm=true the response does not wait
+        let response = blind_service
+            .control_blind_by_id("test_blind", "open")
+            .await
+            .unwrap();
+        assert_eq!(response.acknowledgement, None);
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_queued_while_broker_is_down() {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        let blind_service = BlindService::new(mqtt_service.clone(), Arc::new(create_test_config()))
+            .with_outbox(outbox);
+
+        let response = blind_service
+            .control_blind_by_id("test_blind", "open")
+            .await
+            .unwrap();
+        assert!(response.queued);
+        // Nothing has reached the motor yet
+        let status = blind_service.get_blinds_status().await;
+        assert_eq!(status["test_room"][0].last_command, None);
+        let BatchOutcome::Completed(response) =
+            blind_service.control_all_blinds("close").await.unwrap()
+        else {
+            panic!("batch without stagger should complete");
+        };
+        assert_eq!(response.queued, 1);
+        assert_eq!(response.results[0].status, "queued");
+
+        let outbox = blind_service.get_outbox().await;
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].command, "CLOSE");
+
+        // Once connected, a new command supersedes the queued one
+        mqtt_service.set_connected(true).await;
+        let response = blind_service
+            .control_blind_by_id("test_blind", "stop")
+            .await
+            .unwrap();
+        assert!(!response.queued);
+        assert!(blind_service.get_outbox().await.is_empty());
+        let status = blind_service.get_blinds_status().await;
+        assert_eq!(
+            status["test_room"][0].last_command,
+            Some(BlindCommand::Stop)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flushed_commands_are_recorded() {
+        let mut config = create_test_config();
+        config.device_profiles.insert(
+            "test".to_string(),
+            serde_json::from_str(r#"{"protection": {"max_moves": 1, "cooldown_secs": 30}}"#)
+                .unwrap(),
+        );
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        let blind_service =
+            BlindService::new(mqtt_service.clone(), Arc::new(config)).with_outbox(outbox.clone());
+
+        let response = blind_service
+            .control_blind_by_id("test_blind", "close")
+            .await
+            .unwrap();
+        assert!(response.queued);
+
+        mqtt_service.set_connected(true).await;
+        assert_eq!(outbox.flush().await, 1);
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            loop {
+                let status = blind_service.get_blinds_status().await;
+                if status["test_room"][0].last_command == Some(BlindCommand::Close) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // The flushed move counts against the motor limits
+        assert!(matches!(
+            blind_service
+                .control_blind_by_id("test_blind", "open")
+                .await,
+            Err(AppError::RateLimited(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_results_keep_blind_order() {
+        let mut config = create_test_config();
+        config.batch.concurrency = 3;
+        let template = config.blinds[0].clone();
+        config.blinds = (0..10)
+            .map(|i| BlindConfig {
+                id: format!("blind_{}", i),
+                mqtt_topic: format!("test/blind_{}", i),
+                ..template.clone()
+            })
+            .collect();
+        // Relay blinds without travel times reject positions
+        config.blinds[4].device_type = "relay_blind".to_string();
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 20);
+        let blind_service = BlindService::new(MqttService::new(client), Arc::new(config));
+
+        let BatchOutcome::Completed(response) = blind_service.set_position_all("50").await.unwrap()
+        else {
+            panic!("batch without stagger should complete");
+        };
+        let ids: Vec<_> = response
+            .results
+            .iter()
+            .map(|result| result.blind_id.as_str())
+            .collect();
+        let expected: Vec<_> = (0..10).map(|i| format!("blind_{}", i)).collect();
+        assert_eq!(ids, expected);
+        assert_eq!(response.successful, 9);
+        assert_eq!(response.results[4].status, "error");
+    }
+
+    #[tokio::test]
+    async fn test_staggered_batch_runs_in_background() {
+        let mut config = create_test_config();
+        config.batch.stagger_delay_ms = 10;
+        let template = config.blinds[0].clone();
+        config.blinds = (0..3)
+            .map(|i| BlindConfig {
+                id: format!("blind_{}", i),
+                mqtt_topic: format!("test/blind_{}", i),
+                ..template.clone()
+            })
+            .collect();
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let blind_service = BlindService::new(MqttService::new(client), Arc::new(config));
+
+        let BatchOutcome::Started(handle) = blind_service.control_all_blinds("open").await.unwrap()
+        else {
+            panic!("staggered batch should run in the background");
+        };
+        assert_eq!(handle.total_blinds, 3);
+        assert_eq!(
+            handle.status_url,
+            format!("/blinds/batches/{}", handle.batch_id)
+        );
+
+        let progress = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            loop {
+                let progress = blind_service
+                    .get_batch_progress(&handle.batch_id)
+                    .await
+                    .unwrap();
+                if progress.status == BatchStatus::Completed {
+                    return progress;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(progress.dispatched, 3);
+        assert_eq!(progress.result.successful, 3);
+
+        // STOP is never staggered
+        assert!(matches!(
+            blind_service.control_all_blinds("stop").await.unwrap(),
+            BatchOutcome::Completed(_)
+        ));
+        assert!(blind_service.get_batch_progress("unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stop_cancels_staggered_batch() {
+        let mut config = create_test_config();
+        config.batch.stagger_delay_ms = 60_000;
+        let template = config.blinds[0].clone();
+        config.blinds = (0..3)
+            .map(|i| BlindConfig {
+                id: format!("blind_{}", i),
+                mqtt_topic: format!("test/blind_{}", i),
+                ..template.clone()
+            })
+            .collect();
+
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let blind_service = BlindService::new(MqttService::new(client), Arc::new(config));
+
+        let BatchOutcome::Started(handle) = blind_service.control_all_blinds("open").await.unwrap()
+        else {
+            panic!("staggered batch should run in the background");
+        };
+        // The first blind starts right away; the rest wait for the stagger delay
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        blind_service
+            .control_blinds_by_room("test_room", "stop")
+            .await
+            .unwrap();
+
+        let progress = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            loop {
+                let progress = blind_service
+                    .get_batch_progress(&handle.batch_id)
+                    .await
+                    .unwrap();
+                if progress.status != BatchStatus::Running {
+                    return progress;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(progress.status, BatchStatus::Cancelled);
+        assert_eq!(progress.dispatched, 1);
+        assert_eq!(progress.result.successful, 1);
+        assert_eq!(progress.result.cancelled, 2);
+        let statuses: Vec<_> = progress
+            .result
+            .results
+            .iter()
+            .map(|result| result.status.as_str())
+            .collect();
+        assert_eq!(statuses, ["success", "cancelled", "cancelled"]);
+    }
+
     #[test]
     fn test_get_rooms() {
         let config = Arc::new(create_test_config());
         let mqtt_options = MqttOptions::new("test", "localhost", 1883);
         let (client, _) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
         let blind_service = BlindService::new(mqtt_service, config);
 
         let rooms_response = blind_service.get_rooms();
@@ -0,0 +1,131 @@
+use crate::config::MqttQos;
+use crate::errors::AppError;
+use crate::models::responses::BrokerSysStats;
+use crate::services::mqtt_inspector::topic_matches;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use chrono::Utc;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+/// Prefijo de las estadísticas del broker (Mosquitto, EMQX, ...)
+const SYS_BROKER_PREFIX: &str = "$SYS/broker/";
+
+/// Últimas estadísticas `$SYS` recibidas del broker, mostradas en `/mqtt/info`
+#[derive(Clone, Default)]
+pub struct BrokerStats {
+    stats: Arc<RwLock<Option<BrokerSysStats>>>,
+}
+
+impl BrokerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Suscribe a `topics` y lanza la tarea que guarda los valores recibidos
+    pub async fn start(
+        &self,
+        mqtt_service: &MqttService,
+        topics: Vec<String>,
+    ) -> Result<(), AppError> {
+        let mut receiver = mqtt_service.subscribe_messages();
+        for topic in &topics {
+            mqtt_service
+                .subscribe_with_qos(topic, MqttQos::AtMostOnce)
+                .await?;
+        }
+
+        let broker_stats = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        if topics
+                            .iter()
+                            .any(|filter| topic_matches(filter, &message.topic))
+                        {
+                            broker_stats.apply(&message).await;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Broker stats lagged, {} MQTT messages skipped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Guarda el valor de un tema `$SYS/broker/...`
+    pub async fn apply(&self, message: &MqttMessage) {
+        let Some(name) = message.topic.strip_prefix(SYS_BROKER_PREFIX) else {
+            return;
+        };
+        let value = message.payload_str().trim().to_string();
+
+        let mut stats = self.stats.write().await;
+        let stats = stats.get_or_insert_with(BrokerSysStats::default);
+        match name {
+            "version" => stats.version = Some(value),
+            "uptime" => stats.uptime = Some(value),
+            "clients/connected" => stats.clients_connected = value.parse().ok(),
+            _ => match name.strip_prefix("load/") {
+                Some(load) => {
+                    if let Ok(value) = value.parse() {
+                        stats.load.insert(load.to_string(), value);
+                    }
+                }
+                None => {
+                    stats.metrics.insert(name.to_string(), value);
+                }
+            },
+        }
+        stats.updated_at = Some(Utc::now());
+    }
+
+    pub async fn snapshot(&self) -> Option<BrokerSysStats> {
+        self.stats.read().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            ..MqttMessage::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_sys_topics() {
+        let broker_stats = BrokerStats::new();
+        assert!(broker_stats.snapshot().await.is_none());
+
+        for (topic, payload) in [
+            ("$SYS/broker/version", "mosquitto version 2.0.18"),
+            ("$SYS/broker/uptime", "3600 seconds"),
+            ("$SYS/broker/clients/connected", "4"),
+            ("$SYS/broker/load/messages/received/1min", "12.5"),
+            ("$SYS/broker/load/bytes/sent/5min", "not a number"),
+            ("$SYS/broker/subscriptions/count", "17"),
+            ("home/blinds/bedroom/status", "open"),
+        ] {
+            broker_stats.apply(&message(topic, payload)).await;
+        }
+
+        let stats = broker_stats.snapshot().await.unwrap();
+        assert_eq!(stats.version.as_deref(), Some("mosquitto version 2.0.18"));
+        assert_eq!(stats.uptime.as_deref(), Some("3600 seconds"));
+        assert_eq!(stats.clients_connected, Some(4));
+        assert_eq!(stats.load.len(), 1);
+        assert_eq!(stats.load["messages/received/1min"], 12.5);
+        assert_eq!(stats.metrics["subscriptions/count"], "17");
+        assert!(stats.updated_at.is_some());
+    }
+}
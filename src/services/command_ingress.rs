@@ -0,0 +1,353 @@
+use crate::config::{CommandIngressConfig, MqttQos};
+use crate::errors::AppError;
+use crate::models::responses::{ApiResponse, CommandResultMessage};
+use crate::services::blind_service::BlindService;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Emisor de los comandos recibidos por MQTT
+const MQTT_ISSUER: &str = "mqtt";
+
+/// Persianas a las que va dirigido un comando
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandTarget {
+    Blind(String),
+    Room(String),
+    All,
+}
+
+impl CommandTarget {
+    /// Destino de `<prefix>/blind/<id>`, `<prefix>/room/<habitación>` o `<prefix>/all`
+    pub fn from_topic(prefix: &str, topic: &str) -> Option<Self> {
+        let path = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+        match path.split_once('/') {
+            Some(("blind", id)) if !id.is_empty() => Some(CommandTarget::Blind(id.to_string())),
+            Some(("room", room)) if !room.is_empty() => Some(CommandTarget::Room(room.to_string())),
+            None if path == "all" => Some(CommandTarget::All),
+            _ => None,
+        }
+    }
+
+    /// Subtema común al comando y a su resultado
+    fn path(&self) -> String {
+        match self {
+            CommandTarget::Blind(id) => format!("blind/{}", id),
+            CommandTarget::Room(room) => format!("room/{}", room),
+            CommandTarget::All => "all".to_string(),
+        }
+    }
+}
+
+/// Qué hacer con las persianas: una acción (`open`, `close`, `stop`), una posición
+/// o una inclinación
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandAction {
+    Action(String),
+    Position(String),
+    Tilt(String),
+}
+
+/// Comando recibido por MQTT
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngressCommand {
+    pub action: CommandAction,
+    pub request_id: Option<String>,
+    pub confirm: bool,
+}
+
+impl IngressCommand {
+    /// Acepta texto (`OPEN`, `stop`, `50` para una posición) o JSON:
+    /// `{"action": "open"}`, `{"position": 50}` o `{"tilt": 30}`, con
+    /// `request_id` y `confirm` opcionales
+    pub fn parse(payload: &str) -> Result<Self, AppError> {
+        let payload = payload.trim();
+        let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(payload) else {
+            let action = if payload.parse::<u8>().is_ok() {
+                CommandAction::Position(payload.to_string())
+            } else {
+                CommandAction::Action(payload.to_string())
+            };
+            return Ok(Self {
+                action,
+                request_id: None,
+                confirm: false,
+            });
+        };
+
+        let field = |name: &str| match fields.get(name) {
+            Some(Value::String(value)) => Some(value.clone()),
+            Some(Value::Number(value)) => Some(value.to_string()),
+            _ => None,
+        };
+        let action = if let Some(action) = field("action") {
+            CommandAction::Action(action)
+        } else if let Some(position) = field("position") {
+            CommandAction::Position(position)
+        } else if let Some(tilt) = field("tilt") {
+            CommandAction::Tilt(tilt)
+        } else {
+            return Err(AppError::ValidationError(
+                "Command needs 'action', 'position' or 'tilt'".to_string(),
+            ));
+        };
+
+        Ok(Self {
+            action,
+            request_id: field("request_id"),
+            confirm: fields
+                .get("confirm")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Recibe comandos publicados bajo `command_prefix`, los ejecuta con los mismos
+/// métodos de `BlindService` que la API HTTP y publica el resultado bajo
+/// `result_prefix`
+#[derive(Clone)]
+pub struct CommandIngress {
+    blind_service: BlindService,
+    mqtt_service: MqttService,
+    config: CommandIngressConfig,
+}
+
+impl CommandIngress {
+    pub fn new(
+        blind_service: BlindService,
+        mqtt_service: MqttService,
+        config: CommandIngressConfig,
+    ) -> Self {
+        Self {
+            blind_service: blind_service.with_issuer(MQTT_ISSUER),
+            mqtt_service,
+            config,
+        }
+    }
+
+    pub async fn start(&self) -> Result<(), AppError> {
+        let mut receiver = self.mqtt_service.subscribe_messages();
+        self.mqtt_service
+            .subscribe_with_qos(
+                &format!("{}/#", self.config.command_prefix),
+                MqttQos::AtLeastOnce,
+            )
+            .await?;
+
+        let ingress = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => ingress.handle_message(&message).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!(
+                            "MQTT command ingress lagged, {} MQTT messages skipped",
+                            skipped
+                        );
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn handle_message(&self, message: &MqttMessage) {
+        let Some(target) = CommandTarget::from_topic(&self.config.command_prefix, &message.topic)
+        else {
+            return;
+        };
+        // A retained command would move the blinds again on every reconnect
+        if message.retain {
+            log::debug!("Ignoring retained MQTT command on {}", message.topic);
+            return;
+        }
+        let command = IngressCommand::parse(&message.payload_str());
+
+        // Confirmed commands wait for the device; run them aside so the
+        // commands behind them are not held up
+        if matches!(command, Ok(IngressCommand { confirm: true, .. })) {
+            let ingress = self.clone();
+            tokio::spawn(async move { ingress.execute(target, command).await });
+        } else {
+            self.execute(target, command).await;
+        }
+    }
+
+    async fn execute(&self, target: CommandTarget, command: Result<IngressCommand, AppError>) {
+        let request_id = command
+            .as_ref()
+            .ok()
+            .and_then(|command| command.request_id.clone());
+        let result = match command {
+            Ok(command) => self.run(&target, command).await,
+            Err(e) => Err(e),
+        };
+
+        let response = match result {
+            Ok(data) => ApiResponse::success(data),
+            Err(e) => {
+                log::warn!("MQTT command for {} failed: {}", target.path(), e);
+                ApiResponse::error(e.to_string())
+            }
+        };
+        let message = CommandResultMessage {
+            request_id,
+            response,
+        };
+        let topic = format!("{}/{}", self.config.result_prefix, target.path());
+        match serde_json::to_string(&message) {
+            Ok(payload) => {
+                if let Err(e) = self.mqtt_service.publish_command(&topic, &payload).await {
+                    log::error!("Failed to publish command result to '{}': {}", topic, e);
+                }
+            }
+            Err(e) => log::error!("Failed to encode command result: {}", e),
+        }
+    }
+
+    async fn run(
+        &self,
+        target: &CommandTarget,
+        command: IngressCommand,
+    ) -> Result<Value, AppError> {
+        let service = self.blind_service.with_confirmation(command.confirm);
+        let data = match (target, &command.action) {
+            (CommandTarget::Blind(id), CommandAction::Action(action)) => {
+                serde_json::to_value(service.control_blind_by_id(id, action).await?)
+            }
+            (CommandTarget::Blind(id), CommandAction::Position(percent)) => {
+                serde_json::to_value(service.set_position_by_id(id, percent).await?)
+            }
+            (CommandTarget::Blind(id), CommandAction::Tilt(value)) => {
+                serde_json::to_value(service.set_tilt_by_id(id, value).await?)
+            }
+            (CommandTarget::Room(room), CommandAction::Action(action)) => {
+                serde_json::to_value(service.control_blinds_by_room(room, action).await?)
+            }
+            (CommandTarget::Room(room), CommandAction::Position(percent)) => {
+                serde_json::to_value(service.set_position_by_room(room, percent).await?)
+            }
+            (CommandTarget::Room(room), CommandAction::Tilt(value)) => {
+                serde_json::to_value(service.set_tilt_by_room(room, value).await?)
+            }
+            (CommandTarget::All, CommandAction::Action(action)) => {
+                serde_json::to_value(service.control_all_blinds(action).await?)
+            }
+            (CommandTarget::All, CommandAction::Position(percent)) => {
+                serde_json::to_value(service.set_position_all(percent).await?)
+            }
+            (CommandTarget::All, CommandAction::Tilt(value)) => {
+                serde_json::to_value(service.set_tilt_all(value).await?)
+            }
+        };
+        Ok(data?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::models::blind::BlindCommand;
+    use rumqttc::{AsyncClient, MqttOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_topics_and_payloads() {
+        assert_eq!(
+            CommandTarget::from_topic("tabi/cmd", "tabi/cmd/blind/salon"),
+            Some(CommandTarget::Blind("salon".to_string()))
+        );
+        assert_eq!(
+            CommandTarget::from_topic("tabi/cmd", "tabi/cmd/room/Living Room"),
+            Some(CommandTarget::Room("Living Room".to_string()))
+        );
+        assert_eq!(
+            CommandTarget::from_topic("tabi/cmd", "tabi/cmd/all"),
+            Some(CommandTarget::All)
+        );
+        assert_eq!(
+            CommandTarget::from_topic("tabi/cmd", "tabi/cmd/blind/"),
+            None
+        );
+        assert_eq!(CommandTarget::from_topic("tabi/cmd", "tabi/cmdx/all"), None);
+
+        assert_eq!(
+            IngressCommand::parse("OPEN").unwrap().action,
+            CommandAction::Action("OPEN".to_string())
+        );
+        assert_eq!(
+            IngressCommand::parse("40").unwrap().action,
+            CommandAction::Position("40".to_string())
+        );
+        let command =
+            IngressCommand::parse(r#"{"tilt": 30, "request_id": "r1", "confirm": true}"#).unwrap();
+        assert_eq!(command.action, CommandAction::Tilt("30".to_string()));
+        assert_eq!(command.request_id.as_deref(), Some("r1"));
+        assert!(command.confirm);
+        assert!(IngressCommand::parse(r#"{"speed": 3}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_commands_use_blind_service() {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service.clone(), Arc::new(AppConfig::default()));
+        let ingress =
+            CommandIngress::new(blind_service, mqtt_service, CommandIngressConfig::default());
+
+        let target = CommandTarget::Blind("blind_001".to_string());
+        let data = ingress
+            .run(&target, IngressCommand::parse("close").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(data["command"], "CLOSE");
+
+        // The same validation as the HTTP API applies
+        let error = ingress
+            .run(&target, IngressCommand::parse("101").unwrap())
+            .await;
+        assert!(matches!(error, Err(AppError::ValidationError(_))));
+        let error = ingress
+            .run(
+                &CommandTarget::Blind("garage".to_string()),
+                IngressCommand::parse("open").unwrap(),
+            )
+            .await;
+        assert!(matches!(error, Err(AppError::BlindNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_retained_commands_are_ignored() {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let config = CommandIngressConfig::default();
+        let blind_service = BlindService::new(mqtt_service.clone(), Arc::new(AppConfig::default()));
+        let ingress = CommandIngress::new(blind_service.clone(), mqtt_service, config.clone());
+
+        let mut message = MqttMessage {
+            topic: format!("{}/blind/blind_001", config.command_prefix),
+            payload: b"close".to_vec(),
+            correlation_data: None,
+            response_topic: None,
+            qos: MqttQos::AtLeastOnce,
+            retain: true,
+        };
+        let last_command = || async {
+            blind_service.get_blinds_status().await["bedroom"][0]
+                .last_command
+                .clone()
+        };
+        ingress.handle_message(&message).await;
+        assert_eq!(last_command().await, None);
+
+        message.retain = false;
+        ingress.handle_message(&message).await;
+        assert_eq!(last_command().await, Some(BlindCommand::Close));
+    }
+}
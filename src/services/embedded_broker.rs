@@ -0,0 +1,219 @@
+use crate::config::{BrokerProtocol, MqttConfig};
+use crate::errors::AppError;
+use rumqttd::{Broker, Config, ConnectionSettings, RouterConfig, ServerSettings};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Espera máxima hasta que el broker acepte conexiones
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configuración de rumqttd parecida a la del mosquitto de docker-compose: los
+/// listeners configurados y, salvo `allow_anonymous`, solo usuarios con contraseña.
+/// rumqttd no restringe temas, así que no hay equivalente a `mosquitto_acl`.
+fn broker_config(mqtt: &MqttConfig) -> Config {
+    let broker = &mqtt.embedded_broker;
+    let auth = (!broker.allow_anonymous).then(|| {
+        let mut users = broker.users.clone();
+        if let (Some(username), Some(password)) = (&mqtt.username, &mqtt.password) {
+            users.insert(username.clone(), password.clone());
+        }
+        users
+    });
+    let connections = ConnectionSettings {
+        connection_timeout_ms: 60_000,
+        max_payload_size: 1024 * 1024,
+        max_inflight_count: 100,
+        auth,
+        external_auth: None,
+        dynamic_filters: true,
+    };
+
+    let mut servers: [HashMap<String, ServerSettings>; 3] = Default::default();
+    for (index, listener) in broker.listeners.iter().enumerate() {
+        let name = format!("{:?}-{}", listener.protocol, index).to_lowercase();
+        let settings = ServerSettings {
+            name: name.clone(),
+            listen: listener.bind,
+            tls: None,
+            next_connection_delay_ms: 1,
+            connections: connections.clone(),
+        };
+        let servers = match listener.protocol {
+            BrokerProtocol::Tcp => &mut servers[0],
+            BrokerProtocol::Tcp5 => &mut servers[1],
+            BrokerProtocol::Ws => &mut servers[2],
+        };
+        servers.insert(name, settings);
+    }
+    let [v4, v5, ws] = servers;
+
+    Config {
+        id: 0,
+        router: RouterConfig {
+            max_connections: 1000,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 100 * 1024 * 1024,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        },
+        v4: (!v4.is_empty()).then_some(v4),
+        v5: (!v5.is_empty()).then_some(v5),
+        ws: (!ws.is_empty()).then_some(ws),
+        ..Config::default()
+    }
+}
+
+/// Clientes, además del propio backend, con acceso a todos los temas
+fn unrestricted_clients(mqtt: &MqttConfig) -> Option<String> {
+    let broker = &mqtt.embedded_broker;
+    if broker.allow_anonymous {
+        return Some("any client".to_string());
+    }
+    let mut users: Vec<_> = broker
+        .users
+        .keys()
+        .filter(|user| mqtt.username.as_ref() != Some(*user))
+        .map(String::as_str)
+        .collect();
+    if users.is_empty() {
+        return None;
+    }
+    users.sort_unstable();
+    Some(format!("users {}", users.join(", ")))
+}
+
+/// Arranca el broker integrado en su propio hilo y espera a que el primer
+/// listener acepte conexiones
+pub async fn start(mqtt: &MqttConfig) -> Result<(), AppError> {
+    let config = broker_config(mqtt);
+    if let Some(clients) = unrestricted_clients(mqtt) {
+        log::warn!(
+            "Embedded MQTT broker has no per-topic ACL: {} can publish to blind and command topics",
+            clients
+        );
+    }
+    let mut broker = Broker::new(config);
+    std::thread::Builder::new()
+        .name("embedded-broker".to_string())
+        .spawn(move || {
+            if let Err(e) = broker.start() {
+                log::error!("Embedded MQTT broker stopped: {}", e);
+            }
+        })?;
+
+    let Some(listener) = mqtt.embedded_broker.listeners.first() else {
+        return Ok(());
+    };
+    // Wildcard binds are reachable through loopback
+    let mut address = listener.bind;
+    if address.ip().is_unspecified() {
+        address.set_ip([127, 0, 0, 1].into());
+    }
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while tokio::net::TcpStream::connect(address).await.is_err() {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(AppError::InternalError(format!(
+                "Embedded MQTT broker did not start listening on {}",
+                address
+            )));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    log::info!(
+        "Embedded MQTT broker listening on {}",
+        mqtt.embedded_broker
+            .listeners
+            .iter()
+            .map(|listener| listener.bind.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BrokerListener;
+    use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    async fn connect(port: u16, username: &str, password: &str) -> Option<rumqttc::EventLoop> {
+        let mut options = MqttOptions::new(format!("test-{}", username), "127.0.0.1", port);
+        options.set_credentials(username, password);
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+        let connack = tokio::time::timeout(Duration::from_secs(2), eventloop.poll()).await;
+        match connack {
+            Ok(Ok(Event::Incoming(Incoming::ConnAck(_)))) => {}
+            _ => return None,
+        }
+        client
+            .subscribe("tabi/test", QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        client
+            .publish("tabi/test", QoS::AtLeastOnce, false, "hello")
+            .await
+            .unwrap();
+        Some(eventloop)
+    }
+
+    #[test]
+    fn test_warns_about_unrestricted_clients() {
+        let mut mqtt = MqttConfig {
+            username: Some("tabi-backend".to_string()),
+            ..MqttConfig::default()
+        };
+        assert_eq!(unrestricted_clients(&mqtt), None);
+
+        mqtt.embedded_broker
+            .users
+            .insert("panel-cocina".to_string(), "secret".to_string());
+        assert_eq!(
+            unrestricted_clients(&mqtt).as_deref(),
+            Some("users panel-cocina")
+        );
+
+        mqtt.embedded_broker.allow_anonymous = true;
+        assert_eq!(unrestricted_clients(&mqtt).as_deref(), Some("any client"));
+    }
+
+    #[tokio::test]
+    async fn test_embedded_broker_requires_credentials() {
+        let port = free_port();
+        let mut mqtt = MqttConfig {
+            username: Some("tabi-backend".to_string()),
+            password: Some("secret".to_string()),
+            ..MqttConfig::default()
+        };
+        mqtt.embedded_broker.listeners = vec![BrokerListener {
+            protocol: BrokerProtocol::Tcp,
+            bind: ([127, 0, 0, 1], port).into(),
+        }];
+        start(&mqtt).await.unwrap();
+
+        let mut eventloop = connect(port, "tabi-backend", "secret").await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let Event::Incoming(Incoming::Publish(publish)) = eventloop.poll().await.unwrap()
+                {
+                    return publish;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(received.payload.as_ref(), b"hello");
+
+        assert!(connect(port, "tabi-backend", "wrong").await.is_none());
+    }
+}
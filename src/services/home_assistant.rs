@@ -0,0 +1,447 @@
+use crate::config::{AppConfig, BatteryFormat, BlindConfig, SharedConfig, StatusFormat};
+use crate::errors::AppError;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use crate::services::payload_encoder::encoder_for;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Prefijo de `unique_id` que identifica los documentos publicados por tabi
+const UNIQUE_ID_PREFIX: &str = "tabi_";
+
+/// Documento de descubrimiento retenido
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryDocument {
+    pub topic: String,
+    pub payload: Value,
+}
+
+/// Publica los documentos de descubrimiento MQTT de Home Assistant de las
+/// persianas habilitadas y retira los de persianas deshabilitadas o eliminadas
+pub struct HomeAssistantDiscovery {
+    mqtt_service: MqttService,
+    config: SharedConfig,
+}
+
+impl HomeAssistantDiscovery {
+    pub fn new(mqtt_service: MqttService, config: impl Into<SharedConfig>) -> Self {
+        Self {
+            mqtt_service,
+            config: config.into(),
+        }
+    }
+
+    /// Publica los documentos en cada conexión y cambio de configuración, y escucha
+    /// los documentos retenidos para borrar los que ya no corresponden a ninguna persiana
+    pub async fn start(&self) -> Result<(), AppError> {
+        let mut receiver = self.mqtt_service.subscribe_messages();
+        let prefix = &self.config.snapshot().home_assistant.discovery_prefix;
+        for component in ["cover", "sensor"] {
+            self.mqtt_service
+                .subscribe_to_topic(&format!("{}/{}/+/config", prefix, component))
+                .await?;
+        }
+
+        let publisher = self.clone();
+        let mut connection = self.mqtt_service.watch_connection();
+        let mut config_changes = self.config.subscribe();
+        tokio::spawn(async move {
+            let mut previous = publisher.config.snapshot();
+            loop {
+                if *connection.borrow_and_update() {
+                    let current = config_changes.borrow_and_update().clone();
+                    if let Err(e) = publisher.publish_all(&previous, &current).await {
+                        log::error!("Failed to publish Home Assistant discovery: {}", e);
+                    }
+                    previous = current;
+                }
+                tokio::select! {
+                    changed = connection.changed() => if changed.is_err() { break },
+                    changed = config_changes.changed() => if changed.is_err() { break },
+                }
+            }
+        });
+
+        let sweeper = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => sweeper.handle_message(&message).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Discovery sweep lagged, {} MQTT messages skipped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Publica los documentos de `current` y borra los de persianas deshabilitadas
+    /// o que estaban en `previous` y ya no existen
+    pub async fn publish_all(
+        &self,
+        previous: &AppConfig,
+        current: &AppConfig,
+    ) -> Result<(), AppError> {
+        let prefix = &current.home_assistant.discovery_prefix;
+        let removed = previous
+            .blinds
+            .iter()
+            .filter(|blind| current.get_blind_by_id(&blind.id).is_none());
+
+        for blind in current.blinds.iter().chain(removed) {
+            let enabled = blind.enabled && current.get_blind_by_id(&blind.id).is_some();
+            if enabled {
+                for document in discovery_documents(current, blind) {
+                    self.mqtt_service
+                        .publish_retained(&document.topic, &document.payload.to_string())
+                        .await?;
+                }
+            } else {
+                for topic in [
+                    cover_topic(prefix, &blind.id),
+                    battery_topic(prefix, &blind.id),
+                ] {
+                    self.mqtt_service.publish_retained(&topic, "").await?;
+                }
+            }
+        }
+
+        log::info!(
+            "Home Assistant discovery published for {} blinds",
+            current.get_enabled_blinds().len()
+        );
+        Ok(())
+    }
+
+    /// Borra un documento retenido de tabi que ya no corresponde a ninguna persiana habilitada
+    pub async fn handle_message(&self, message: &MqttMessage) {
+        if !self.is_orphan(message) {
+            return;
+        }
+
+        log::info!("Removing stale Home Assistant discovery: {}", message.topic);
+        if let Err(e) = self.mqtt_service.publish_retained(&message.topic, "").await {
+            log::error!("Failed to remove discovery '{}': {}", message.topic, e);
+        }
+    }
+
+    fn is_orphan(&self, message: &MqttMessage) -> bool {
+        let config = self.config.snapshot();
+        let prefix = format!("{}/", config.home_assistant.discovery_prefix);
+        if !message.topic.starts_with(&prefix) || !message.topic.ends_with("/config") {
+            return false;
+        }
+
+        let owned = serde_json::from_slice::<Value>(&message.payload)
+            .ok()
+            .and_then(|document| {
+                document
+                    .get("unique_id")
+                    .and_then(Value::as_str)
+                    .map(|id| id.starts_with(UNIQUE_ID_PREFIX))
+            })
+            .unwrap_or(false);
+
+        owned && !expected_topics(&config).contains(&message.topic)
+    }
+}
+
+impl Clone for HomeAssistantDiscovery {
+    fn clone(&self) -> Self {
+        Self {
+            mqtt_service: self.mqtt_service.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+fn expected_topics(config: &AppConfig) -> HashSet<String> {
+    config
+        .get_enabled_blinds()
+        .into_iter()
+        .flat_map(|blind| discovery_documents(config, blind))
+        .map(|document| document.topic)
+        .collect()
+}
+
+/// Home Assistant solo admite `[a-zA-Z0-9_-]` en el identificador del tema
+fn object_id(blind_id: &str) -> String {
+    blind_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn cover_topic(prefix: &str, blind_id: &str) -> String {
+    format!("{}/cover/{}/config", prefix, object_id(blind_id))
+}
+
+pub fn battery_topic(prefix: &str, blind_id: &str) -> String {
+    format!("{}/sensor/{}_battery/config", prefix, object_id(blind_id))
+}
+
+/// Documentos de la persiana: la cubierta y, si tiene `battery_topic`, el sensor de batería
+pub fn discovery_documents(config: &AppConfig, blind: &BlindConfig) -> Vec<DiscoveryDocument> {
+    let prefix = &config.home_assistant.discovery_prefix;
+    let device = json!({
+        "identifiers": [format!("{}{}", UNIQUE_ID_PREFIX, blind.id)],
+        "name": blind.name,
+        "model": blind.device_type,
+        "manufacturer": "Tabi",
+        "suggested_area": blind.room,
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    let mut documents = vec![DiscoveryDocument {
+        topic: cover_topic(prefix, &blind.id),
+        payload: cover_document(config, blind, device.clone()),
+    }];
+
+    if let Some(battery_topic_name) = &blind.battery_topic {
+        let format = config.device_profile(&blind.device_type).battery_format;
+        let (device_class, unit, template) = match format {
+            BatteryFormat::Auto | BatteryFormat::Percent => (
+                "battery",
+                "%",
+                "{{ value_json.battery if value_json is defined else value }}",
+            ),
+            BatteryFormat::Voltage => ("voltage", "V", "{{ value }}"),
+            BatteryFormat::Millivolts => {
+                ("voltage", "V", "{{ (value | float / 1000) | round(2) }}")
+            }
+        };
+        documents.push(DiscoveryDocument {
+            topic: battery_topic(prefix, &blind.id),
+            payload: json!({
+                "name": "Battery",
+                "unique_id": format!("{}{}_battery", UNIQUE_ID_PREFIX, blind.id),
+                "state_topic": battery_topic_name,
+                "device_class": device_class,
+                "unit_of_measurement": unit,
+                "state_class": "measurement",
+                "entity_category": "diagnostic",
+                "value_template": template,
+                "device": device,
+            }),
+        });
+    }
+
+    documents
+}
+
+fn cover_document(config: &AppConfig, blind: &BlindConfig, device: Value) -> Value {
+    let profile = config.device_profile(&blind.device_type);
+    let capabilities = profile.capabilities(blind);
+    let commands = encoder_for(profile.payload_format(blind)).discovery(&blind.mqtt_topic);
+
+    let mut document = Map::new();
+    document.insert("name".into(), Value::Null);
+    document.insert(
+        "unique_id".into(),
+        json!(format!("{}{}", UNIQUE_ID_PREFIX, blind.id)),
+    );
+    let device_class = match blind.device_type.as_str() {
+        "roller_shutter" | "tasmota_shutter" => "shutter",
+        _ => "blind",
+    };
+    document.insert("device_class".into(), json!(device_class));
+    document.insert("command_topic".into(), json!(commands.command_topic));
+    document.insert("payload_open".into(), json!(commands.payload_open));
+    document.insert("payload_close".into(), json!(commands.payload_close));
+    // `null` oculta el botón de parada en Home Assistant
+    let payload_stop = capabilities.stop.then_some(commands.payload_stop);
+    document.insert("payload_stop".into(), json!(payload_stop));
+
+    // La posición emulada por tiempos de recorrido solo está disponible a través de tabi
+    if profile.position {
+        document.insert(
+            "set_position_topic".into(),
+            json!(commands.set_position_topic),
+        );
+        if let Some(template) = commands.set_position_template {
+            document.insert("set_position_template".into(), json!(template));
+        }
+    }
+
+    match &blind.status_topic {
+        Some(status_topic) => match profile.status_format {
+            StatusFormat::Auto | StatusFormat::Json => {
+                document.insert("state_topic".into(), json!(status_topic));
+                document.insert(
+                    "value_template".into(),
+                    json!("{{ value_json.state | lower }}"),
+                );
+                if profile.position {
+                    document.insert("position_topic".into(), json!(status_topic));
+                    document.insert(
+                        "position_template".into(),
+                        json!("{{ value_json.position }}"),
+                    );
+                }
+            }
+            StatusFormat::Text => {
+                document.insert("state_topic".into(), json!(status_topic));
+                document.insert("value_template".into(), json!("{{ value | lower }}"));
+            }
+            StatusFormat::Position => {
+                document.insert("position_topic".into(), json!(status_topic));
+            }
+        },
+        None => {
+            document.insert("optimistic".into(), json!(true));
+        }
+    }
+
+    let tilt = blind
+        .tilt
+        .as_ref()
+        .filter(|_| capabilities.tilt)
+        .and_then(|tilt| {
+            let topic = blind.tilt_command_topic()?;
+            Some((
+                tilt,
+                encoder_for(tilt.payload_format).discovery_tilt(topic)?,
+            ))
+        });
+    if let Some((tilt, commands)) = tilt {
+        document.insert(
+            "tilt_command_topic".into(),
+            json!(commands.tilt_command_topic),
+        );
+        if let Some(template) = commands.tilt_command_template {
+            document.insert("tilt_command_template".into(), json!(template));
+        }
+        document.insert("tilt_min".into(), json!(0));
+        document.insert("tilt_max".into(), json!(100));
+        document.insert("tilt_opened_value".into(), json!(tilt.open_value));
+        document.insert("tilt_closed_value".into(), json!(tilt.closed_value));
+        if let Some(status_topic) = &tilt.status_topic {
+            document.insert("tilt_status_topic".into(), json!(status_topic));
+            document.insert(
+                "tilt_status_template".into(),
+                json!("{{ value_json.tilt if value_json is defined else value }}"),
+            );
+        }
+    }
+
+    document.insert("device".into(), device);
+    Value::Object(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PayloadFormat, TiltConfig};
+    use rumqttc::{AsyncClient, MqttOptions, Publish, QoS};
+
+    fn venetian_blind() -> BlindConfig {
+        BlindConfig {
+            id: "living.venetian".to_string(),
+            name: "Veneciana Sala".to_string(),
+            room: "living".to_string(),
+            mqtt_topic: "zigbee2mqtt/living_venetian".to_string(),
+            device_type: "zigbee_cover".to_string(),
+            enabled: true,
+            battery_topic: Some("zigbee2mqtt/living_venetian".to_string()),
+            status_topic: Some("zigbee2mqtt/living_venetian".to_string()),
+            tilt: Some(TiltConfig {
+                command_topic: None,
+                status_topic: None,
+                payload_format: PayloadFormat::Zigbee2mqtt,
+                open_value: 50,
+                closed_value: 0,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_discovery_documents() {
+        let config = AppConfig::default();
+        let documents = discovery_documents(&config, &venetian_blind());
+        assert_eq!(documents.len(), 2);
+
+        let cover = &documents[0];
+        assert_eq!(cover.topic, "homeassistant/cover/living_venetian/config");
+        assert_eq!(cover.payload["unique_id"], "tabi_living.venetian");
+        assert_eq!(
+            cover.payload["command_topic"],
+            "zigbee2mqtt/living_venetian/set"
+        );
+        assert_eq!(cover.payload["payload_open"], r#"{"state":"OPEN"}"#);
+        assert_eq!(
+            cover.payload["set_position_template"],
+            r#"{"position": {{ position }}}"#
+        );
+        assert_eq!(
+            cover.payload["position_topic"],
+            "zigbee2mqtt/living_venetian"
+        );
+        assert_eq!(
+            cover.payload["tilt_command_topic"],
+            "zigbee2mqtt/living_venetian/set"
+        );
+        assert_eq!(cover.payload["device"]["suggested_area"], "living");
+
+        let battery = &documents[1];
+        assert_eq!(
+            battery.topic,
+            "homeassistant/sensor/living_venetian_battery/config"
+        );
+        assert_eq!(battery.payload["device_class"], "battery");
+    }
+
+    #[test]
+    fn test_relay_blind_without_position_or_status() {
+        let config = AppConfig::default();
+        let blind = BlindConfig {
+            id: "relay".to_string(),
+            mqtt_topic: "home/relay".to_string(),
+            device_type: "relay_blind".to_string(),
+            enabled: true,
+            travel_time_open_secs: Some(20.0),
+            travel_time_close_secs: Some(20.0),
+            ..Default::default()
+        };
+
+        let documents = discovery_documents(&config, &blind);
+        assert_eq!(documents.len(), 1);
+        let cover = documents[0].payload.as_object().unwrap();
+        assert_eq!(cover["payload_stop"], "STOP");
+        assert_eq!(cover["optimistic"], true);
+        assert!(!cover.contains_key("set_position_topic"));
+        assert!(!cover.contains_key("tilt_command_topic"));
+    }
+
+    #[tokio::test]
+    async fn test_orphan_detection() {
+        let mut config = AppConfig::default();
+        config.blinds[1].enabled = false;
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let discovery = HomeAssistantDiscovery::new(MqttService::new(client), config);
+
+        let message = |topic: &str, payload: &str| {
+            MqttMessage::from(Publish::new(topic, QoS::AtMostOnce, payload.to_string()))
+        };
+        let ours = r#"{"unique_id": "tabi_x"}"#;
+
+        assert!(!discovery.is_orphan(&message("homeassistant/cover/blind_001/config", ours)));
+        assert!(discovery.is_orphan(&message("homeassistant/cover/blind_002/config", ours)));
+        assert!(discovery.is_orphan(&message("homeassistant/cover/deleted/config", ours)));
+        assert!(!discovery.is_orphan(&message(
+            "homeassistant/cover/other/config",
+            r#"{"unique_id": "other_cover"}"#
+        )));
+        assert!(!discovery.is_orphan(&message("homeassistant/cover/deleted/config", "")));
+    }
+}
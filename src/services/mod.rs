@@ -1,5 +1,25 @@
+pub mod acknowledgement;
+pub mod availability;
+pub mod battery_monitor;
 pub mod blind_service;
+pub mod broker_stats;
+pub mod command_ingress;
+#[cfg(feature = "embedded-broker")]
+pub mod embedded_broker;
+pub mod home_assistant;
+pub mod motor_protection;
+pub mod mqtt_api;
+pub mod mqtt_event_loop;
+pub mod mqtt_inspector;
 pub mod mqtt_service;
+pub mod mqtt_tls;
+pub mod mqtt_transport;
+pub mod outbox;
+pub mod payload_encoder;
+pub mod position_estimator;
+pub mod staggered_batch;
+pub mod state_store;
+pub mod zigbee2mqtt_bridge;
 
 pub use blind_service::BlindService;
 pub use mqtt_service::MqttService;
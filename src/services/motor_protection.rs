@@ -0,0 +1,282 @@
+use crate::config::MotorProtection;
+use crate::errors::AppError;
+use crate::services::position_estimator::Direction;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct MotorHistory {
+    /// Inicio de los movimientos dentro de la ventana
+    moves: VecDeque<DateTime<Utc>>,
+    /// Sentido y hora del último movimiento
+    last_direction: Option<(Direction, DateTime<Utc>)>,
+    cooldown_until: Option<DateTime<Utc>>,
+}
+
+/// Aplica los límites de `MotorProtection` a cada persiana: cada movimiento se
+/// reserva antes de publicarlo y se libera si no llega a enviarse
+#[derive(Clone, Default)]
+pub struct MotorGuard {
+    histories: Arc<Mutex<HashMap<String, MotorHistory>>>,
+}
+
+/// Movimiento admitido por `MotorGuard::reserve`, pendiente de enviarse
+#[derive(Debug)]
+pub struct MoveReservation {
+    direction: Direction,
+    at: DateTime<Utc>,
+    /// Último sentido antes de la reserva, para restaurarlo al liberarla
+    previous_direction: Option<(Direction, DateTime<Utc>)>,
+}
+
+impl MotorGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Comprueba si los límites permiten el movimiento y, en ese caso, lo cuenta
+    /// ya; si no, devuelve `RateLimited` con la espera necesaria. Si el comando
+    /// no llega a enviarse, la reserva se deshace con `release`.
+    pub async fn reserve(
+        &self,
+        blind_id: &str,
+        direction: Direction,
+        protection: &MotorProtection,
+        now: DateTime<Utc>,
+    ) -> Result<MoveReservation, AppError> {
+        let mut histories = self.histories.lock().await;
+        let history = histories.entry(blind_id.to_string()).or_default();
+        let rejected = |reason: &str, until: DateTime<Utc>| {
+            log::warn!("Blind '{}' command rejected: {}", blind_id, reason);
+            Err(AppError::RateLimited(
+                blind_id.to_string(),
+                reason.to_string(),
+                retry_after_secs(now, until),
+            ))
+        };
+
+        if let Some(until) = history.cooldown_until.filter(|until| *until > now) {
+            return rejected("motor cooling down", until);
+        }
+
+        let window = Duration::seconds(protection.window_secs as i64);
+        while history
+            .moves
+            .front()
+            .is_some_and(|start| *start + window <= now)
+        {
+            history.moves.pop_front();
+        }
+        if protection.max_moves > 0 && history.moves.len() >= protection.max_moves as usize {
+            let until = if protection.cooldown_secs > 0 {
+                let until = now + Duration::seconds(protection.cooldown_secs as i64);
+                history.cooldown_until = Some(until);
+                until
+            } else {
+                history.moves.front().map_or(now, |start| *start + window)
+            };
+            return rejected("too many moves", until);
+        }
+
+        let reversal = Duration::milliseconds(protection.min_reversal_interval_ms as i64);
+        if let Some((last, at)) = history.last_direction {
+            if direction != last && at + reversal > now {
+                return rejected("direction reversed too soon", at + reversal);
+            }
+        }
+
+        history.moves.push_back(now);
+        let previous_direction = history.last_direction.replace((direction, now));
+        Ok(MoveReservation {
+            direction,
+            at: now,
+            previous_direction,
+        })
+    }
+
+    /// Deshace la reserva de un movimiento que no llegó a enviarse
+    pub async fn release(&self, blind_id: &str, reservation: MoveReservation) {
+        let mut histories = self.histories.lock().await;
+        let Some(history) = histories.get_mut(blind_id) else {
+            return;
+        };
+        if let Some(index) = history.moves.iter().rposition(|at| *at == reservation.at) {
+            history.moves.remove(index);
+        }
+        // A later reservation already replaced the direction
+        if history.last_direction == Some((reservation.direction, reservation.at)) {
+            history.last_direction = reservation.previous_direction;
+        }
+    }
+
+    /// Registra un movimiento enviado sin reserva previa (comandos de la cola)
+    pub async fn record(&self, blind_id: &str, direction: Direction, now: DateTime<Utc>) {
+        let mut histories = self.histories.lock().await;
+        let history = histories.entry(blind_id.to_string()).or_default();
+        history.moves.push_back(now);
+        history.last_direction = Some((direction, now));
+    }
+}
+
+/// Segundos hasta `until`, redondeados hacia arriba y como mínimo 1
+fn retry_after_secs(now: DateTime<Utc>, until: DateTime<Utc>) -> u64 {
+    let millis = (until - now).num_milliseconds().max(0) as u64;
+    millis.div_ceil(1000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protection() -> MotorProtection {
+        MotorProtection {
+            min_reversal_interval_ms: 2000,
+            max_moves: 3,
+            window_secs: 60,
+            cooldown_secs: 0,
+        }
+    }
+
+    fn retry_after(result: Result<MoveReservation, AppError>) -> Option<u64> {
+        match result {
+            Err(AppError::RateLimited(_, _, retry_after)) => Some(retry_after),
+            _ => None,
+        }
+    }
+
+    async fn admit(
+        guard: &MotorGuard,
+        blind_id: &str,
+        direction: Direction,
+        protection: &MotorProtection,
+        now: DateTime<Utc>,
+    ) -> Result<MoveReservation, AppError> {
+        guard.reserve(blind_id, direction, protection, now).await
+    }
+
+    #[tokio::test]
+    async fn test_reversals_need_min_interval() {
+        let guard = MotorGuard::new();
+        let start = Utc::now();
+        let at = |millis| start + Duration::milliseconds(millis);
+        let protection = protection();
+
+        assert!(admit(&guard, "b1", Direction::Opening, &protection, at(0))
+            .await
+            .is_ok());
+        // Same direction again is not a reversal
+        assert!(
+            admit(&guard, "b1", Direction::Opening, &protection, at(100))
+                .await
+                .is_ok()
+        );
+        let result = admit(&guard, "b1", Direction::Closing, &protection, at(600)).await;
+        assert_eq!(retry_after(result), Some(2));
+        // Other blinds are tracked separately
+        assert!(
+            admit(&guard, "b2", Direction::Closing, &protection, at(600))
+                .await
+                .is_ok()
+        );
+        assert!(
+            admit(&guard, "b1", Direction::Closing, &protection, at(2100))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_window_and_cooldown() {
+        let guard = MotorGuard::new();
+        let start = Utc::now();
+        let at = |secs| start + Duration::seconds(secs);
+        let protection = MotorProtection {
+            min_reversal_interval_ms: 0,
+            ..protection()
+        };
+
+        for secs in 0..3 {
+            assert!(
+                admit(&guard, "b1", Direction::Opening, &protection, at(secs))
+                    .await
+                    .is_ok()
+            );
+        }
+        // Without cooldown the next move waits for the oldest one to leave the window
+        let result = admit(&guard, "b1", Direction::Opening, &protection, at(10)).await;
+        assert_eq!(retry_after(result), Some(50));
+        assert!(admit(&guard, "b1", Direction::Opening, &protection, at(60))
+            .await
+            .is_ok());
+
+        let protection = MotorProtection {
+            cooldown_secs: 300,
+            ..protection
+        };
+        for secs in 0..3 {
+            assert!(
+                admit(&guard, "b2", Direction::Opening, &protection, at(secs))
+                    .await
+                    .is_ok()
+            );
+        }
+        let result = admit(&guard, "b2", Direction::Opening, &protection, at(10)).await;
+        assert_eq!(retry_after(result), Some(300));
+        // The cooldown holds even once the window has room again
+        let result = admit(&guard, "b2", Direction::Opening, &protection, at(100)).await;
+        assert_eq!(retry_after(result), Some(210));
+        assert!(
+            admit(&guard, "b2", Direction::Opening, &protection, at(310))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_released_moves_do_not_count() {
+        let guard = MotorGuard::new();
+        let start = Utc::now();
+        let at = |secs| start + Duration::seconds(secs);
+        let protection = MotorProtection {
+            max_moves: 1,
+            ..protection()
+        };
+
+        // A move that was reserved but never sent leaves no trace
+        for secs in 0..3 {
+            let reservation = admit(&guard, "b1", Direction::Closing, &protection, at(secs))
+                .await
+                .unwrap();
+            guard.release("b1", reservation).await;
+        }
+        // Not even its direction, so the reversal is allowed
+        assert!(admit(&guard, "b1", Direction::Opening, &protection, at(3))
+            .await
+            .is_ok());
+        let result = admit(&guard, "b1", Direction::Opening, &protection, at(4)).await;
+        assert_eq!(retry_after(result), Some(59));
+    }
+
+    #[tokio::test]
+    async fn test_recorded_moves_count() {
+        let guard = MotorGuard::new();
+        let start = Utc::now();
+        let protection = MotorProtection {
+            max_moves: 1,
+            ..protection()
+        };
+
+        guard.record("b1", Direction::Closing, start).await;
+        let result = admit(
+            &guard,
+            "b1",
+            Direction::Closing,
+            &protection,
+            start + Duration::seconds(1),
+        )
+        .await;
+        assert_eq!(retry_after(result), Some(59));
+    }
+}
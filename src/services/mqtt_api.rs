@@ -0,0 +1,326 @@
+use crate::config::{MqttApiConfig, MqttQos};
+use crate::errors::AppError;
+use crate::models::responses::{ApiReplyMessage, ApiResponse, BlindsStatusResponse};
+use crate::services::blind_service::BlindService;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Consultas disponibles, con el nombre del endpoint HTTP equivalente
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiOperation {
+    BlindsStatus,
+    Rooms,
+    Config,
+    SystemStatus,
+}
+
+impl ApiOperation {
+    pub fn from_name(name: &str) -> Result<Self, AppError> {
+        match name.trim().to_lowercase().as_str() {
+            "get_blinds_status" => Ok(ApiOperation::BlindsStatus),
+            "get_rooms" => Ok(ApiOperation::Rooms),
+            "get_config" => Ok(ApiOperation::Config),
+            "get_system_status" => Ok(ApiOperation::SystemStatus),
+            _ => Err(AppError::ValidationError(format!(
+                "Unknown operation '{}'",
+                name
+            ))),
+        }
+    }
+}
+
+/// Petición recibida en `<request_prefix>/<cliente>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiRequest {
+    pub client: String,
+    pub operation: Result<ApiOperation, String>,
+    pub response_topic: Option<String>,
+    pub correlation_id: Option<String>,
+}
+
+impl ApiRequest {
+    /// Acepta el nombre de la operación en texto (`get_rooms`) o JSON:
+    /// `{"operation": "get_rooms", "response_topic": "...", "correlation_id": "..."}`.
+    /// Con MQTT v5 también se usan el tema de respuesta y los datos de correlación
+    /// del mensaje.
+    pub fn parse(prefix: &str, message: &MqttMessage) -> Option<Self> {
+        let client = message.topic.strip_prefix(prefix)?.strip_prefix('/')?;
+        if client.is_empty() || client.contains('/') {
+            return None;
+        }
+
+        let payload = message.payload_str();
+        let (operation, response_topic, correlation_id) =
+            match serde_json::from_str::<Value>(payload.trim()) {
+                Ok(Value::Object(fields)) => {
+                    let field =
+                        |name: &str| fields.get(name).and_then(Value::as_str).map(str::to_string);
+                    let operation = field("operation").ok_or_else(|| {
+                        AppError::ValidationError("Request needs 'operation'".to_string())
+                    });
+                    (operation, field("response_topic"), field("correlation_id"))
+                }
+                _ => (Ok(payload.trim().to_string()), None, None),
+            };
+
+        let correlation_id = correlation_id.or_else(|| {
+            message
+                .correlation_data
+                .as_ref()
+                .and_then(|data| String::from_utf8(data.clone()).ok())
+        });
+        Some(Self {
+            client: client.to_string(),
+            operation: operation
+                .and_then(|name| ApiOperation::from_name(&name))
+                .map_err(|e| e.to_string()),
+            response_topic: message.response_topic.clone().or(response_topic),
+            correlation_id,
+        })
+    }
+}
+
+/// Responde por MQTT a las mismas consultas que `get_blinds_status`, `get_rooms`,
+/// `get_config` y `get_system_status`, con los mismos tipos de respuesta
+#[derive(Clone)]
+pub struct MqttApi {
+    blind_service: BlindService,
+    mqtt_service: MqttService,
+    config: MqttApiConfig,
+}
+
+impl MqttApi {
+    pub fn new(
+        blind_service: BlindService,
+        mqtt_service: MqttService,
+        config: MqttApiConfig,
+    ) -> Self {
+        Self {
+            blind_service,
+            mqtt_service,
+            config,
+        }
+    }
+
+    pub async fn start(&self) -> Result<(), AppError> {
+        let mut receiver = self.mqtt_service.subscribe_messages();
+        self.mqtt_service
+            .subscribe_with_qos(
+                &format!("{}/+", self.config.request_prefix),
+                MqttQos::AtLeastOnce,
+            )
+            .await?;
+
+        let api = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => api.handle_message(&message).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("MQTT API lagged, {} MQTT messages skipped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn handle_message(&self, message: &MqttMessage) {
+        let Some(request) = ApiRequest::parse(&self.config.request_prefix, message) else {
+            return;
+        };
+        // A retained request would be answered again on every reconnect
+        if message.retain {
+            log::debug!("Ignoring retained MQTT API request on {}", message.topic);
+            return;
+        }
+
+        let topic = self.reply_topic(&request);
+        let response = match request.operation {
+            Ok(operation) => ApiResponse::success(self.run(operation).await),
+            Err(e) => {
+                log::warn!("MQTT API request from '{}' failed: {}", request.client, e);
+                ApiResponse::error(e)
+            }
+        };
+        let reply = ApiReplyMessage {
+            correlation_id: request.correlation_id,
+            response,
+        };
+        match serde_json::to_string(&reply) {
+            Ok(payload) => {
+                let correlation_data = message.correlation_data.clone();
+                if let Err(e) = self
+                    .mqtt_service
+                    .publish_reply(&topic, &payload, correlation_data)
+                    .await
+                {
+                    log::error!("Failed to publish MQTT API reply to '{}': {}", topic, e);
+                }
+            }
+            Err(e) => log::error!("Failed to encode MQTT API reply: {}", e),
+        }
+    }
+
+    /// Tema indicado por el cliente si cuelga de `response_prefix`; si no,
+    /// `<response_prefix>/<cliente>`. Así una petición no puede publicar en los
+    /// temas de las persianas ni en los de comandos.
+    fn reply_topic(&self, request: &ApiRequest) -> String {
+        let response_tree = format!("{}/", self.config.response_prefix);
+        match &request.response_topic {
+            Some(topic)
+                if topic.len() > response_tree.len()
+                    && topic.starts_with(&response_tree)
+                    && !topic.contains(['+', '#']) =>
+            {
+                topic.clone()
+            }
+            Some(topic) => {
+                log::warn!(
+                    "MQTT API request from '{}' asked for reply topic '{}' outside {}",
+                    request.client,
+                    topic,
+                    self.config.response_prefix
+                );
+                format!("{}/{}", self.config.response_prefix, request.client)
+            }
+            None => format!("{}/{}", self.config.response_prefix, request.client),
+        }
+    }
+
+    async fn run(&self, operation: ApiOperation) -> Value {
+        let service = &self.blind_service;
+        let data = match operation {
+            ApiOperation::BlindsStatus => serde_json::to_value(BlindsStatusResponse {
+                rooms: service.get_blinds_status().await,
+            }),
+            ApiOperation::Rooms => serde_json::to_value(service.get_rooms()),
+            ApiOperation::Config => serde_json::to_value(service.get_config().await),
+            ApiOperation::SystemStatus => serde_json::to_value(service.get_system_status().await),
+        };
+        // The response types always serialize to JSON
+        data.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use rumqttc::{AsyncClient, MqttOptions};
+    use std::sync::Arc;
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            ..MqttMessage::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        let prefix = "tabi/api/request";
+        let request =
+            ApiRequest::parse(prefix, &message("tabi/api/request/panel", "get_rooms")).unwrap();
+        assert_eq!(request.client, "panel");
+        assert_eq!(request.operation, Ok(ApiOperation::Rooms));
+        assert_eq!(request.response_topic, None);
+
+        let request = ApiRequest::parse(
+            prefix,
+            &message(
+                "tabi/api/request/panel",
+                r#"{"operation": "get_config", "response_topic": "panel/in", "correlation_id": "7"}"#,
+            ),
+        )
+        .unwrap();
+        assert_eq!(request.operation, Ok(ApiOperation::Config));
+        assert_eq!(request.response_topic.as_deref(), Some("panel/in"));
+        assert_eq!(request.correlation_id.as_deref(), Some("7"));
+
+        // MQTT v5 properties take precedence over the payload
+        let request = ApiRequest::parse(
+            prefix,
+            &MqttMessage {
+                correlation_data: Some(b"abc".to_vec()),
+                response_topic: Some("panel/v5".to_string()),
+                ..message(
+                    "tabi/api/request/panel",
+                    r#"{"operation": "get_system_status", "response_topic": "panel/in"}"#,
+                )
+            },
+        )
+        .unwrap();
+        assert_eq!(request.response_topic.as_deref(), Some("panel/v5"));
+        assert_eq!(request.correlation_id.as_deref(), Some("abc"));
+
+        let request =
+            ApiRequest::parse(prefix, &message("tabi/api/request/panel", "reboot")).unwrap();
+        assert!(request.operation.is_err());
+        assert!(ApiRequest::parse(prefix, &message("tabi/api/request/a/b", "get_rooms")).is_none());
+        assert!(ApiRequest::parse(prefix, &message("tabi/api/request", "get_rooms")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reply_topic_and_operations() {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let blind_service = BlindService::new(mqtt_service.clone(), Arc::new(AppConfig::default()));
+        let api = MqttApi::new(
+            blind_service,
+            mqtt_service.clone(),
+            MqttApiConfig::default(),
+        );
+
+        let request = |response_topic: &str| ApiRequest {
+            client: "panel".to_string(),
+            operation: Ok(ApiOperation::Rooms),
+            response_topic: Some(response_topic.to_string()),
+            correlation_id: None,
+        };
+        assert_eq!(
+            api.reply_topic(&request("tabi/api/response/panel/in")),
+            "tabi/api/response/panel/in"
+        );
+        // Replies never leave the response tree
+        for topic in [
+            "panel/in",
+            "home/blinds/bedroom/control",
+            "tabi/cmd/all",
+            "tabi/api/request/panel",
+            "tabi/api/response/",
+            "tabi/api/response/#",
+            "tabi/api/responses",
+        ] {
+            assert_eq!(api.reply_topic(&request(topic)), "tabi/api/response/panel");
+        }
+
+        // Retained requests get no reply
+        let published = || async {
+            mqtt_service
+                .get_client_info()
+                .await
+                .traffic
+                .messages_published
+        };
+        let mut retained = message("tabi/api/request/panel", "get_rooms");
+        retained.retain = true;
+        api.handle_message(&retained).await;
+        assert_eq!(published().await, 0);
+        api.handle_message(&message("tabi/api/request/panel", "get_rooms"))
+            .await;
+        assert_eq!(published().await, 1);
+
+        let rooms = api.run(ApiOperation::Rooms).await;
+        assert!(rooms["rooms"].is_array());
+        let status = api.run(ApiOperation::BlindsStatus).await;
+        assert!(status["rooms"].is_object());
+        let system = api.run(ApiOperation::SystemStatus).await;
+        assert_eq!(system["total_blinds"], AppConfig::default().blinds.len());
+    }
+}
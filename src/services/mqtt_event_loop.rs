@@ -0,0 +1,328 @@
+use crate::config::MqttConfig;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use rand::Rng;
+use rumqttc::v5::mqttbytes::v5::Packet;
+use rumqttc::v5::StateError;
+use rumqttc::{v5, ConnectionError, Event, EventLoop, Incoming, Outgoing};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Event loop de rumqttc de la versión de protocolo configurada
+pub enum MqttEventLoop {
+    V4(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+impl From<EventLoop> for MqttEventLoop {
+    fn from(eventloop: EventLoop) -> Self {
+        MqttEventLoop::V4(Box::new(eventloop))
+    }
+}
+
+impl From<v5::EventLoop> for MqttEventLoop {
+    fn from(eventloop: v5::EventLoop) -> Self {
+        MqttEventLoop::V5(Box::new(eventloop))
+    }
+}
+
+/// Evento del broker, común a v4 y v5
+#[derive(Debug)]
+enum LoopEvent {
+    ConnAck(String),
+    Message(MqttMessage),
+    /// Publicación v5 enviada con su packet id
+    Published(u16),
+    /// PUBACK correcto de una publicación v5
+    PubAck(u16),
+    Disconnected(&'static str),
+    Other(String),
+}
+
+#[derive(Debug)]
+enum LoopError {
+    RequestsDone,
+    Connection {
+        message: String,
+        /// Código de motivo v5 que provocó el error, si lo hay
+        reason_code: Option<String>,
+        /// El broker rechazó una publicación pendiente
+        publish_rejected: bool,
+    },
+}
+
+impl MqttEventLoop {
+    /// Publicaciones QoS 1/2 enviadas y aún sin confirmar por el broker
+    fn in_flight(&self) -> u16 {
+        match self {
+            MqttEventLoop::V4(eventloop) => eventloop.state.inflight(),
+            MqttEventLoop::V5(eventloop) => eventloop.state.inflight(),
+        }
+    }
+
+    /// Packet ids de las publicaciones v5 que se reenviarán al reconectar
+    fn retransmissions(&self) -> Vec<u16> {
+        match self {
+            MqttEventLoop::V4(_) => Vec::new(),
+            MqttEventLoop::V5(eventloop) => eventloop
+                .pending
+                .iter()
+                .filter_map(|request| match request {
+                    v5::Request::Publish(publish) if publish.pkid != 0 => Some(publish.pkid),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    async fn poll(&mut self) -> Result<LoopEvent, LoopError> {
+        match self {
+            MqttEventLoop::V4(eventloop) => match eventloop.poll().await {
+                Ok(Event::Incoming(Incoming::ConnAck(connack))) => {
+                    Ok(LoopEvent::ConnAck(format!("{:?}", connack.code)))
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) => {
+                    Ok(LoopEvent::Message(MqttMessage::from(publish)))
+                }
+                Ok(Event::Incoming(Incoming::Disconnect)) => {
+                    Ok(LoopEvent::Disconnected("Disconnect received from broker"))
+                }
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                    Ok(LoopEvent::Disconnected("Client disconnected"))
+                }
+                Ok(event) => Ok(LoopEvent::Other(format!("{:?}", event))),
+                Err(ConnectionError::RequestsDone) => Err(LoopError::RequestsDone),
+                Err(e) => Err(LoopError::Connection {
+                    message: e.to_string(),
+                    reason_code: None,
+                    publish_rejected: false,
+                }),
+            },
+            MqttEventLoop::V5(eventloop) => match eventloop.poll().await {
+                Ok(v5::Event::Incoming(Packet::ConnAck(connack))) => {
+                    Ok(LoopEvent::ConnAck(format!("{:?}", connack.code)))
+                }
+                Ok(v5::Event::Incoming(Packet::Publish(publish))) => {
+                    Ok(LoopEvent::Message(MqttMessage::from(publish)))
+                }
+                Ok(v5::Event::Outgoing(Outgoing::Publish(pkid))) => Ok(LoopEvent::Published(pkid)),
+                Ok(v5::Event::Incoming(Packet::PubAck(puback))) => {
+                    Ok(LoopEvent::PubAck(puback.pkid))
+                }
+                Ok(v5::Event::Incoming(Packet::Disconnect(_))) => {
+                    Ok(LoopEvent::Disconnected("Disconnect received from broker"))
+                }
+                Ok(v5::Event::Outgoing(Outgoing::Disconnect)) => {
+                    Ok(LoopEvent::Disconnected("Client disconnected"))
+                }
+                Ok(event) => Ok(LoopEvent::Other(format!("{:?}", event))),
+                Err(v5::ConnectionError::RequestsDone) => Err(LoopError::RequestsDone),
+                Err(e) => Err(v5_error(e)),
+            },
+        }
+    }
+}
+
+/// Extrae el código de motivo de los errores v5 provocados por el broker
+fn v5_error(error: v5::ConnectionError) -> LoopError {
+    let message = error.to_string();
+    let (reason_code, publish_rejected) = match &error {
+        v5::ConnectionError::MqttState(state) => match state {
+            StateError::PubAckFail { reason } => (Some(format!("{:?}", reason)), true),
+            StateError::SubFail { reason } => (Some(format!("{:?}", reason)), false),
+            StateError::UnsubFail { reason } => (Some(format!("{:?}", reason)), false),
+            StateError::ServerDisconnect { reason_code, .. } => {
+                (Some(format!("{:?}", reason_code)), false)
+            }
+            StateError::ConnFail { reason } => (Some(format!("{:?}", reason)), false),
+            _ => (None, false),
+        },
+        v5::ConnectionError::ConnectionRefused(code) => (Some(format!("{:?}", code)), false),
+        _ => (None, false),
+    };
+    LoopError::Connection {
+        message,
+        reason_code,
+        publish_rejected,
+    }
+}
+
+/// Política de reconexión con backoff exponencial y jitter
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+        }
+    }
+
+    pub fn from_config(config: &MqttConfig) -> Self {
+        Self::new(
+            Duration::from_millis(config.reconnect_initial_delay_ms),
+            Duration::from_secs(config.reconnect_max_delay_secs),
+        )
+    }
+
+    /// Espera máxima (sin jitter) para el intento `attempt`, empezando en 1
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        self.initial_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay)
+    }
+
+    /// Espera con jitter en el rango [base/2, base] para evitar reconexiones sincronizadas
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        let half = base / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+}
+
+/// Lanza la tarea que conduce el event loop de rumqttc (v4 o v5).
+///
+/// El estado de conexión de `MqttService` se actualiza a partir de los eventos
+/// del broker (ConnAck / Disconnect / errores de red). Tras un error la tarea
+/// espera según `policy` antes de volver a sondear, lo que provoca la reconexión.
+pub fn spawn_event_loop(
+    eventloop: impl Into<MqttEventLoop>,
+    mqtt_service: MqttService,
+    policy: ReconnectPolicy,
+) -> JoinHandle<()> {
+    let mut eventloop = eventloop.into();
+    tokio::spawn(async move {
+        println!("🔄 Iniciando gestor de eventos MQTT...");
+        let mut attempt: u32 = 0;
+        let mut in_flight = 0;
+
+        loop {
+            let event = eventloop.poll().await;
+            if eventloop.in_flight() != in_flight {
+                in_flight = eventloop.in_flight();
+                mqtt_service.record_in_flight(in_flight).await;
+            }
+            match event {
+                Ok(LoopEvent::ConnAck(code)) => {
+                    log::info!("MQTT ConnAck received: {}", code);
+                    attempt = 0;
+                    mqtt_service.record_connected().await;
+
+                    // Las suscripciones se renuevan fuera del bucle: el canal de
+                    // peticiones lo vacía este mismo bucle y no debe bloquearse
+                    let service = mqtt_service.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = service.resubscribe_all().await {
+                            log::error!("Failed to restore MQTT subscriptions: {}", e);
+                        }
+                    });
+                }
+                Ok(LoopEvent::Message(message)) => {
+                    mqtt_service.record_incoming(&message).await;
+                    mqtt_service.dispatch_incoming(message);
+                }
+                Ok(LoopEvent::Published(pkid)) => {
+                    mqtt_service.record_outgoing_publish(pkid).await;
+                }
+                Ok(LoopEvent::PubAck(pkid)) => {
+                    mqtt_service.resolve_puback(pkid).await;
+                }
+                Ok(LoopEvent::Disconnected(reason)) => {
+                    mqtt_service.record_disconnected(reason).await;
+                }
+                Ok(LoopEvent::Other(event)) => {
+                    log::trace!("MQTT event: {}", event);
+                }
+                Err(LoopError::RequestsDone) => {
+                    log::info!("MQTT client dropped, stopping event loop");
+                    mqtt_service.record_disconnected("Requests done").await;
+                    mqtt_service.fail_pending_acks(&[], None).await;
+                    break;
+                }
+                Err(LoopError::Connection {
+                    message,
+                    reason_code,
+                    publish_rejected,
+                }) => {
+                    if let Some(reason) = &reason_code {
+                        mqtt_service.record_reason_code(reason).await;
+                    }
+                    // The rejected publish is the one rumqttc no longer retransmits
+                    let rejection = reason_code.as_deref().filter(|_| publish_rejected);
+                    mqtt_service
+                        .fail_pending_acks(&eventloop.retransmissions(), rejection)
+                        .await;
+
+                    attempt = attempt.saturating_add(1);
+                    let delay = policy.delay(attempt);
+                    log::warn!(
+                        "MQTT connection error: {}. Reconnecting in {:?} (attempt {})",
+                        message,
+                        delay,
+                        attempt
+                    );
+                    mqtt_service.record_disconnected(&message).await;
+                    mqtt_service.record_reconnect_attempt(delay).await;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_exponentially_until_max() {
+        let policy = ReconnectPolicy::new(Duration::from_millis(500), Duration::from_secs(10));
+
+        assert_eq!(policy.base_delay(1), Duration::from_millis(500));
+        assert_eq!(policy.base_delay(2), Duration::from_millis(1000));
+        assert_eq!(policy.base_delay(3), Duration::from_millis(2000));
+        assert_eq!(policy.base_delay(6), Duration::from_secs(10));
+        assert_eq!(policy.base_delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_v5_reason_codes() {
+        use rumqttc::v5::mqttbytes::v5::PubAckReason;
+
+        let error = v5_error(v5::ConnectionError::MqttState(StateError::PubAckFail {
+            reason: PubAckReason::NotAuthorized,
+        }));
+        assert!(matches!(
+            error,
+            LoopError::Connection { reason_code: Some(ref reason), publish_rejected: true, .. }
+                if reason == "NotAuthorized"
+        ));
+
+        let error = v5_error(v5::ConnectionError::MqttState(StateError::AwaitPingResp));
+        assert!(matches!(
+            error,
+            LoopError::Connection {
+                reason_code: None,
+                publish_rejected: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_delay_jitter_stays_within_bounds() {
+        let policy = ReconnectPolicy::new(Duration::from_millis(500), Duration::from_secs(10));
+
+        for attempt in 1..10 {
+            let base = policy.base_delay(attempt);
+            let delay = policy.delay(attempt);
+            assert!(delay >= base / 2);
+            assert!(delay <= base);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::config::{MqttQos, SharedConfig};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Sentido de un mensaje respecto al backend
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficDirection {
+    Inbound,
+    Outbound,
+}
+
+/// Mensaje MQTT de una persiana registrado por el inspector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficEntry {
+    pub direction: TrafficDirection,
+    pub blind_id: String,
+    pub topic: String,
+    /// `None` si `redact_payloads` está activado
+    pub payload: Option<String>,
+    pub payload_size: usize,
+    pub qos: MqttQos,
+    pub retain: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Filtros de `/mqtt/messages`; `topic` admite los comodines `+` y `#`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TrafficFilter {
+    pub blind_id: Option<String>,
+    pub topic: Option<String>,
+    pub direction: Option<TrafficDirection>,
+    /// Número máximo de mensajes devueltos (los más recientes)
+    pub limit: Option<usize>,
+}
+
+impl TrafficFilter {
+    fn matches(&self, entry: &TrafficEntry) -> bool {
+        self.blind_id
+            .as_ref()
+            .is_none_or(|blind_id| *blind_id == entry.blind_id)
+            && self
+                .topic
+                .as_ref()
+                .is_none_or(|filter| topic_matches(filter, &entry.topic))
+            && self
+                .direction
+                .is_none_or(|direction| direction == entry.direction)
+    }
+}
+
+/// Comprueba un tema contra un filtro de suscripción MQTT
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// Guarda los últimos mensajes enviados y recibidos en los temas de las
+/// persianas configuradas, para depurar sin un `mosquitto_sub` aparte
+#[derive(Clone)]
+pub struct MqttInspector {
+    config: SharedConfig,
+    entries: Arc<RwLock<VecDeque<TrafficEntry>>>,
+}
+
+impl MqttInspector {
+    pub fn new(config: impl Into<SharedConfig>) -> Self {
+        Self {
+            config: config.into(),
+            entries: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    /// Registra el mensaje si pertenece a alguna persiana
+    pub async fn record(
+        &self,
+        direction: TrafficDirection,
+        topic: &str,
+        payload: &[u8],
+        qos: MqttQos,
+        retain: bool,
+    ) {
+        let config = self.config.snapshot();
+        let inspector = &config.mqtt.inspector;
+        if !inspector.enabled {
+            return;
+        }
+        let Some(blind) = config.get_blind_by_topic(topic) else {
+            return;
+        };
+
+        let entry = TrafficEntry {
+            direction,
+            blind_id: blind.id.clone(),
+            topic: topic.to_string(),
+            payload: (!inspector.redact_payloads)
+                .then(|| String::from_utf8_lossy(payload).into_owned()),
+            payload_size: payload.len(),
+            qos,
+            retain,
+            timestamp: Utc::now(),
+        };
+        let mut entries = self.entries.write().await;
+        entries.push_back(entry);
+        while entries.len() > inspector.capacity {
+            entries.pop_front();
+        }
+    }
+
+    /// Mensajes que cumplen el filtro, del más antiguo al más reciente
+    pub async fn entries(&self, filter: &TrafficFilter) -> Vec<TrafficEntry> {
+        let entries = self.entries.read().await;
+        let mut matching: Vec<_> = entries
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        matching.reverse();
+        matching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches(
+            "home/blinds/+/status",
+            "home/blinds/bedroom/status"
+        ));
+        assert!(topic_matches("home/#", "home/blinds/bedroom/status"));
+        assert!(topic_matches(
+            "home/blinds/bedroom/control",
+            "home/blinds/bedroom/control"
+        ));
+        assert!(!topic_matches(
+            "home/blinds/+/status",
+            "home/blinds/living/window1/status"
+        ));
+        assert!(!topic_matches(
+            "home/blinds/bedroom",
+            "home/blinds/bedroom/control"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_records_blind_topics_and_filters() {
+        let mut config = AppConfig::default();
+        config.mqtt.inspector.capacity = 3;
+        let blind = config.blinds[0].clone();
+        let inspector = MqttInspector::new(config.clone());
+
+        let record = |direction, topic: String| {
+            let inspector = inspector.clone();
+            async move {
+                inspector
+                    .record(direction, &topic, b"OPEN", MqttQos::AtLeastOnce, false)
+                    .await
+            }
+        };
+        record(TrafficDirection::Outbound, "tabi/status".to_string()).await;
+        record(TrafficDirection::Outbound, blind.mqtt_topic.clone()).await;
+        record(
+            TrafficDirection::Outbound,
+            format!("{}/set", blind.mqtt_topic),
+        )
+        .await;
+        record(
+            TrafficDirection::Inbound,
+            blind.status_topic.clone().unwrap(),
+        )
+        .await;
+        let other = &config.blinds[1];
+        record(TrafficDirection::Outbound, other.mqtt_topic.clone()).await;
+
+        // Topics outside the blinds are ignored and the oldest entries dropped
+        let all = inspector.entries(&TrafficFilter::default()).await;
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].topic, format!("{}/set", blind.mqtt_topic));
+        assert_eq!(all[2].blind_id, other.id);
+        assert_eq!(all[0].payload.as_deref(), Some("OPEN"));
+
+        let filter = TrafficFilter {
+            blind_id: Some(blind.id.clone()),
+            direction: Some(TrafficDirection::Outbound),
+            ..TrafficFilter::default()
+        };
+        assert_eq!(inspector.entries(&filter).await.len(), 1);
+        let filter = TrafficFilter {
+            topic: Some("home/blinds/#".to_string()),
+            limit: Some(1),
+            ..TrafficFilter::default()
+        };
+        let latest = inspector.entries(&filter).await;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].blind_id, other.id);
+    }
+
+    #[tokio::test]
+    async fn test_redacted_payloads() {
+        let mut config = AppConfig::default();
+        config.mqtt.inspector.redact_payloads = true;
+        let topic = config.blinds[0].mqtt_topic.clone();
+        let inspector = MqttInspector::new(config);
+
+        inspector
+            .record(
+                TrafficDirection::Outbound,
+                &topic,
+                b"CLOSE",
+                MqttQos::AtMostOnce,
+                true,
+            )
+            .await;
+        let entries = inspector.entries(&TrafficFilter::default()).await;
+        assert_eq!(entries[0].payload, None);
+        assert_eq!(entries[0].payload_size, 5);
+    }
+}
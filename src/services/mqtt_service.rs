@@ -1,43 +1,435 @@
+use crate::config::{MqttProtocolVersion, MqttQos, PublishOptions};
 use crate::errors::AppError;
-use rumqttc::{AsyncClient, QoS};
+use crate::models::responses::{MqttConnectionStats, MqttInfoResponse, MqttTrafficStats};
+use crate::services::broker_stats::BrokerStats;
+use crate::services::mqtt_inspector::{MqttInspector, TrafficDirection};
+use chrono::Utc;
+use rumqttc::v5::mqttbytes::v5::{Filter, PublishProperties};
+use rumqttc::{v5, AsyncClient, Publish, QoS, SubscribeFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
+use tokio::time::Instant;
+
+/// Capacidad del canal de difusión de mensajes entrantes
+const INCOMING_CHANNEL_CAPACITY: usize = 256;
+
+/// Espera máxima del PUBACK de una publicación v5 antes de darla por enviada
+const PUBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Espera entre intentos mientras el canal de peticiones del cliente v5 está lleno
+const REQUEST_CHANNEL_RETRY: Duration = Duration::from_millis(10);
+/// Tiempo máximo de espera a que el canal de peticiones v5 admita una publicación
+const REQUEST_CHANNEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Mensaje PUBLISH recibido del broker
+#[derive(Debug, Clone, Default)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Datos de correlación MQTT v5 (respuestas a nuestros comandos)
+    pub correlation_data: Option<Vec<u8>>,
+    /// Tema de respuesta MQTT v5 indicado por el emisor
+    pub response_topic: Option<String>,
+    pub qos: MqttQos,
+    pub retain: bool,
+}
+
+impl MqttMessage {
+    pub fn payload_str(&self) -> String {
+        String::from_utf8_lossy(&self.payload).into_owned()
+    }
+}
+
+impl From<Publish> for MqttMessage {
+    fn from(publish: Publish) -> Self {
+        Self {
+            topic: publish.topic,
+            payload: publish.payload.to_vec(),
+            qos: publish.qos.into(),
+            retain: publish.retain,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<v5::mqttbytes::v5::Publish> for MqttMessage {
+    fn from(publish: v5::mqttbytes::v5::Publish) -> Self {
+        let properties = publish.properties.unwrap_or_default();
+        Self {
+            topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+            payload: publish.payload.to_vec(),
+            correlation_data: properties.correlation_data.map(|data| data.to_vec()),
+            response_topic: properties.response_topic,
+            qos: match publish.qos {
+                v5::mqttbytes::QoS::AtMostOnce => MqttQos::AtMostOnce,
+                v5::mqttbytes::QoS::AtLeastOnce => MqttQos::AtLeastOnce,
+                v5::mqttbytes::QoS::ExactlyOnce => MqttQos::ExactlyOnce,
+            },
+            retain: publish.retain,
+        }
+    }
+}
+
+/// Cliente rumqttc de la versión de protocolo configurada. Los clientes de
+/// rumqttc se pueden clonar y usar desde varias tareas a la vez.
+#[derive(Clone)]
+pub enum MqttClient {
+    V4(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl From<AsyncClient> for MqttClient {
+    fn from(client: AsyncClient) -> Self {
+        MqttClient::V4(client)
+    }
+}
+
+impl From<v5::AsyncClient> for MqttClient {
+    fn from(client: v5::AsyncClient) -> Self {
+        MqttClient::V5(client)
+    }
+}
+
+/// Metadatos de un comando; con MQTT v5 viajan como propiedades del mensaje
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandProperties {
+    pub request_id: String,
+    /// Origen del comando (`api`, ...)
+    pub issuer: String,
+    pub correlation_data: Vec<u8>,
+    pub response_topic: Option<String>,
+    pub message_expiry_secs: Option<u32>,
+}
+
+impl From<CommandProperties> for PublishProperties {
+    fn from(properties: CommandProperties) -> Self {
+        PublishProperties {
+            message_expiry_interval: properties.message_expiry_secs,
+            response_topic: properties.response_topic,
+            correlation_data: Some(properties.correlation_data.into()),
+            user_properties: vec![
+                ("request_id".to_string(), properties.request_id),
+                ("issuer".to_string(), properties.issuer),
+            ],
+            ..PublishProperties::default()
+        }
+    }
+}
+
+/// Resultado del PUBACK de una publicación v5 (`Err` con el código de motivo)
+type PubAckResult = Result<(), String>;
+
+/// Publicaciones v5 a la espera de su PUBACK
+#[derive(Default)]
+struct PendingAcks {
+    /// Publicaciones entregadas al event loop que aún no tienen packet id, en el
+    /// orden del canal de peticiones; `None` si nadie espera su PUBACK
+    unassigned: VecDeque<Option<oneshot::Sender<PubAckResult>>>,
+    /// Reenvíos de la sesión anterior que el event loop publicará antes que
+    /// las peticiones nuevas
+    retransmissions: usize,
+    by_pkid: HashMap<u16, oneshot::Sender<PubAckResult>>,
+}
 
 pub struct MqttService {
-    client: Arc<Mutex<AsyncClient>>,
-    connected: Arc<Mutex<bool>>,
+    client: MqttClient,
+    stats: Arc<Mutex<MqttConnectionStats>>,
+    traffic: Arc<Mutex<MqttTrafficStats>>,
+    subscriptions: Arc<Mutex<BTreeMap<String, QoS>>>,
+    incoming: broadcast::Sender<MqttMessage>,
+    connection: Arc<watch::Sender<bool>>,
+    /// Publicaciones v5 pendientes de PUBACK
+    pending_acks: Arc<std::sync::Mutex<PendingAcks>>,
+    /// Registro del tráfico de las persianas
+    inspector: Option<MqttInspector>,
+    /// Estadísticas `$SYS` del broker
+    broker_stats: Option<BrokerStats>,
 }
 
 impl MqttService {
-    pub fn new(client: Arc<Mutex<AsyncClient>>) -> Self {
+    pub fn new(client: impl Into<MqttClient>) -> Self {
+        let (incoming, _) = broadcast::channel(INCOMING_CHANNEL_CAPACITY);
         Self {
-            client,
-            connected: Arc::new(Mutex::new(false)),
+            client: client.into(),
+            stats: Arc::new(Mutex::new(MqttConnectionStats::default())),
+            traffic: Arc::new(Mutex::new(MqttTrafficStats::default())),
+            subscriptions: Arc::new(Mutex::new(BTreeMap::new())),
+            incoming,
+            connection: Arc::new(watch::channel(false).0),
+            pending_acks: Arc::new(std::sync::Mutex::new(PendingAcks::default())),
+            inspector: None,
+            broker_stats: None,
+        }
+    }
+
+    /// Registra en `inspector` los mensajes enviados y recibidos de las persianas
+    pub fn with_inspector(mut self, inspector: MqttInspector) -> Self {
+        self.inspector = Some(inspector);
+        self
+    }
+
+    pub fn inspector(&self) -> Option<&MqttInspector> {
+        self.inspector.as_ref()
+    }
+
+    /// Incluye en `/mqtt/info` las estadísticas `$SYS` que recoja `broker_stats`
+    pub fn with_broker_stats(mut self, broker_stats: BrokerStats) -> Self {
+        self.broker_stats = Some(broker_stats);
+        self
+    }
+
+    /// Registra un mensaje recibido del broker
+    pub async fn record_incoming(&self, message: &MqttMessage) {
+        self.traffic.lock().await.messages_received += 1;
+        if let Some(inspector) = &self.inspector {
+            inspector
+                .record(
+                    TrafficDirection::Inbound,
+                    &message.topic,
+                    &message.payload,
+                    message.qos,
+                    message.retain,
+                )
+                .await;
+        }
+    }
+
+    pub fn protocol_version(&self) -> MqttProtocolVersion {
+        match self.client {
+            MqttClient::V4(_) => MqttProtocolVersion::V4,
+            MqttClient::V5(_) => MqttProtocolVersion::V5,
         }
     }
 
     pub async fn publish_command(&self, topic: &str, payload: &str) -> Result<(), AppError> {
-        let client = self.client.lock().await;
+        self.publish(topic, payload, PublishOptions::default(), None)
+            .await
+    }
+
+    /// Publica un comando con su QoS, retención y metadatos (solo se envían con MQTT v5)
+    pub async fn publish_command_with_properties(
+        &self,
+        topic: &str,
+        payload: &str,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<(), AppError> {
+        self.publish(topic, payload, options, Some(properties.into()))
+            .await
+    }
 
-        client
-            .publish(topic, QoS::AtLeastOnce, false, payload)
+    /// Publica la respuesta a una petición; con MQTT v5 devuelve sus datos de correlación
+    pub async fn publish_reply(
+        &self,
+        topic: &str,
+        payload: &str,
+        correlation_data: Option<Vec<u8>>,
+    ) -> Result<(), AppError> {
+        let properties = correlation_data.map(|data| PublishProperties {
+            correlation_data: Some(data.into()),
+            ..PublishProperties::default()
+        });
+        self.publish(topic, payload, PublishOptions::default(), properties)
             .await
-            .map_err(|e| {
-                log::error!("MQTT publish failed for topic '{}': {}", topic, e);
-                AppError::MqttError(e)
-            })?;
+    }
+
+    /// Publica un mensaje retenido; un payload vacío borra el mensaje retenido del tema
+    pub async fn publish_retained(&self, topic: &str, payload: &str) -> Result<(), AppError> {
+        let options = PublishOptions {
+            retain: true,
+            ..PublishOptions::default()
+        };
+        self.publish(topic, payload, options, None).await
+    }
+
+    async fn publish(
+        &self,
+        topic: &str,
+        payload: &str,
+        options: PublishOptions,
+        properties: Option<PublishProperties>,
+    ) -> Result<(), AppError> {
+        let result = self.send_publish(topic, payload, options, properties).await;
+        let mut traffic = self.traffic.lock().await;
+        match result {
+            Ok(()) => traffic.messages_published += 1,
+            Err(_) => traffic.publish_errors += 1,
+        }
+        result
+    }
+
+    async fn send_publish(
+        &self,
+        topic: &str,
+        payload: &str,
+        options: PublishOptions,
+        properties: Option<PublishProperties>,
+    ) -> Result<(), AppError> {
+        let PublishOptions { qos, retain } = options;
+        match &self.client {
+            MqttClient::V4(client) => {
+                client
+                    .publish(topic, qos.into(), retain, payload)
+                    .await
+                    .map_err(|e| {
+                        log::error!("MQTT publish failed for topic '{}': {}", topic, e);
+                        AppError::from(e)
+                    })?;
+            }
+            MqttClient::V5(client) => {
+                // Solo QoS 1 recibe PUBACK: con QoS 0 no hay respuesta y con QoS 2
+                // el broker responde con PUBREC. Sin conexión no se espera.
+                let awaits_puback = qos == MqttQos::AtLeastOnce && self.is_connected().await;
+                let (sender, ack) = oneshot::channel();
+                let mut waiter = awaits_puback.then_some(sender);
+                let qos = v5_qos(qos.into());
+                let deadline = Instant::now() + REQUEST_CHANNEL_TIMEOUT;
+                loop {
+                    // El packet id se asigna en el event loop en el orden del canal:
+                    // la espera se registra en la misma sección crítica en la que la
+                    // petición entra en él, sin bloquear a las demás publicaciones
+                    let result = {
+                        let mut pending = self.pending_acks();
+                        let result = match &properties {
+                            Some(properties) => client.try_publish_with_properties(
+                                topic,
+                                qos,
+                                retain,
+                                payload.to_string(),
+                                properties.clone(),
+                            ),
+                            None => client.try_publish(topic, qos, retain, payload.to_string()),
+                        };
+                        if result.is_ok() {
+                            pending.unassigned.push_back(waiter.take());
+                        }
+                        result
+                    };
+                    match result {
+                        Ok(()) => break,
+                        // Request channel full: wait for the event loop to drain it
+                        Err(v5::ClientError::TryRequest(_)) if Instant::now() < deadline => {
+                            tokio::time::sleep(REQUEST_CHANNEL_RETRY).await;
+                        }
+                        Err(e) => {
+                            log::error!("MQTT publish failed for topic '{}': {}", topic, e);
+                            return Err(e.into());
+                        }
+                    }
+                }
+                if awaits_puback {
+                    self.await_puback(topic, ack).await?;
+                }
+            }
+        }
 
+        if let Some(inspector) = &self.inspector {
+            inspector
+                .record(
+                    TrafficDirection::Outbound,
+                    topic,
+                    payload.as_bytes(),
+                    qos,
+                    retain,
+                )
+                .await;
+        }
         log::info!("MQTT command sent - Topic: {}, Payload: {}", topic, payload);
         Ok(())
     }
 
+    /// Espera el PUBACK v5 para devolver el código de motivo si el broker rechaza
+    /// el mensaje
+    async fn await_puback(
+        &self,
+        topic: &str,
+        ack: oneshot::Receiver<PubAckResult>,
+    ) -> Result<(), AppError> {
+        match tokio::time::timeout(PUBACK_TIMEOUT, ack).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(reason))) => {
+                log::error!("MQTT broker rejected publish to '{}': {}", topic, reason);
+                Err(AppError::MqttRejected(topic.to_string(), reason))
+            }
+            Ok(Err(_)) => {
+                log::warn!("MQTT connection lost before PUBACK for '{}'", topic);
+                Err(AppError::MqttError(format!(
+                    "Connection lost before PUBACK for {}",
+                    topic
+                )))
+            }
+            Err(_) => {
+                log::warn!(
+                    "No PUBACK received for '{}' within {:?}",
+                    topic,
+                    PUBACK_TIMEOUT
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Asocia el packet id de una publicación v5 enviada por el event loop a la
+    /// siguiente petición pendiente (los reenvíos de la sesión anterior van antes)
+    pub async fn record_outgoing_publish(&self, pkid: u16) {
+        let mut pending = self.pending_acks();
+        if pending.retransmissions > 0 {
+            pending.retransmissions -= 1;
+            return;
+        }
+        if let Some(Some(sender)) = pending.unassigned.pop_front() {
+            pending.by_pkid.insert(pkid, sender);
+        }
+    }
+
+    /// Resuelve la publicación v5 confirmada por un PUBACK correcto
+    pub async fn resolve_puback(&self, pkid: u16) {
+        let sender = self.pending_acks().by_pkid.remove(&pkid);
+        if let Some(sender) = sender {
+            // El emisor puede haber dejado de esperar
+            let _ = sender.send(Ok(()));
+        }
+    }
+
+    /// Tras perder la conexión ninguna espera recibirá su PUBACK. `retransmitted`
+    /// son los packet ids que el event loop reenviará al reconectar; con
+    /// `rejection`, las publicaciones que no están entre ellos son las que el
+    /// broker acaba de rechazar con ese código de motivo.
+    pub async fn fail_pending_acks(&self, retransmitted: &[u16], rejection: Option<&str>) {
+        let mut pending = self.pending_acks();
+        for (pkid, sender) in pending.by_pkid.drain() {
+            if let Some(reason) = rejection.filter(|_| !retransmitted.contains(&pkid)) {
+                let _ = sender.send(Err(reason.to_string()));
+            }
+        }
+        // Las peticiones aún sin enviar siguen en orden, pero nadie las espera
+        for waiter in pending.unassigned.iter_mut() {
+            *waiter = None;
+        }
+        pending.retransmissions = retransmitted.len();
+    }
+
+    /// Publicaciones v5 pendientes; el bloqueo nunca se mantiene a través de un `await`
+    fn pending_acks(&self) -> std::sync::MutexGuard<'_, PendingAcks> {
+        self.pending_acks
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Registra el último código de motivo v5 recibido del broker
+    pub async fn record_reason_code(&self, reason: &str) {
+        self.stats.lock().await.last_reason_code = Some(reason.to_string());
+    }
+
     pub async fn is_connected(&self) -> bool {
-        *self.connected.lock().await
+        *self.connection.borrow()
     }
 
     pub async fn set_connected(&self, status: bool) {
-        let mut connected = self.connected.lock().await;
-        *connected = status;
+        self.connection.send_replace(status);
 
         if status {
             log::info!("MQTT connection established");
@@ -46,47 +438,229 @@ impl MqttService {
         }
     }
 
+    /// Registra un ConnAck recibido del broker
+    pub async fn record_connected(&self) {
+        {
+            let mut stats = self.stats.lock().await;
+            if stats.total_connections > 0 {
+                stats.reconnects += 1;
+            }
+            stats.total_connections += 1;
+            stats.consecutive_failures = 0;
+            stats.connected_since = Some(Utc::now());
+            stats.next_retry_delay_ms = None;
+        }
+        self.set_connected(true).await;
+    }
+
+    /// Registra la pérdida de la conexión (error de red o DISCONNECT)
+    pub async fn record_disconnected(&self, reason: &str) {
+        let was_connected = self.is_connected().await;
+        {
+            let mut stats = self.stats.lock().await;
+            if was_connected {
+                stats.last_disconnect = Some(Utc::now());
+            }
+            stats.connected_since = None;
+            stats.last_error = Some(reason.to_string());
+        }
+        if was_connected {
+            self.set_connected(false).await;
+        }
+    }
+
+    /// Registra un intento de reconexión fallido y la espera hasta el siguiente
+    pub async fn record_reconnect_attempt(&self, delay: std::time::Duration) {
+        let mut stats = self.stats.lock().await;
+        stats.reconnect_attempts += 1;
+        stats.consecutive_failures += 1;
+        stats.next_retry_delay_ms = Some(delay.as_millis() as u64);
+    }
+
+    /// Receptor que se actualiza con cada cambio del estado de conexión
+    pub fn watch_connection(&self) -> watch::Receiver<bool> {
+        self.connection.subscribe()
+    }
+
+    /// Registra las publicaciones pendientes de confirmación en el event loop
+    pub async fn record_in_flight(&self, in_flight: u16) {
+        self.traffic.lock().await.in_flight = in_flight;
+    }
+
+    pub async fn get_connection_stats(&self) -> MqttConnectionStats {
+        self.stats.lock().await.clone()
+    }
+
+    /// Suscribe a un tema. La suscripción queda registrada y se renueva tras
+    /// cada reconexión; si no hay conexión se envía al recibir el ConnAck.
     pub async fn subscribe_to_topic(&self, topic: &str) -> Result<(), AppError> {
-        let client = self.client.lock().await;
+        self.subscribe_with_qos(topic, MqttQos::AtMostOnce).await
+    }
 
-        client
-            .subscribe(topic, QoS::AtMostOnce)
+    /// Igual que `subscribe_to_topic`, con el QoS indicado
+    pub async fn subscribe_with_qos(&self, topic: &str, qos: MqttQos) -> Result<(), AppError> {
+        let qos = QoS::from(qos);
+        self.subscriptions
+            .lock()
             .await
-            .map_err(|e| {
-                log::error!("MQTT subscribe failed for topic '{}': {}", topic, e);
-                AppError::MqttError(e)
-            })?;
+            .insert(topic.to_string(), qos);
+
+        if !self.is_connected().await {
+            log::debug!("MQTT subscription to '{}' deferred until connected", topic);
+            return Ok(());
+        }
+
+        let result = match &self.client {
+            MqttClient::V4(client) => client.subscribe(topic, qos).await.map_err(AppError::from),
+            MqttClient::V5(client) => client
+                .subscribe(topic, v5_qos(qos))
+                .await
+                .map_err(AppError::from),
+        };
+        result.map_err(|e| {
+            log::error!("MQTT subscribe failed for topic '{}': {}", topic, e);
+            e
+        })?;
 
         log::info!("Subscribed to MQTT topic: {}", topic);
         Ok(())
     }
 
-    pub async fn unsubscribe_from_topic(&self, topic: &str) -> Result<(), AppError> {
-        let client = self.client.lock().await;
+    /// Vuelve a enviar todas las suscripciones registradas (tras un ConnAck)
+    pub async fn resubscribe_all(&self) -> Result<(), AppError> {
+        let subscriptions = self.subscriptions.lock().await.clone();
+        if subscriptions.is_empty() {
+            return Ok(());
+        }
+
+        let count = subscriptions.len();
+        let result = match &self.client {
+            MqttClient::V4(client) => {
+                let filters = subscriptions
+                    .into_iter()
+                    .map(|(topic, qos)| SubscribeFilter::new(topic, qos));
+                client.subscribe_many(filters).await.map_err(AppError::from)
+            }
+            MqttClient::V5(client) => {
+                let filters = subscriptions
+                    .into_iter()
+                    .map(|(topic, qos)| Filter::new(topic, v5_qos(qos)));
+                client.subscribe_many(filters).await.map_err(AppError::from)
+            }
+        };
+        result.map_err(|e| {
+            log::error!("MQTT resubscribe failed: {}", e);
+            e
+        })?;
+
+        log::info!("Subscribed to {} MQTT topics", count);
+        Ok(())
+    }
 
-        client.unsubscribe(topic).await.map_err(|e| {
+    pub async fn get_subscriptions(&self) -> Vec<String> {
+        self.subscriptions.lock().await.keys().cloned().collect()
+    }
+
+    /// Receptor de los mensajes entrantes difundidos por el event loop
+    pub fn subscribe_messages(&self) -> broadcast::Receiver<MqttMessage> {
+        self.incoming.subscribe()
+    }
+
+    /// Difunde un mensaje entrante a todos los receptores registrados
+    pub fn dispatch_incoming(&self, message: MqttMessage) {
+        log::debug!(
+            "MQTT message received - Topic: {}, Payload: {}",
+            message.topic,
+            message.payload_str()
+        );
+        // Sin receptores activos el mensaje simplemente se descarta
+        let _ = self.incoming.send(message);
+    }
+
+    pub async fn unsubscribe_from_topic(&self, topic: &str) -> Result<(), AppError> {
+        self.subscriptions.lock().await.remove(topic);
+        let result = match &self.client {
+            MqttClient::V4(client) => client.unsubscribe(topic).await.map_err(AppError::from),
+            MqttClient::V5(client) => client.unsubscribe(topic).await.map_err(AppError::from),
+        };
+        result.map_err(|e| {
             log::error!("MQTT unsubscribe failed for topic '{}': {}", topic, e);
-            AppError::MqttError(e)
+            e
         })?;
 
         log::info!("Unsubscribed from MQTT topic: {}", topic);
         Ok(())
     }
 
-    pub async fn get_client_info(&self) -> String {
-        format!("MQTT Service - Connected: {}", self.is_connected().await)
+    /// Cierra la conexión con un DISCONNECT limpio (el broker no publica el Last Will)
+    pub async fn disconnect(&self) -> Result<(), AppError> {
+        match &self.client {
+            MqttClient::V4(client) => client.disconnect().await?,
+            MqttClient::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+
+    pub async fn get_client_info(&self) -> MqttInfoResponse {
+        let broker = match &self.broker_stats {
+            Some(broker_stats) => broker_stats.snapshot().await,
+            None => None,
+        };
+        MqttInfoResponse {
+            connected: self.is_connected().await,
+            protocol_version: self.protocol_version(),
+            connection: self.get_connection_stats().await,
+            traffic: self.traffic.lock().await.clone(),
+            subscriptions: self.get_subscriptions().await,
+            broker,
+        }
     }
 }
 
 impl Clone for MqttService {
     fn clone(&self) -> Self {
         Self {
-            client: Arc::clone(&self.client),
-            connected: Arc::clone(&self.connected),
+            client: self.client.clone(),
+            stats: Arc::clone(&self.stats),
+            traffic: Arc::clone(&self.traffic),
+            subscriptions: Arc::clone(&self.subscriptions),
+            incoming: self.incoming.clone(),
+            connection: Arc::clone(&self.connection),
+            pending_acks: Arc::clone(&self.pending_acks),
+            inspector: self.inspector.clone(),
+            broker_stats: self.broker_stats.clone(),
         }
     }
 }
 
+impl From<QoS> for MqttQos {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => MqttQos::AtMostOnce,
+            QoS::AtLeastOnce => MqttQos::AtLeastOnce,
+            QoS::ExactlyOnce => MqttQos::ExactlyOnce,
+        }
+    }
+}
+
+impl From<MqttQos> for QoS {
+    fn from(qos: MqttQos) -> Self {
+        match qos {
+            MqttQos::AtMostOnce => QoS::AtMostOnce,
+            MqttQos::AtLeastOnce => QoS::AtLeastOnce,
+            MqttQos::ExactlyOnce => QoS::ExactlyOnce,
+        }
+    }
+}
+
+fn v5_qos(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -97,7 +671,7 @@ mod tests {
     async fn test_mqtt_service_creation() {
         let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
         let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
 
         assert!(!mqtt_service.is_connected().await);
     }
@@ -106,10 +680,12 @@ mod tests {
     async fn test_connection_status() {
         let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
         let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
 
+        let connection = mqtt_service.watch_connection();
         mqtt_service.set_connected(true).await;
         assert!(mqtt_service.is_connected().await);
+        assert!(*connection.borrow());
 
         mqtt_service.set_connected(false).await;
         assert!(!mqtt_service.is_connected().await);
@@ -119,11 +695,214 @@ mod tests {
     async fn test_clone() {
         let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
         let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
-        let mqtt_service = MqttService::new(Arc::new(Mutex::new(client)));
+        let mqtt_service = MqttService::new(client);
 
         let cloned_service = mqtt_service.clone();
 
         mqtt_service.set_connected(true).await;
         assert!(cloned_service.is_connected().await);
     }
+
+    #[tokio::test]
+    async fn test_subscriptions_are_registered_while_disconnected() {
+        let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+
+        mqtt_service
+            .subscribe_to_topic("home/a/status")
+            .await
+            .unwrap();
+        mqtt_service
+            .subscribe_to_topic("home/b/status")
+            .await
+            .unwrap();
+        mqtt_service
+            .subscribe_to_topic("home/a/status")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mqtt_service.get_subscriptions().await,
+            vec!["home/a/status".to_string(), "home/b/status".to_string()]
+        );
+        assert!(mqtt_service.resubscribe_all().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_incoming() {
+        let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        let mut receiver = mqtt_service.subscribe_messages();
+
+        mqtt_service.dispatch_incoming(MqttMessage::from(Publish::new(
+            "home/a/status",
+            QoS::AtMostOnce,
+            "open",
+        )));
+
+        let message = receiver.recv().await.unwrap();
+        assert_eq!(message.topic, "home/a/status");
+        assert_eq!(message.payload_str(), "open");
+    }
+
+    #[tokio::test]
+    async fn test_v5_publish_reports_reason_code() {
+        tokio::time::pause();
+        let mqtt_options = v5::MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = v5::AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        assert_eq!(mqtt_service.protocol_version(), MqttProtocolVersion::V5);
+        mqtt_service.set_connected(true).await;
+
+        let broker = mqtt_service.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            broker.record_outgoing_publish(1).await;
+            broker.fail_pending_acks(&[], Some("NotAuthorized")).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            broker.record_outgoing_publish(2).await;
+            broker.resolve_puback(2).await;
+        });
+
+        let result = mqtt_service
+            .publish_command_with_properties(
+                "home/a/set",
+                "OPEN",
+                PublishOptions::default(),
+                CommandProperties {
+                    request_id: "req".to_string(),
+                    issuer: "api".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::MqttRejected(ref topic, ref reason))
+                if topic == "home/a/set" && reason == "NotAuthorized"
+        ));
+        assert!(mqtt_service
+            .publish_command("home/a/set", "CLOSE")
+            .await
+            .is_ok());
+
+        let traffic = mqtt_service.get_client_info().await.traffic;
+        assert_eq!(traffic.messages_published, 1);
+        assert_eq!(traffic.publish_errors, 1);
+    }
+
+    #[tokio::test]
+    async fn test_v5_pubacks_match_packet_ids() {
+        tokio::time::pause();
+        let mqtt_options = v5::MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = v5::AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+        mqtt_service.set_connected(true).await;
+
+        let publish = |payload: &'static str| {
+            let service = mqtt_service.clone();
+            tokio::spawn(async move { service.publish_command("home/a/set", payload).await })
+        };
+        let settle = || tokio::time::sleep(Duration::from_millis(1));
+
+        let first = publish("OPEN");
+        settle().await;
+        let second = publish("CLOSE");
+        settle().await;
+        mqtt_service.record_outgoing_publish(1).await;
+        mqtt_service.record_outgoing_publish(2).await;
+
+        // PUBACKs may arrive out of order
+        mqtt_service.resolve_puback(2).await;
+        assert!(second.await.unwrap().is_ok());
+
+        // The connection drops before packet 1 is acknowledged
+        mqtt_service.fail_pending_acks(&[1], None).await;
+        assert!(matches!(first.await.unwrap(), Err(AppError::MqttError(_))));
+
+        // Packet 1 is retransmitted first and must not take the new waiter
+        let third = publish("STOP");
+        settle().await;
+        mqtt_service.record_outgoing_publish(1).await;
+        mqtt_service.resolve_puback(1).await;
+        assert!(!third.is_finished());
+        mqtt_service.record_outgoing_publish(3).await;
+        mqtt_service.resolve_puback(3).await;
+        assert!(third.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_v5_publish_waits_for_request_channel() {
+        tokio::time::pause();
+        let mqtt_options = v5::MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = v5::AsyncClient::new(mqtt_options, 1);
+        let mqtt_service = MqttService::new(client);
+        mqtt_service.set_connected(true).await;
+
+        let options = PublishOptions {
+            qos: MqttQos::AtMostOnce,
+            retain: false,
+        };
+        mqtt_service
+            .publish("home/a/set", "OPEN", options, None)
+            .await
+            .unwrap();
+
+        // The event loop never drains the channel, so the second publish gives up
+        let result = mqtt_service
+            .publish("home/a/set", "CLOSE", options, None)
+            .await;
+        assert!(matches!(result, Err(AppError::MqttError(_))));
+        assert_eq!(mqtt_service.pending_acks().unassigned.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_info_includes_broker_stats() {
+        let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let broker_stats = BrokerStats::new();
+        let mqtt_service = MqttService::new(client).with_broker_stats(broker_stats.clone());
+        assert!(mqtt_service.get_client_info().await.broker.is_none());
+
+        let message = MqttMessage::from(Publish::new(
+            "$SYS/broker/clients/connected",
+            QoS::AtMostOnce,
+            "3",
+        ));
+        mqtt_service.record_incoming(&message).await;
+        broker_stats.apply(&message).await;
+        mqtt_service.record_in_flight(2).await;
+
+        let info = mqtt_service.get_client_info().await;
+        assert_eq!(info.traffic.messages_received, 1);
+        assert_eq!(info.traffic.in_flight, 2);
+        assert_eq!(info.broker.unwrap().clients_connected, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_stats() {
+        let mqtt_options = MqttOptions::new("test_client", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mqtt_service = MqttService::new(client);
+
+        mqtt_service.record_connected().await;
+        mqtt_service.record_disconnected("connection reset").await;
+        assert!(!mqtt_service.is_connected().await);
+
+        mqtt_service
+            .record_reconnect_attempt(std::time::Duration::from_millis(500))
+            .await;
+        mqtt_service.record_connected().await;
+
+        let stats = mqtt_service.get_connection_stats().await;
+        assert!(mqtt_service.is_connected().await);
+        assert_eq!(stats.total_connections, 2);
+        assert_eq!(stats.reconnects, 1);
+        assert_eq!(stats.reconnect_attempts, 1);
+        assert_eq!(stats.consecutive_failures, 0);
+        assert!(stats.connected_since.is_some());
+        assert!(stats.last_disconnect.is_some());
+    }
 }
@@ -0,0 +1,183 @@
+use crate::config::MqttTlsConfig;
+use crate::errors::AppError;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// Construye la configuración rustls de la conexión MQTT a partir de `MqttTlsConfig`
+pub fn client_config(tls: &MqttTlsConfig) -> Result<ClientConfig, AppError> {
+    let builder = ClientConfig::builder();
+
+    let builder = if tls.insecure_skip_verify {
+        log::warn!("MQTT TLS certificate verification is disabled");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipVerification::new()))
+    } else {
+        // rumqttc uses the connect host as SNI and certificate name, so
+        // `server_name` only needs to be a valid name here
+        if let Some(name) = &tls.server_name {
+            ServerName::try_from(name.as_str())
+                .map_err(|_| AppError::ConfigError(format!("server_name inválido: {}", name)))?;
+        }
+        builder.with_root_certificates(root_store(tls.ca_path.as_deref())?)
+    };
+
+    let mut config = match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert_path), Some(key_path)) => builder
+            .with_client_auth_cert(load_certificates(cert_path)?, load_private_key(key_path)?)
+            .map_err(|e| {
+                AppError::ConfigError(format!("Certificado de cliente inválido: {}", e))
+            })?,
+        _ => builder.with_no_client_auth(),
+    };
+
+    config.alpn_protocols = tls
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    Ok(config)
+}
+
+fn root_store(ca_path: Option<&str>) -> Result<RootCertStore, AppError> {
+    let certificates = match ca_path {
+        Some(path) => load_certificates(path)?,
+        None => rustls_native_certs::load_native_certs().map_err(|e| {
+            AppError::ConfigError(format!(
+                "No se pudieron cargar los certificados del sistema: {}",
+                e
+            ))
+        })?,
+    };
+
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates {
+        roots
+            .add(certificate)
+            .map_err(|e| AppError::ConfigError(format!("Certificado de CA inválido: {}", e)))?;
+    }
+    if roots.is_empty() {
+        return Err(AppError::ConfigError(
+            "No hay certificados de CA para validar el broker".to_string(),
+        ));
+    }
+    Ok(roots)
+}
+
+fn open_pem(path: &str) -> Result<BufReader<std::fs::File>, AppError> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| AppError::ConfigError(format!("No se pudo abrir {}: {}", path, e)))
+}
+
+fn load_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, AppError> {
+    let certificates = rustls_pemfile::certs(&mut open_pem(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::ConfigError(format!("PEM inválido en {}: {}", path, e)))?;
+
+    if certificates.is_empty() {
+        return Err(AppError::ConfigError(format!(
+            "No hay certificados en {}",
+            path
+        )));
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, AppError> {
+    rustls_pemfile::private_key(&mut open_pem(path)?)
+        .map_err(|e| AppError::ConfigError(format!("PEM inválido en {}: {}", path, e)))?
+        .ok_or_else(|| AppError::ConfigError(format!("No hay clave privada en {}", path)))
+}
+
+/// Acepta cualquier certificado (`insecure_skip_verify`); las firmas del
+/// handshake sí se comprueban
+#[derive(Debug)]
+struct SkipVerification {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl SkipVerification {
+    fn new() -> Self {
+        Self {
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_insecure_config_with_alpn() {
+        let tls = MqttTlsConfig {
+            insecure_skip_verify: true,
+            alpn: vec!["mqtt".to_string()],
+            ..Default::default()
+        };
+
+        let config = client_config(&tls).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"mqtt".to_vec()]);
+    }
+
+    #[test]
+    fn test_invalid_pem_files() {
+        let mut empty_ca = NamedTempFile::new().unwrap();
+        writeln!(empty_ca, "no es un certificado").unwrap();
+        let tls = MqttTlsConfig {
+            ca_path: Some(empty_ca.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(client_config(&tls), Err(AppError::ConfigError(_))));
+
+        let tls = MqttTlsConfig {
+            insecure_skip_verify: true,
+            client_cert_path: Some("/no/existe/client.crt".to_string()),
+            client_key_path: Some("/no/existe/client.key".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(client_config(&tls), Err(AppError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::config::{MqttConfig, MqttTransport};
+use crate::errors::AppError;
+use crate::services::mqtt_tls;
+use http::{HeaderName, HeaderValue};
+use rumqttc::{v5, LastWill, MqttOptions, QoS, TlsConfiguration, Transport};
+use std::future::Future;
+use std::sync::Arc;
+
+/// Opciones de conexión comunes a los clientes MQTT v4 y v5
+trait TransportOptions: Sized {
+    fn create(client_id: &str, host: String, port: u16) -> Self;
+    fn set_transport(&mut self, transport: Transport);
+    /// Last Will retenido con QoS 1
+    fn set_last_will(&mut self, topic: &str, payload: &str);
+    fn set_request_modifier<F, O>(&mut self, modifier: F)
+    where
+        F: Fn(http::Request<()>) -> O + Send + Sync + 'static,
+        O: Future<Output = http::Request<()>> + Send + 'static;
+}
+
+impl TransportOptions for MqttOptions {
+    fn create(client_id: &str, host: String, port: u16) -> Self {
+        MqttOptions::new(client_id, host, port)
+    }
+
+    fn set_transport(&mut self, transport: Transport) {
+        MqttOptions::set_transport(self, transport);
+    }
+
+    fn set_last_will(&mut self, topic: &str, payload: &str) {
+        MqttOptions::set_last_will(self, LastWill::new(topic, payload, QoS::AtLeastOnce, true));
+    }
+
+    fn set_request_modifier<F, O>(&mut self, modifier: F)
+    where
+        F: Fn(http::Request<()>) -> O + Send + Sync + 'static,
+        O: Future<Output = http::Request<()>> + Send + 'static,
+    {
+        MqttOptions::set_request_modifier(self, modifier);
+    }
+}
+
+impl TransportOptions for v5::MqttOptions {
+    fn create(client_id: &str, host: String, port: u16) -> Self {
+        v5::MqttOptions::new(client_id, host, port)
+    }
+
+    fn set_transport(&mut self, transport: Transport) {
+        v5::MqttOptions::set_transport(self, transport);
+    }
+
+    fn set_last_will(&mut self, topic: &str, payload: &str) {
+        let will = v5::mqttbytes::v5::LastWill::new(
+            topic,
+            payload,
+            v5::mqttbytes::QoS::AtLeastOnce,
+            true,
+            None,
+        );
+        v5::MqttOptions::set_last_will(self, will);
+    }
+
+    fn set_request_modifier<F, O>(&mut self, modifier: F)
+    where
+        F: Fn(http::Request<()>) -> O + Send + Sync + 'static,
+        O: Future<Output = http::Request<()>> + Send + 'static,
+    {
+        v5::MqttOptions::set_request_modifier(self, modifier);
+    }
+}
+
+/// Crea las opciones de conexión con el transporte configurado (`tcp`, `tls`, `ws`, `wss`)
+/// y el Last Will de disponibilidad
+pub fn mqtt_options(config: &MqttConfig) -> Result<MqttOptions, AppError> {
+    build_options(config)
+}
+
+/// Igual que `mqtt_options`, para el cliente MQTT v5
+pub fn mqtt_options_v5(config: &MqttConfig) -> Result<v5::MqttOptions, AppError> {
+    build_options(config)
+}
+
+fn build_options<O: TransportOptions>(config: &MqttConfig) -> Result<O, AppError> {
+    let transport = config.transport();
+    let mut options = O::create(
+        &config.client_id,
+        broker_address(config),
+        config.broker_port,
+    );
+
+    match transport {
+        MqttTransport::Tcp => {}
+        MqttTransport::Tls => {
+            options.set_transport(Transport::tls_with_config(tls_configuration(config)?));
+        }
+        MqttTransport::Ws => {
+            options.set_transport(Transport::Ws);
+        }
+        MqttTransport::Wss => {
+            options.set_transport(Transport::wss_with_config(tls_configuration(config)?));
+        }
+    }
+
+    if matches!(transport, MqttTransport::Ws | MqttTransport::Wss)
+        && !config.websocket_headers.is_empty()
+    {
+        let headers = websocket_headers(config)?;
+        options.set_request_modifier(move |mut request: http::Request<()>| {
+            let headers = headers.clone();
+            async move {
+                for (name, value) in headers {
+                    request.headers_mut().insert(name, value);
+                }
+                request
+            }
+        });
+    }
+
+    let availability = &config.availability;
+    if availability.enabled {
+        options.set_last_will(&availability.topic, &availability.offline_payload);
+    }
+
+    Ok(options)
+}
+
+/// Con WebSocket el broker se indica como URL (`ws://host:puerto/ruta`)
+pub fn broker_address(config: &MqttConfig) -> String {
+    let host = connect_host(config);
+    let scheme = match config.transport() {
+        MqttTransport::Tcp | MqttTransport::Tls => return host.to_string(),
+        MqttTransport::Ws => "ws",
+        MqttTransport::Wss => "wss",
+    };
+    format!(
+        "{}://{}:{}{}",
+        scheme, host, config.broker_port, config.websocket_path
+    )
+}
+
+/// Host de conexión: con TLS, `server_name` si está configurado. rumqttc envía
+/// este host como SNI y valida el certificado con él, así que el nombre debe
+/// resolverse a la dirección del broker.
+fn connect_host(config: &MqttConfig) -> &str {
+    let server_name = match config.transport() {
+        MqttTransport::Tls | MqttTransport::Wss => config
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.server_name.as_deref()),
+        MqttTransport::Tcp | MqttTransport::Ws => None,
+    };
+    server_name.unwrap_or(&config.broker_host)
+}
+
+fn tls_configuration(config: &MqttConfig) -> Result<TlsConfiguration, AppError> {
+    let tls = config.tls.clone().unwrap_or_default();
+    let client_config = mqtt_tls::client_config(&tls)?;
+    Ok(TlsConfiguration::Rustls(Arc::new(client_config)))
+}
+
+fn websocket_headers(config: &MqttConfig) -> Result<Vec<(HeaderName, HeaderValue)>, AppError> {
+    config
+        .websocket_headers
+        .iter()
+        .map(|(name, value)| {
+            let header = HeaderName::try_from(name.as_str()).map_err(|_| {
+                AppError::ConfigError(format!("Cabecera WebSocket inválida: {}", name))
+            })?;
+            let value = HeaderValue::try_from(value.as_str()).map_err(|_| {
+                AppError::ConfigError(format!("Valor inválido para la cabecera {}", name))
+            })?;
+            Ok((header, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MqttTlsConfig;
+
+    #[test]
+    fn test_websocket_options() {
+        let mut config = MqttConfig {
+            broker_port: 9001,
+            transport: Some(MqttTransport::Ws),
+            ..MqttConfig::default()
+        };
+        config
+            .websocket_headers
+            .insert("Authorization".to_string(), "Bearer token".to_string());
+
+        let options = mqtt_options(&config).unwrap();
+        assert_eq!(options.broker_address().0, "ws://localhost:9001/mqtt");
+        assert!(matches!(options.transport(), Transport::Ws));
+        assert!(options.request_modifier().is_some());
+
+        config.transport = Some(MqttTransport::Wss);
+        config.tls = Some(MqttTlsConfig {
+            insecure_skip_verify: true,
+            ..Default::default()
+        });
+        let options = mqtt_options(&config).unwrap();
+        assert_eq!(options.broker_address().0, "wss://localhost:9001/mqtt");
+        assert!(matches!(options.transport(), Transport::Wss(_)));
+
+        let options = mqtt_options_v5(&config).unwrap();
+        assert_eq!(options.broker_address().0, "wss://localhost:9001/mqtt");
+        assert!(matches!(options.transport(), Transport::Wss(_)));
+        assert!(options.request_modifier().is_some());
+    }
+
+    #[test]
+    fn test_tls_server_name_is_connect_host() {
+        let ca_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = MqttConfig {
+            broker_host: "192.168.1.10".to_string(),
+            broker_port: 8883,
+            tls: Some(MqttTlsConfig {
+                ca_path: Some(ca_file.path().to_string_lossy().into_owned()),
+                server_name: Some("broker.local".to_string()),
+                ..Default::default()
+            }),
+            ..MqttConfig::default()
+        };
+        assert_eq!(broker_address(&config), "broker.local");
+
+        config.transport = Some(MqttTransport::Wss);
+        config.websocket_path = "/mqtt".to_string();
+        assert_eq!(broker_address(&config), "wss://broker.local:8883/mqtt");
+
+        config.transport = Some(MqttTransport::Ws);
+        assert_eq!(broker_address(&config), "ws://192.168.1.10:8883/mqtt");
+    }
+
+    #[test]
+    fn test_tcp_options_and_invalid_header() {
+        let mut config = MqttConfig::default();
+        let options = mqtt_options(&config).unwrap();
+        assert_eq!(options.broker_address(), ("localhost".to_string(), 1883));
+        assert!(matches!(options.transport(), Transport::Tcp));
+        let will = options.last_will().unwrap();
+        assert_eq!(will.topic, "tabi/status");
+        assert_eq!(will.message, "offline");
+        assert!(will.retain);
+
+        config.transport = Some(MqttTransport::Ws);
+        config
+            .websocket_headers
+            .insert("bad header".to_string(), "x".to_string());
+        assert!(matches!(
+            mqtt_options(&config),
+            Err(AppError::ConfigError(_))
+        ));
+    }
+}
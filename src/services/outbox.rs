@@ -0,0 +1,706 @@
+use crate::config::PublishOptions;
+use crate::errors::AppError;
+use crate::models::blind::BlindCommand;
+use crate::services::mqtt_service::{CommandProperties, MqttService};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+/// Comando guardado mientras el broker no está disponible
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Orden de llegada a la cola
+    pub sequence: u64,
+    pub blind_id: String,
+    pub command: String,
+    /// Posición o inclinación de `SET_POSITION` y `SET_TILT`
+    #[serde(default)]
+    pub value: Option<u8>,
+    pub topic: String,
+    pub payload: String,
+    #[serde(default)]
+    pub options: PublishOptions,
+    pub properties: CommandProperties,
+    pub queued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl OutboxEntry {
+    /// Comando guardado, si se reconoce
+    pub fn blind_command(&self) -> Option<BlindCommand> {
+        match (self.command.as_str(), self.value) {
+            ("SET_POSITION", Some(position)) => Some(BlindCommand::SetPosition(position)),
+            ("SET_TILT", Some(tilt)) => Some(BlindCommand::SetTilt(tilt)),
+            (command, _) => command.parse().ok(),
+        }
+    }
+}
+
+/// Comandos de la cola publicados al vaciarla; quien escucha va por detrás si se llena
+const SENT_CHANNEL_CAPACITY: usize = 64;
+
+/// Resultado de entregar un comando
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    Sent,
+    /// Guardado en la cola; se enviará al reconectar
+    Queued,
+}
+
+/// Comandos guardados y los que se están publicando en este momento
+#[derive(Debug, Default)]
+struct Queue {
+    entries: Vec<OutboxEntry>,
+    /// Secuencias en publicación; un vaciado no las vuelve a enviar
+    sending: HashSet<u64>,
+    next_sequence: u64,
+    /// Cambios de la cola; ordena las copias que se guardan en disco
+    version: u64,
+}
+
+impl Queue {
+    fn new(entries: Vec<OutboxEntry>) -> Self {
+        let next_sequence = entries
+            .iter()
+            .map(|entry| entry.sequence + 1)
+            .max()
+            .unwrap_or(0);
+        Self {
+            entries,
+            sending: HashSet::new(),
+            next_sequence,
+            version: 0,
+        }
+    }
+}
+
+/// Contenido de la cola pendiente de guardar
+struct Snapshot {
+    version: u64,
+    content: String,
+}
+
+/// Cola persistente de comandos. Cada comando se guarda antes de publicarlo y
+/// se retira cuando el broker lo acepta. Solo se conserva el último comando de
+/// cada persiana y los que superan el TTL se descartan; al reconectar con el
+/// broker se envían en orden de llegada.
+#[derive(Clone)]
+pub struct Outbox {
+    mqtt_service: MqttService,
+    queue: Arc<Mutex<Queue>>,
+    path: Option<Arc<PathBuf>>,
+    /// Última versión guardada; también ordena las escrituras del fichero
+    saved_version: Arc<Mutex<u64>>,
+    ttl: chrono::Duration,
+    /// Evita dos vaciados a la vez; los envíos directos no lo esperan
+    flush_lock: Arc<Mutex<()>>,
+    /// Comandos enviados al vaciar la cola
+    sent: broadcast::Sender<OutboxEntry>,
+}
+
+impl Outbox {
+    pub fn new(mqtt_service: MqttService, ttl_secs: u64) -> Self {
+        let (sent, _) = broadcast::channel(SENT_CHANNEL_CAPACITY);
+        Self {
+            mqtt_service,
+            queue: Arc::new(Mutex::new(Queue::default())),
+            path: None,
+            saved_version: Arc::new(Mutex::new(0)),
+            ttl: chrono::Duration::seconds(ttl_secs as i64),
+            flush_lock: Arc::new(Mutex::new(())),
+            sent,
+        }
+    }
+
+    /// Igual que `new`, pero la cola se guarda en `path` y se recupera al arrancar.
+    /// Un fichero ilegible se aparta con el sufijo `.corrupt-<fecha>` en lugar de
+    /// sobrescribirlo.
+    pub fn with_file(mqtt_service: MqttService, ttl_secs: u64, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut entries = match load_entries(&path) {
+            Ok(entries) => entries,
+            Err(e) => match set_aside(&path) {
+                Ok(aside) => {
+                    log::error!(
+                        "Could not load outbox from {}: {}; moved it to {}",
+                        path.display(),
+                        e,
+                        aside.display()
+                    );
+                    Vec::new()
+                }
+                Err(rename_error) => {
+                    // Keep the unreadable file untouched rather than overwrite it
+                    log::error!(
+                        "Could not load outbox from {} ({}) nor move it aside ({}); queued commands will not be saved",
+                        path.display(),
+                        e,
+                        rename_error
+                    );
+                    return Self::new(mqtt_service, ttl_secs);
+                }
+            },
+        };
+        let now = Utc::now();
+        entries.retain(|entry| entry.expires_at > now);
+        if !entries.is_empty() {
+            log::info!(
+                "Recovered {} queued commands from {}",
+                entries.len(),
+                path.display()
+            );
+        }
+
+        Self {
+            queue: Arc::new(Mutex::new(Queue::new(entries))),
+            path: Some(Arc::new(path)),
+            ..Self::new(mqtt_service, ttl_secs)
+        }
+    }
+
+    /// Comandos de la cola que llegan al broker al vaciarla, para completar lo que
+    /// no se registró al encolarlos
+    pub fn subscribe_sent(&self) -> broadcast::Receiver<OutboxEntry> {
+        self.sent.subscribe()
+    }
+
+    /// Lanza la tarea que vacía la cola cada vez que se recupera la conexión
+    pub fn start(&self) {
+        let outbox = self.clone();
+        let mut connection = self.mqtt_service.watch_connection();
+        tokio::spawn(async move {
+            loop {
+                if *connection.borrow_and_update() {
+                    outbox.flush().await;
+                }
+                if connection.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Guarda el comando y lo publica si hay conexión. Queda en la cola hasta que
+    /// el broker lo acepta: sin conexión, o si se pierde durante el envío, se
+    /// reenvía al reconectar. Solo se bloquea la cola para modificarla, así que
+    /// los comandos de varias persianas se publican a la vez.
+    pub async fn deliver(
+        &self,
+        blind_id: &str,
+        command: &BlindCommand,
+        topic: &str,
+        payload: &str,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> Result<Delivery, AppError> {
+        let (entry, sending) = self
+            .enqueue(blind_id, command, topic, payload, options, properties)
+            .await;
+        if !sending {
+            log::info!(
+                "Broker unavailable, queued {} for blind '{}'",
+                command.as_str(),
+                blind_id
+            );
+            return Ok(Delivery::Queued);
+        }
+
+        match self.publish(&entry).await {
+            Ok(()) => {
+                self.remove(&entry).await;
+                Ok(Delivery::Sent)
+            }
+            Err(e @ AppError::MqttRejected(..)) => {
+                self.remove(&entry).await;
+                Err(e)
+            }
+            Err(e) => {
+                log::warn!(
+                    "Publish of {} for blind '{}' failed, kept queued: {}",
+                    command.as_str(),
+                    blind_id,
+                    e
+                );
+                self.release(&entry).await;
+                // A flush that ran during the publish skipped this command
+                if self.mqtt_service.is_connected().await {
+                    let outbox = self.clone();
+                    tokio::spawn(async move { outbox.flush().await });
+                }
+                Ok(Delivery::Queued)
+            }
+        }
+    }
+
+    /// Guarda un comando, sustituyendo al pendiente de la misma persiana. Si hay
+    /// conexión queda marcado como en publicación, para que un vaciado no lo
+    /// envíe también; devuelve si hay que publicarlo.
+    async fn enqueue(
+        &self,
+        blind_id: &str,
+        command: &BlindCommand,
+        topic: &str,
+        payload: &str,
+        options: PublishOptions,
+        properties: CommandProperties,
+    ) -> (OutboxEntry, bool) {
+        let now = Utc::now();
+        let mut queue = self.queue.lock().await;
+        let sequence = queue.next_sequence;
+        queue.next_sequence += 1;
+        queue
+            .entries
+            .retain(|entry| entry.blind_id != blind_id && entry.expires_at > now);
+        let entry = OutboxEntry {
+            sequence,
+            blind_id: blind_id.to_string(),
+            command: command.as_str().to_string(),
+            value: command.value(),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            options,
+            properties,
+            queued_at: now,
+            expires_at: now + self.ttl,
+        };
+        queue.entries.push(entry.clone());
+        let snapshot = self.snapshot(&mut queue);
+
+        // Read under the queue lock: a flush that starts after a reconnect sees the entry
+        let sending = self.mqtt_service.is_connected().await;
+        if sending {
+            queue.sending.insert(entry.sequence);
+        }
+        drop(queue);
+        self.save(snapshot).await;
+        (entry, sending)
+    }
+
+    /// Descarta el comando pendiente de una persiana que acaba de recibir otro
+    pub async fn discard(&self, blind_id: &str) {
+        let mut queue = self.queue.lock().await;
+        let before = queue.entries.len();
+        queue.entries.retain(|entry| entry.blind_id != blind_id);
+        if queue.entries.len() != before {
+            log::info!("Discarded queued command for blind '{}'", blind_id);
+            let snapshot = self.snapshot(&mut queue);
+            drop(queue);
+            self.save(snapshot).await;
+        }
+    }
+
+    /// Comandos pendientes que aún no han caducado
+    pub async fn entries(&self) -> Vec<OutboxEntry> {
+        let now = Utc::now();
+        let queue = self.queue.lock().await;
+        queue
+            .entries
+            .iter()
+            .filter(|entry| entry.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Envía los comandos pendientes mientras haya conexión. Devuelve cuántos se enviaron.
+    pub async fn flush(&self) -> usize {
+        let _flushing = self.flush_lock.lock().await;
+        let mut sent = 0;
+
+        while self.mqtt_service.is_connected().await {
+            let Some(entry) = self.next_entry().await else {
+                break;
+            };
+
+            match self.publish(&entry).await {
+                Ok(()) => {
+                    sent += 1;
+                    let _ = self.sent.send(entry.clone());
+                }
+                Err(AppError::MqttRejected(topic, reason)) => {
+                    log::error!(
+                        "Dropping queued command for blind '{}': broker rejected {} ({})",
+                        entry.blind_id,
+                        topic,
+                        reason
+                    );
+                }
+                Err(e) => {
+                    log::warn!("Outbox delivery interrupted: {}", e);
+                    self.release(&entry).await;
+                    break;
+                }
+            }
+            self.remove(&entry).await;
+        }
+
+        if sent > 0 {
+            log::info!("Delivered {} queued commands", sent);
+        }
+        sent
+    }
+
+    async fn publish(&self, entry: &OutboxEntry) -> Result<(), AppError> {
+        self.mqtt_service
+            .publish_command_with_properties(
+                &entry.topic,
+                &entry.payload,
+                entry.options,
+                entry.properties.clone(),
+            )
+            .await
+    }
+
+    /// Comando más antiguo de la cola que nadie está publicando, descartando
+    /// los caducados. Queda marcado como en publicación.
+    async fn next_entry(&self) -> Option<OutboxEntry> {
+        let now = Utc::now();
+        let mut queue = self.queue.lock().await;
+        let before = queue.entries.len();
+        queue.entries.retain(|entry| {
+            let alive = entry.expires_at > now;
+            if !alive {
+                log::warn!(
+                    "Dropping expired {} for blind '{}' queued at {}",
+                    entry.command,
+                    entry.blind_id,
+                    entry.queued_at
+                );
+            }
+            alive
+        });
+        let snapshot = (queue.entries.len() != before)
+            .then(|| self.snapshot(&mut queue))
+            .flatten();
+        let entry = queue
+            .entries
+            .iter()
+            .filter(|entry| !queue.sending.contains(&entry.sequence))
+            .min_by_key(|entry| entry.sequence)
+            .cloned();
+        if let Some(entry) = &entry {
+            queue.sending.insert(entry.sequence);
+        }
+        drop(queue);
+        self.save(snapshot).await;
+        entry
+    }
+
+    async fn remove(&self, sent: &OutboxEntry) {
+        let mut queue = self.queue.lock().await;
+        queue.sending.remove(&sent.sequence);
+        queue
+            .entries
+            .retain(|entry| entry.sequence != sent.sequence);
+        let snapshot = self.snapshot(&mut queue);
+        drop(queue);
+        self.save(snapshot).await;
+    }
+
+    /// Devuelve a la cola un comando cuya publicación no terminó
+    async fn release(&self, entry: &OutboxEntry) {
+        self.queue.lock().await.sending.remove(&entry.sequence);
+    }
+
+    /// Copia de la cola para guardarla sin mantenerla bloqueada
+    fn snapshot(&self, queue: &mut Queue) -> Option<Snapshot> {
+        self.path.as_ref()?;
+        queue.version += 1;
+        match serde_json::to_string_pretty(&queue.entries) {
+            Ok(content) => Some(Snapshot {
+                version: queue.version,
+                content,
+            }),
+            Err(e) => {
+                log::error!("Failed to encode outbox: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Guarda la copia fuera del runtime, salvo que ya haya una más reciente en disco
+    async fn save(&self, snapshot: Option<Snapshot>) {
+        let (Some(path), Some(snapshot)) = (&self.path, snapshot) else {
+            return;
+        };
+        let mut saved_version = self.saved_version.lock().await;
+        if *saved_version >= snapshot.version {
+            return;
+        }
+        let target = Arc::clone(path);
+        let result =
+            tokio::task::spawn_blocking(move || write_atomically(&target, &snapshot.content))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|result| result);
+        match result {
+            Ok(()) => *saved_version = snapshot.version,
+            Err(e) => log::error!("Failed to save outbox to {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Escribe el fichero entero o nada: primero en un temporal que luego se renombra
+fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    let temp = sibling(path, ".tmp");
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)
+}
+
+/// Aparta un fichero que no se pudo leer para no perder su contenido
+fn set_aside(path: &Path) -> std::io::Result<PathBuf> {
+    let aside = sibling(
+        path,
+        &format!(".corrupt-{}", Utc::now().format("%Y%m%dT%H%M%S")),
+    );
+    std::fs::rename(path, &aside)?;
+    Ok(aside)
+}
+
+/// `path` con `suffix` añadido al nombre del fichero
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn load_entries(path: &Path) -> Result<Vec<OutboxEntry>, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::{AsyncClient, MqttOptions};
+    use tempfile::tempdir;
+
+    fn test_service() -> (MqttService, rumqttc::EventLoop) {
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(mqtt_options, 10);
+        (MqttService::new(client), eventloop)
+    }
+
+    #[tokio::test]
+    async fn test_latest_command_per_blind_survives_restart() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("outbox.json");
+        let (mqtt_service, _eventloop) = test_service();
+
+        let outbox = Outbox::with_file(mqtt_service.clone(), 300, &path);
+        let options = PublishOptions::default();
+        let properties = CommandProperties::default();
+        outbox
+            .enqueue(
+                "b1",
+                &BlindCommand::Open,
+                "home/b1",
+                "OPEN",
+                options,
+                properties.clone(),
+            )
+            .await;
+        outbox
+            .enqueue(
+                "b2",
+                &BlindCommand::Stop,
+                "home/b2",
+                "STOP",
+                options,
+                properties.clone(),
+            )
+            .await;
+        outbox
+            .enqueue(
+                "b1",
+                &BlindCommand::Close,
+                "home/b1",
+                "CLOSE",
+                options,
+                properties,
+            )
+            .await;
+
+        let restored = Outbox::with_file(mqtt_service, 300, &path).entries().await;
+        let commands: Vec<_> = restored
+            .iter()
+            .map(|entry| (entry.blind_id.as_str(), entry.command.as_str()))
+            .collect();
+        assert_eq!(commands, vec![("b2", "STOP"), ("b1", "CLOSE")]);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_file_is_moved_aside() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("outbox.json");
+        std::fs::write(&path, r#"[{"sequence": 0, "blind_id": "b1""#).unwrap();
+        let (mqtt_service, _eventloop) = test_service();
+
+        let outbox = Outbox::with_file(mqtt_service, 300, &path);
+        assert!(outbox.entries().await.is_empty());
+        let aside: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("outbox.json.corrupt-"))
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(&aside[0])).unwrap(),
+            r#"[{"sequence": 0, "blind_id": "b1""#
+        );
+
+        // New commands are saved to a fresh file, written in one piece
+        outbox
+            .enqueue(
+                "b2",
+                &BlindCommand::Open,
+                "home/b2",
+                "OPEN",
+                PublishOptions::default(),
+                CommandProperties::default(),
+            )
+            .await;
+        assert_eq!(load_entries(&path).unwrap().len(), 1);
+        assert!(!sibling(&path, ".tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_keeps_command_until_published() {
+        let (mqtt_service, eventloop) = test_service();
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        mqtt_service.set_connected(true).await;
+        let deliver = |command: BlindCommand| {
+            let outbox = outbox.clone();
+            async move {
+                outbox
+                    .deliver(
+                        "b1",
+                        &command,
+                        "home/b1",
+                        command.as_str(),
+                        PublishOptions::default(),
+                        CommandProperties::default(),
+                    )
+                    .await
+            }
+        };
+
+        assert_eq!(deliver(BlindCommand::Open).await.unwrap(), Delivery::Sent);
+        assert!(outbox.entries().await.is_empty());
+
+        // The publish fails once the event loop is gone: the command stays queued
+        drop(eventloop);
+        assert_eq!(
+            deliver(BlindCommand::Close).await.unwrap(),
+            Delivery::Queued
+        );
+        let entries = outbox.entries().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "CLOSE");
+    }
+
+    #[tokio::test]
+    async fn test_flush_skips_commands_being_delivered() {
+        // With room for a single request the second publish waits for the event loop
+        let mqtt_options = MqttOptions::new("test", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqtt_options, 1);
+        let mqtt_service = MqttService::new(client);
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        mqtt_service.set_connected(true).await;
+        let deliver = |blind_id: &'static str| {
+            let outbox = outbox.clone();
+            async move {
+                outbox
+                    .deliver(
+                        blind_id,
+                        &BlindCommand::Open,
+                        "home/blind",
+                        "OPEN",
+                        PublishOptions::default(),
+                        CommandProperties::default(),
+                    )
+                    .await
+            }
+        };
+
+        assert_eq!(deliver("b1").await.unwrap(), Delivery::Sent);
+        let pending = tokio::spawn(deliver("b2"));
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert!(!pending.is_finished());
+
+        // Neither the queue nor a flush waits for the publish in progress
+        let sent = tokio::time::timeout(std::time::Duration::from_secs(1), outbox.flush())
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+        let entries = outbox.entries().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].blind_id, "b2");
+    }
+
+    #[tokio::test]
+    async fn test_flush_announces_sent_commands() {
+        let (mqtt_service, _eventloop) = test_service();
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        let mut sent = outbox.subscribe_sent();
+        outbox
+            .enqueue(
+                "b1",
+                &BlindCommand::SetPosition(30),
+                "home/b1",
+                "30",
+                PublishOptions::default(),
+                CommandProperties::default(),
+            )
+            .await;
+
+        mqtt_service.set_connected(true).await;
+        assert_eq!(outbox.flush().await, 1);
+        let entry = sent.try_recv().unwrap();
+        assert_eq!(entry.blind_id, "b1");
+        assert_eq!(entry.blind_command(), Some(BlindCommand::SetPosition(30)));
+    }
+
+    #[tokio::test]
+    async fn test_flush_skips_expired_commands() {
+        let (mqtt_service, _eventloop) = test_service();
+        let outbox = Outbox::new(mqtt_service.clone(), 300);
+        outbox
+            .enqueue(
+                "b1",
+                &BlindCommand::Open,
+                "home/b1",
+                "OPEN",
+                PublishOptions::default(),
+                CommandProperties::default(),
+            )
+            .await;
+        outbox
+            .enqueue(
+                "b2",
+                &BlindCommand::Open,
+                "home/b2",
+                "OPEN",
+                PublishOptions::default(),
+                CommandProperties::default(),
+            )
+            .await;
+        outbox.queue.lock().await.entries[0].expires_at = Utc::now();
+
+        // Sin conexión no se envía nada
+        assert_eq!(outbox.flush().await, 0);
+        assert_eq!(outbox.entries().await.len(), 1);
+
+        mqtt_service.set_connected(true).await;
+        assert_eq!(outbox.flush().await, 1);
+        assert!(outbox.entries().await.is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::config::PayloadFormat;
+use crate::models::blind::BlindCommand;
+
+/// Mensaje MQTT listo para publicar
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedCommand {
+    pub topic: String,
+    pub payload: String,
+}
+
+impl EncodedCommand {
+    fn new(topic: impl Into<String>, payload: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+            payload: payload.into(),
+        }
+    }
+}
+
+/// Equivalente de los comandos para el descubrimiento de Home Assistant
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryCommands {
+    pub command_topic: String,
+    pub payload_open: String,
+    pub payload_close: String,
+    pub payload_stop: String,
+    pub set_position_topic: String,
+    /// Plantilla de Home Assistant; sin ella se envía el número tal cual
+    pub set_position_template: Option<String>,
+}
+
+/// Tema y plantilla de inclinación para el descubrimiento de Home Assistant
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryTilt {
+    pub tilt_command_topic: String,
+    pub tilt_command_template: Option<String>,
+}
+
+/// Traduce un comando al tema y payload que entiende cada ecosistema.
+/// `topic` es el `mqtt_topic` de la persiana (o el tema de inclinación).
+pub trait PayloadEncoder: Send + Sync {
+    /// Devuelve `None` si el protocolo no admite el comando
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand>;
+
+    fn discovery(&self, topic: &str) -> DiscoveryCommands;
+
+    /// `None` si el protocolo no admite inclinación
+    fn discovery_tilt(&self, _topic: &str) -> Option<DiscoveryTilt> {
+        None
+    }
+}
+
+const JSON_POSITION_TEMPLATE: &str = r#"{"position": {{ position }}}"#;
+const JSON_TILT_TEMPLATE: &str = r#"{"tilt": {{ tilt_position }}}"#;
+
+fn json_discovery(topic: String) -> DiscoveryCommands {
+    DiscoveryCommands {
+        command_topic: topic.clone(),
+        payload_open: json_payload(&BlindCommand::Open).unwrap_or_default(),
+        payload_close: json_payload(&BlindCommand::Close).unwrap_or_default(),
+        payload_stop: json_payload(&BlindCommand::Stop).unwrap_or_default(),
+        set_position_topic: topic,
+        set_position_template: Some(JSON_POSITION_TEMPLATE.to_string()),
+    }
+}
+
+/// Codificador para el `payload_format` configurado
+pub fn encoder_for(format: PayloadFormat) -> &'static dyn PayloadEncoder {
+    match format {
+        PayloadFormat::Text => &TextEncoder,
+        PayloadFormat::Json => &JsonEncoder,
+        PayloadFormat::Zigbee2mqtt => &Zigbee2MqttEncoder,
+        PayloadFormat::Tasmota => &TasmotaEncoder,
+        PayloadFormat::Shelly => &ShellyEncoder,
+    }
+}
+
+/// `OPEN`, `CLOSE`, `STOP` o el valor numérico en el mismo tema
+pub struct TextEncoder;
+
+impl PayloadEncoder for TextEncoder {
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand> {
+        let payload = match command.value() {
+            Some(value) => value.to_string(),
+            None => command.as_str().to_string(),
+        };
+        Some(EncodedCommand::new(topic, payload))
+    }
+
+    fn discovery(&self, topic: &str) -> DiscoveryCommands {
+        DiscoveryCommands {
+            command_topic: topic.to_string(),
+            payload_open: BlindCommand::Open.as_str().to_string(),
+            payload_close: BlindCommand::Close.as_str().to_string(),
+            payload_stop: BlindCommand::Stop.as_str().to_string(),
+            set_position_topic: topic.to_string(),
+            set_position_template: None,
+        }
+    }
+
+    fn discovery_tilt(&self, topic: &str) -> Option<DiscoveryTilt> {
+        Some(DiscoveryTilt {
+            tilt_command_topic: topic.to_string(),
+            tilt_command_template: None,
+        })
+    }
+}
+
+/// `{"state": "OPEN"}`, `{"position": 50}` o `{"tilt": 30}` en el mismo tema
+pub struct JsonEncoder;
+
+impl PayloadEncoder for JsonEncoder {
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand> {
+        Some(EncodedCommand::new(topic, json_payload(command)?))
+    }
+
+    fn discovery(&self, topic: &str) -> DiscoveryCommands {
+        json_discovery(topic.to_string())
+    }
+
+    fn discovery_tilt(&self, topic: &str) -> Option<DiscoveryTilt> {
+        Some(DiscoveryTilt {
+            tilt_command_topic: topic.to_string(),
+            tilt_command_template: Some(JSON_TILT_TEMPLATE.to_string()),
+        })
+    }
+}
+
+fn json_payload(command: &BlindCommand) -> Option<String> {
+    let payload = match command {
+        BlindCommand::SetPosition(position) => serde_json::json!({ "position": position }),
+        BlindCommand::SetTilt(tilt) => serde_json::json!({ "tilt": tilt }),
+        BlindCommand::Open | BlindCommand::Close | BlindCommand::Stop => {
+            serde_json::json!({ "state": command.as_str() })
+        }
+        BlindCommand::OpenTilt | BlindCommand::CloseTilt => return None,
+    };
+    Some(payload.to_string())
+}
+
+/// Zigbee2MQTT: JSON en `<topic>/set` (p. ej. `zigbee2mqtt/salon_cover/set`)
+pub struct Zigbee2MqttEncoder;
+
+impl PayloadEncoder for Zigbee2MqttEncoder {
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand> {
+        Some(EncodedCommand::new(
+            zigbee2mqtt_set_topic(topic),
+            json_payload(command)?,
+        ))
+    }
+
+    fn discovery(&self, topic: &str) -> DiscoveryCommands {
+        json_discovery(zigbee2mqtt_set_topic(topic))
+    }
+
+    fn discovery_tilt(&self, topic: &str) -> Option<DiscoveryTilt> {
+        Some(DiscoveryTilt {
+            tilt_command_topic: zigbee2mqtt_set_topic(topic),
+            tilt_command_template: Some(JSON_TILT_TEMPLATE.to_string()),
+        })
+    }
+}
+
+fn zigbee2mqtt_set_topic(topic: &str) -> String {
+    if topic.ends_with("/set") {
+        topic.to_string()
+    } else {
+        format!("{}/set", topic)
+    }
+}
+
+/// Tasmota: `<topic>/ShutterOpen1`, `ShutterClose1`, `ShutterStop1` o
+/// `ShutterPosition1` con `topic = cmnd/<dispositivo>`
+pub struct TasmotaEncoder;
+
+impl PayloadEncoder for TasmotaEncoder {
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand> {
+        let (action, payload) = match command {
+            BlindCommand::Open => ("ShutterOpen1", String::new()),
+            BlindCommand::Close => ("ShutterClose1", String::new()),
+            BlindCommand::Stop => ("ShutterStop1", String::new()),
+            BlindCommand::SetPosition(position) => ("ShutterPosition1", position.to_string()),
+            _ => return None,
+        };
+        Some(EncodedCommand::new(
+            format!("{}/{}", topic, action),
+            payload,
+        ))
+    }
+
+    /// `ShutterPosition1` también acepta `UP`, `DOWN` y `STOP`
+    fn discovery(&self, topic: &str) -> DiscoveryCommands {
+        let position_topic = format!("{}/ShutterPosition1", topic);
+        DiscoveryCommands {
+            command_topic: position_topic.clone(),
+            payload_open: "UP".to_string(),
+            payload_close: "DOWN".to_string(),
+            payload_stop: "STOP".to_string(),
+            set_position_topic: position_topic,
+            set_position_template: None,
+        }
+    }
+}
+
+/// Shelly (Gen1): `open`/`close`/`stop` en `<topic>/command` y la posición en
+/// `<topic>/command/pos` (equivalente a `to_pos`), con `topic = shellies/<id>/roller/0`
+pub struct ShellyEncoder;
+
+impl PayloadEncoder for ShellyEncoder {
+    fn encode(&self, topic: &str, command: &BlindCommand) -> Option<EncodedCommand> {
+        let topic = topic.trim_end_matches("/command");
+        let (suffix, payload) = match command {
+            BlindCommand::Open => ("command", "open".to_string()),
+            BlindCommand::Close => ("command", "close".to_string()),
+            BlindCommand::Stop => ("command", "stop".to_string()),
+            BlindCommand::SetPosition(position) => ("command/pos", position.to_string()),
+            _ => return None,
+        };
+        Some(EncodedCommand::new(
+            format!("{}/{}", topic, suffix),
+            payload,
+        ))
+    }
+
+    fn discovery(&self, topic: &str) -> DiscoveryCommands {
+        let topic = topic.trim_end_matches("/command");
+        DiscoveryCommands {
+            command_topic: format!("{}/command", topic),
+            payload_open: "open".to_string(),
+            payload_close: "close".to_string(),
+            payload_stop: "stop".to_string(),
+            set_position_topic: format!("{}/command/pos", topic),
+            set_position_template: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(
+        format: PayloadFormat,
+        topic: &str,
+        command: BlindCommand,
+    ) -> Option<(String, String)> {
+        encoder_for(format)
+            .encode(topic, &command)
+            .map(|encoded| (encoded.topic, encoded.payload))
+    }
+
+    fn pair(topic: &str, payload: &str) -> Option<(String, String)> {
+        Some((topic.to_string(), payload.to_string()))
+    }
+
+    #[test]
+    fn test_text_and_json_encoders() {
+        assert_eq!(
+            encode(PayloadFormat::Text, "home/b1", BlindCommand::Open),
+            pair("home/b1", "OPEN")
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Text,
+                "home/b1",
+                BlindCommand::SetPosition(40)
+            ),
+            pair("home/b1", "40")
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Text,
+                "home/b1/tilt",
+                BlindCommand::SetTilt(30)
+            ),
+            pair("home/b1/tilt", "30")
+        );
+        assert_eq!(
+            encode(PayloadFormat::Json, "home/b1", BlindCommand::Close),
+            pair("home/b1", r#"{"state":"CLOSE"}"#)
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Json,
+                "home/b1",
+                BlindCommand::SetPosition(40)
+            ),
+            pair("home/b1", r#"{"position":40}"#)
+        );
+        assert_eq!(
+            encode(PayloadFormat::Json, "home/b1", BlindCommand::SetTilt(30)),
+            pair("home/b1", r#"{"tilt":30}"#)
+        );
+    }
+
+    #[test]
+    fn test_zigbee2mqtt_encoder() {
+        assert_eq!(
+            encode(
+                PayloadFormat::Zigbee2mqtt,
+                "zigbee2mqtt/cover",
+                BlindCommand::Open
+            ),
+            pair("zigbee2mqtt/cover/set", r#"{"state":"OPEN"}"#)
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Zigbee2mqtt,
+                "zigbee2mqtt/cover/set",
+                BlindCommand::SetPosition(50)
+            ),
+            pair("zigbee2mqtt/cover/set", r#"{"position":50}"#)
+        );
+    }
+
+    #[test]
+    fn test_tasmota_encoder() {
+        assert_eq!(
+            encode(PayloadFormat::Tasmota, "cmnd/shutter", BlindCommand::Open),
+            pair("cmnd/shutter/ShutterOpen1", "")
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Tasmota,
+                "cmnd/shutter",
+                BlindCommand::SetPosition(25)
+            ),
+            pair("cmnd/shutter/ShutterPosition1", "25")
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Tasmota,
+                "cmnd/shutter",
+                BlindCommand::SetTilt(25)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_shelly_encoder() {
+        assert_eq!(
+            encode(
+                PayloadFormat::Shelly,
+                "shellies/s25/roller/0",
+                BlindCommand::Stop
+            ),
+            pair("shellies/s25/roller/0/command", "stop")
+        );
+        assert_eq!(
+            encode(
+                PayloadFormat::Shelly,
+                "shellies/s25/roller/0/command",
+                BlindCommand::SetPosition(70)
+            ),
+            pair("shellies/s25/roller/0/command/pos", "70")
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::models::blind::PositionEstimate;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
+use tokio::time::Instant;
+
+/// Diferencia mínima (en %) para mover la persiana al emular `SetPosition`
+const POSITION_TOLERANCE_PERCENT: f64 = 0.5;
+
+/// Hora actual según el reloj de tokio, el mismo que temporiza los STOP
+/// programados (y que los tests pueden pausar)
+pub fn now() -> DateTime<Utc> {
+    static ANCHOR: OnceLock<(Instant, DateTime<Utc>)> = OnceLock::new();
+    let (instant, wall) = *ANCHOR.get_or_init(|| (Instant::now(), Utc::now()));
+    wall + chrono::Duration::from_std(Instant::now() - instant).unwrap_or_default()
+}
+
+/// Sentido de un movimiento
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Opening,
+    Closing,
+}
+
+/// Tiempos de recorrido completo de una persiana
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TravelTimes {
+    pub open_secs: f64,
+    pub close_secs: f64,
+}
+
+impl TravelTimes {
+    fn secs(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::Opening => self.open_secs,
+            Direction::Closing => self.close_secs,
+        }
+    }
+}
+
+impl From<(f64, f64)> for TravelTimes {
+    fn from((open_secs, close_secs): (f64, f64)) -> Self {
+        Self {
+            open_secs,
+            close_secs,
+        }
+    }
+}
+
+/// Movimiento a realizar: `direction = None` significa enviar STOP, y
+/// `stop_after` programa un STOP tras ese tiempo
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovePlan {
+    pub direction: Option<Direction>,
+    pub stop_after: Option<Duration>,
+}
+
+impl MovePlan {
+    /// Movimiento hasta el final de carrera (el motor se detiene solo)
+    pub fn full(direction: Direction) -> Self {
+        Self {
+            direction: Some(direction),
+            stop_after: None,
+        }
+    }
+
+    pub fn stop() -> Self {
+        Self {
+            direction: None,
+            stop_after: None,
+        }
+    }
+
+    /// Planifica un movimiento a `target` desde la posición estimada. Los extremos
+    /// (0 y 100) no necesitan posición conocida; devuelve `None` si hace falta.
+    pub fn to_position(current: Option<f64>, target: u8, travel: TravelTimes) -> Option<Self> {
+        match target {
+            0 => return Some(Self::full(Direction::Closing)),
+            100 => return Some(Self::full(Direction::Opening)),
+            _ => {}
+        }
+
+        let delta = target as f64 - current?;
+        if delta.abs() < POSITION_TOLERANCE_PERCENT {
+            return Some(Self::stop());
+        }
+        let direction = if delta > 0.0 {
+            Direction::Opening
+        } else {
+            Direction::Closing
+        };
+        let secs = delta.abs() / 100.0 * travel.secs(direction);
+        Some(Self {
+            direction: Some(direction),
+            stop_after: Some(Duration::from_secs_f64(secs)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Movement {
+    direction: Direction,
+    /// Posición al empezar; `None` si era desconocida
+    from: Option<f64>,
+    started_at: DateTime<Utc>,
+    travel_secs: f64,
+}
+
+impl Movement {
+    fn target(&self) -> f64 {
+        match self.direction {
+            Direction::Opening => 100.0,
+            Direction::Closing => 0.0,
+        }
+    }
+
+    fn position_at(&self, now: DateTime<Utc>) -> Option<f64> {
+        let elapsed = (now - self.started_at).num_milliseconds().max(0) as f64 / 1000.0;
+        if elapsed >= self.travel_secs {
+            return Some(self.target());
+        }
+
+        let moved = elapsed / self.travel_secs * 100.0;
+        self.from.map(|from| match self.direction {
+            Direction::Opening => (from + moved).min(100.0),
+            Direction::Closing => (from - moved).max(0.0),
+        })
+    }
+
+    fn is_running(&self, now: DateTime<Utc>) -> bool {
+        self.position_at(now) != Some(self.target())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Track {
+    position: Option<f64>,
+    movement: Option<Movement>,
+    /// Se incrementa con cada comando para invalidar los STOP programados
+    generation: u64,
+}
+
+impl Track {
+    fn position_at(&self, now: DateTime<Utc>) -> Option<f64> {
+        match &self.movement {
+            Some(movement) => movement.position_at(now),
+            None => self.position,
+        }
+    }
+
+    fn settle(&mut self, now: DateTime<Utc>) {
+        self.position = self.position_at(now);
+        self.movement = None;
+        self.generation += 1;
+    }
+}
+
+/// Estima la posición de motores sin reporte a partir de los comandos enviados
+/// y los tiempos de recorrido configurados
+pub struct PositionEstimator {
+    tracks: Arc<RwLock<HashMap<String, Track>>>,
+    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl PositionEstimator {
+    pub fn new() -> Self {
+        Self {
+            tracks: Arc::new(RwLock::new(HashMap::new())),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reserva el envío de comandos a una persiana. Un STOP programado comprueba
+    /// su generación y se publica con la reserva, sin que otro comando se cuele
+    /// entre ambos pasos.
+    pub async fn lock(&self, blind_id: &str) -> OwnedMutexGuard<()> {
+        let lock = Arc::clone(
+            self.locks
+                .lock()
+                .await
+                .entry(blind_id.to_string())
+                .or_default(),
+        );
+        lock.lock_owned().await
+    }
+
+    /// Registra el inicio de un movimiento y devuelve su generación
+    pub async fn start(
+        &self,
+        blind_id: &str,
+        direction: Direction,
+        travel: TravelTimes,
+        now: DateTime<Utc>,
+    ) -> u64 {
+        let mut tracks = self.tracks.write().await;
+        let track = tracks.entry(blind_id.to_string()).or_default();
+        track.settle(now);
+        track.movement = Some(Movement {
+            direction,
+            from: track.position,
+            started_at: now,
+            travel_secs: travel.secs(direction),
+        });
+        track.generation
+    }
+
+    /// Registra un STOP y devuelve la nueva generación
+    pub async fn stop(&self, blind_id: &str, now: DateTime<Utc>) -> u64 {
+        let mut tracks = self.tracks.write().await;
+        let track = tracks.entry(blind_id.to_string()).or_default();
+        track.settle(now);
+        track.generation
+    }
+
+    /// `true` si no se ha enviado ningún comando desde `generation`
+    pub async fn is_current(&self, blind_id: &str, generation: u64) -> bool {
+        self.tracks
+            .read()
+            .await
+            .get(blind_id)
+            .is_some_and(|track| track.generation == generation)
+    }
+
+    pub async fn position(&self, blind_id: &str, now: DateTime<Utc>) -> Option<f64> {
+        self.tracks
+            .read()
+            .await
+            .get(blind_id)
+            .and_then(|track| track.position_at(now))
+    }
+
+    pub async fn estimate(&self, blind_id: &str, now: DateTime<Utc>) -> Option<PositionEstimate> {
+        let tracks = self.tracks.read().await;
+        let track = tracks.get(blind_id)?;
+        let position = track
+            .position_at(now)
+            .map(|position| position.round() as u8);
+
+        let state = match &track.movement {
+            Some(movement) if movement.is_running(now) => match movement.direction {
+                Direction::Opening => "opening",
+                Direction::Closing => "closing",
+            },
+            _ => match position {
+                Some(0) => "closed",
+                Some(_) => "open",
+                None => "stopped",
+            },
+        };
+
+        Some(PositionEstimate {
+            position,
+            state: state.to_string(),
+        })
+    }
+}
+
+impl Default for PositionEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for PositionEstimator {
+    fn clone(&self) -> Self {
+        Self {
+            tracks: Arc::clone(&self.tracks),
+            locks: Arc::clone(&self.locks),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRAVEL: TravelTimes = TravelTimes {
+        open_secs: 20.0,
+        close_secs: 10.0,
+    };
+
+    fn secs(value: i64) -> chrono::Duration {
+        chrono::Duration::seconds(value)
+    }
+
+    #[test]
+    fn test_plan_to_position() {
+        assert_eq!(
+            MovePlan::to_position(None, 100, TRAVEL),
+            Some(MovePlan::full(Direction::Opening))
+        );
+        assert_eq!(MovePlan::to_position(None, 40, TRAVEL), None);
+        assert_eq!(
+            MovePlan::to_position(Some(40.2), 40, TRAVEL),
+            Some(MovePlan::stop())
+        );
+        assert_eq!(
+            MovePlan::to_position(Some(20.0), 70, TRAVEL),
+            Some(MovePlan {
+                direction: Some(Direction::Opening),
+                stop_after: Some(Duration::from_secs(10)),
+            })
+        );
+        assert_eq!(
+            MovePlan::to_position(Some(70.0), 20, TRAVEL),
+            Some(MovePlan {
+                direction: Some(Direction::Closing),
+                stop_after: Some(Duration::from_secs(5)),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_movement() {
+        let estimator = PositionEstimator::new();
+        let t0 = Utc::now();
+        assert!(estimator.estimate("b1", t0).await.is_none());
+
+        // Desde posición desconocida solo se conoce al llegar al final de carrera
+        estimator.start("b1", Direction::Closing, TRAVEL, t0).await;
+        let estimate = estimator.estimate("b1", t0 + secs(5)).await.unwrap();
+        assert_eq!(estimate.position, None);
+        assert_eq!(estimate.state, "closing");
+        let estimate = estimator.estimate("b1", t0 + secs(10)).await.unwrap();
+        assert_eq!(estimate.position, Some(0));
+        assert_eq!(estimate.state, "closed");
+
+        let t1 = t0 + secs(30);
+        let generation = estimator.start("b1", Direction::Opening, TRAVEL, t1).await;
+        assert_eq!(estimator.position("b1", t1 + secs(5)).await, Some(25.0));
+
+        estimator.stop("b1", t1 + secs(8)).await;
+        assert!(!estimator.is_current("b1", generation).await);
+        let estimate = estimator.estimate("b1", t1 + secs(60)).await.unwrap();
+        assert_eq!(estimate.position, Some(40));
+        assert_eq!(estimate.state, "open");
+    }
+}
@@ -0,0 +1,336 @@
+use crate::config::{BlindConfig, PowerCircuitConfig};
+use crate::models::blind::BlindCommand;
+use crate::models::responses::{
+    BatchControlResponse, BatchHandleResponse, BatchProgressResponse, BatchStatus,
+};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, OwnedSemaphorePermit, Semaphore};
+
+/// Lotes escalonados cuyo progreso se conserva para consultarlo
+const MAX_TRACKED_BATCHES: usize = 50;
+
+/// Resultado de un comando por habitación o global
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum BatchOutcome {
+    /// Todas las persianas procesadas
+    Completed(BatchControlResponse),
+    /// Lote escalonado en segundo plano; el progreso se consulta con `batch_id`
+    Started(BatchHandleResponse),
+}
+
+/// Aviso de cancelación de un lote escalonado
+#[derive(Clone)]
+pub struct BatchCancellation(watch::Receiver<bool>);
+
+impl BatchCancellation {
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Termina cuando se cancela el lote
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.0.clone();
+        // A batch dropped from the tracker can no longer be cancelled
+        if cancelled.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Lote registrado, con sus persianas para poder cancelarlo
+struct TrackedBatch {
+    progress: BatchProgressResponse,
+    blind_ids: Vec<String>,
+    cancel: watch::Sender<bool>,
+}
+
+/// Progreso de los últimos lotes escalonados
+#[derive(Clone, Default)]
+pub struct BatchTracker {
+    batches: Arc<Mutex<VecDeque<TrackedBatch>>>,
+}
+
+impl BatchTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registra un lote nuevo, olvidando el más antiguo si se supera el límite
+    pub async fn start(
+        &self,
+        batch_id: &str,
+        blinds: &[BlindConfig],
+        result: BatchControlResponse,
+    ) -> BatchCancellation {
+        let (cancel, cancellation) = watch::channel(false);
+        let mut batches = self.batches.lock().await;
+        if batches.len() == MAX_TRACKED_BATCHES {
+            batches.pop_front();
+        }
+        batches.push_back(TrackedBatch {
+            progress: BatchProgressResponse {
+                batch_id: batch_id.to_string(),
+                status: BatchStatus::Running,
+                total_blinds: blinds.len(),
+                dispatched: 0,
+                started_at: chrono::Utc::now(),
+                finished_at: None,
+                result,
+            },
+            blind_ids: blinds.iter().map(|blind| blind.id.clone()).collect(),
+            cancel,
+        });
+        BatchCancellation(cancellation)
+    }
+
+    /// Cancela los lotes en marcha que incluyen alguna de las persianas.
+    /// Devuelve cuántos se cancelaron.
+    pub async fn cancel_including(&self, blind_ids: &[&str]) -> usize {
+        let batches = self.batches.lock().await;
+        let mut cancelled = 0;
+        for batch in batches.iter() {
+            if batch.progress.status == BatchStatus::Running
+                && !*batch.cancel.borrow()
+                && batch
+                    .blind_ids
+                    .iter()
+                    .any(|id| blind_ids.contains(&id.as_str()))
+            {
+                batch.cancel.send_replace(true);
+                log::info!("Cancelling staggered batch {}", batch.progress.batch_id);
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
+
+    pub async fn update<R>(
+        &self,
+        batch_id: &str,
+        update: impl FnOnce(&mut BatchProgressResponse) -> R,
+    ) -> Option<R> {
+        let mut batches = self.batches.lock().await;
+        batches
+            .iter_mut()
+            .find(|batch| batch.progress.batch_id == batch_id)
+            .map(|batch| update(&mut batch.progress))
+    }
+
+    /// Cierra el lote, como cancelado si un `STOP` lo interrumpió
+    pub async fn finish(&self, batch_id: &str) {
+        let mut batches = self.batches.lock().await;
+        if let Some(batch) = batches
+            .iter_mut()
+            .find(|batch| batch.progress.batch_id == batch_id)
+        {
+            batch.progress.status = if *batch.cancel.borrow() {
+                BatchStatus::Cancelled
+            } else {
+                BatchStatus::Completed
+            };
+            batch.progress.finished_at = Some(chrono::Utc::now());
+        }
+    }
+
+    pub async fn get(&self, batch_id: &str) -> Option<BatchProgressResponse> {
+        let batches = self.batches.lock().await;
+        batches
+            .iter()
+            .find(|batch| batch.progress.batch_id == batch_id)
+            .map(|batch| batch.progress.clone())
+    }
+}
+
+/// Plazas de un circuito eléctrico
+struct Circuit {
+    max_moving: usize,
+    slots: Arc<Semaphore>,
+    /// Plazas que sobran tras bajar el límite y que se retiran cuando los
+    /// motores que las ocupan terminan
+    excess: Arc<AtomicUsize>,
+}
+
+impl Circuit {
+    fn new(max_moving: usize) -> Self {
+        Self {
+            max_moving,
+            slots: Arc::new(Semaphore::new(max_moving)),
+            excess: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Ajusta el límite sin olvidar los motores que ya están en marcha
+    fn resize(&mut self, max_moving: usize) {
+        if max_moving > self.max_moving {
+            let added = max_moving - self.max_moving;
+            let cancelled = take_excess(&self.excess, added);
+            self.slots.add_permits(added - cancelled);
+        } else {
+            let removed = self.max_moving - max_moving;
+            let forgotten = self.slots.forget_permits(removed);
+            self.excess.fetch_add(removed - forgotten, Ordering::SeqCst);
+        }
+        self.max_moving = max_moving;
+    }
+}
+
+/// Descuenta hasta `amount` plazas sobrantes y devuelve cuántas se descontaron
+fn take_excess(excess: &AtomicUsize, amount: usize) -> usize {
+    let previous = excess
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |excess| {
+            Some(excess - excess.min(amount))
+        })
+        .unwrap_or_default();
+    previous.min(amount)
+}
+
+/// Plaza ocupada por un motor en marcha; se libera al soltarla
+pub struct CircuitSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    excess: Arc<AtomicUsize>,
+}
+
+impl Drop for CircuitSlot {
+    fn drop(&mut self) {
+        // A slot above a lowered limit is retired instead of returned
+        if take_excess(&self.excess, 1) == 1 {
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+/// Plazas libres de cada circuito eléctrico, compartidas por todos los lotes
+#[derive(Clone, Default)]
+pub struct PowerCircuits {
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl PowerCircuits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Espera a que haya menos de `max_moving` motores en marcha en el circuito.
+    /// El motor ocupa la plaza hasta que se suelta.
+    pub async fn acquire(&self, circuit: &str, max_moving: usize) -> CircuitSlot {
+        let (slots, excess) = {
+            let mut circuits = self.circuits.lock().await;
+            let entry = circuits
+                .entry(circuit.to_string())
+                .or_insert_with(|| Circuit::new(max_moving));
+            // A reloaded configuration changes the limit for new movements
+            if entry.max_moving != max_moving {
+                entry.resize(max_moving);
+            }
+            (Arc::clone(&entry.slots), Arc::clone(&entry.excess))
+        };
+        let permit = slots
+            .acquire_owned()
+            .await
+            .expect("power circuit semaphore is never closed");
+        CircuitSlot {
+            permit: Some(permit),
+            excess,
+        }
+    }
+}
+
+/// Tiempo que el motor se considera en marcha tras el comando: el recorrido de la
+/// persiana si se conoce, si no el del circuito
+pub fn move_duration(
+    blind: &BlindConfig,
+    command: &BlindCommand,
+    circuit: &PowerCircuitConfig,
+) -> Duration {
+    let secs = match (blind.travel_times(), command) {
+        (Some((open, _)), BlindCommand::Open) => open,
+        (Some((_, close)), BlindCommand::Close) => close,
+        (Some((open, close)), _) => open.max(close),
+        (None, _) => circuit.move_time_secs,
+    };
+    Duration::from_secs_f64(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_circuit_limits_moving_motors() {
+        let circuits = PowerCircuits::new();
+        let first = circuits.acquire("garage", 2).await;
+        let _second = circuits.acquire("garage", 2).await;
+
+        let waiting = circuits.clone();
+        let third = tokio::spawn(async move { waiting.acquire("garage", 2).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!third.is_finished());
+
+        // Other circuits are independent
+        let _other = circuits.acquire("kitchen", 1).await;
+
+        drop(first);
+        let _third = tokio::time::timeout(Duration::from_secs(1), third)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lowered_limit_counts_moving_motors() {
+        let circuits = PowerCircuits::new();
+        let first = circuits.acquire("garage", 2).await;
+        let second = circuits.acquire("garage", 2).await;
+
+        // Both motors still run after the limit drops to one
+        let waiting = circuits.clone();
+        let third = tokio::spawn(async move { waiting.acquire("garage", 1).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!third.is_finished());
+
+        drop(second);
+        let third = tokio::time::timeout(Duration::from_secs(1), third)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Raising the limit again frees a slot right away
+        let fourth = tokio::time::timeout(Duration::from_secs(1), circuits.acquire("garage", 2))
+            .await
+            .unwrap();
+        drop((third, fourth));
+    }
+
+    #[test]
+    fn test_move_duration_uses_travel_times() {
+        let circuit = PowerCircuitConfig {
+            max_moving: 1,
+            move_time_secs: 30.0,
+        };
+        let mut blind = BlindConfig::default();
+        assert_eq!(
+            move_duration(&blind, &BlindCommand::Open, &circuit),
+            Duration::from_secs(30)
+        );
+
+        blind.travel_time_open_secs = Some(20.0);
+        blind.travel_time_close_secs = Some(15.0);
+        assert_eq!(
+            move_duration(&blind, &BlindCommand::Close, &circuit),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            move_duration(&blind, &BlindCommand::SetPosition(50), &circuit),
+            Duration::from_secs(20)
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use crate::config::StatusFormat;
+use crate::models::blind::{BlindCommand, BlindState, StatusUpdate};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+/// Capacidad del canal de reportes de estado
+const UPDATES_CHANNEL_CAPACITY: usize = 64;
+
+/// Estado en vivo de cada persiana, alimentado por los temas de estado y los
+/// comandos enviados por `BlindService`
+pub struct BlindStateStore {
+    states: Arc<RwLock<HashMap<String, BlindState>>>,
+    updates: broadcast::Sender<StatusUpdate>,
+}
+
+impl BlindStateStore {
+    pub fn new() -> Self {
+        Self {
+            states: Arc::new(RwLock::new(HashMap::new())),
+            updates: broadcast::channel(UPDATES_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Receptor de los reportes de estado que se apliquen a partir de ahora
+    pub fn subscribe_updates(&self) -> broadcast::Receiver<StatusUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Registra un comando enviado a la persiana
+    pub async fn record_command(&self, blind_id: &str, command: &BlindCommand) {
+        let mut states = self.states.write().await;
+        let state = states.entry(blind_id.to_string()).or_default();
+        state.last_command = Some(command.clone());
+        state.last_command_at = Some(Utc::now());
+    }
+
+    /// Aplica un mensaje recibido en el `status_topic` de la persiana.
+    /// Devuelve `false` si el payload no se pudo interpretar.
+    pub async fn apply_status_report(
+        &self,
+        blind_id: &str,
+        payload: &str,
+        format: StatusFormat,
+    ) -> bool {
+        let Some(report) = StatusReport::parse(payload, format) else {
+            log::warn!(
+                "Unrecognised status payload for blind '{}': {}",
+                blind_id,
+                payload
+            );
+            return false;
+        };
+
+        let reported = report
+            .state
+            .or_else(|| report.position.map(state_from_position));
+        {
+            let mut states = self.states.write().await;
+            let state = states.entry(blind_id.to_string()).or_default();
+            if let Some(position) = report.position {
+                state.position = Some(position);
+            }
+            if let Some(tilt) = report.tilt {
+                state.tilt = Some(tilt);
+            }
+            if reported.is_some() {
+                state.state = reported.clone();
+            }
+            state.last_report_at = Some(Utc::now());
+        }
+
+        // Sin receptores (nadie espera confirmación) el envío falla y se ignora
+        let _ = self.updates.send(StatusUpdate {
+            blind_id: blind_id.to_string(),
+            state: reported,
+            position: report.position,
+            tilt: report.tilt,
+        });
+        true
+    }
+
+    /// Aplica un mensaje recibido en el tema de inclinación de la persiana
+    /// (`30` o `{"tilt": 30}`). Devuelve `false` si no se pudo interpretar.
+    pub async fn apply_tilt_report(&self, blind_id: &str, payload: &str) -> bool {
+        let tilt = match serde_json::from_str::<serde_json::Value>(payload.trim()) {
+            Ok(serde_json::Value::Object(object)) => object.get("tilt").and_then(parse_percent),
+            Ok(value) => parse_percent(&value),
+            Err(_) => None,
+        };
+        let Some(tilt) = tilt else {
+            log::warn!(
+                "Unrecognised tilt payload for blind '{}': {}",
+                blind_id,
+                payload
+            );
+            return false;
+        };
+
+        {
+            let mut states = self.states.write().await;
+            let state = states.entry(blind_id.to_string()).or_default();
+            state.tilt = Some(tilt);
+            state.last_report_at = Some(Utc::now());
+        }
+
+        let _ = self.updates.send(StatusUpdate {
+            blind_id: blind_id.to_string(),
+            state: None,
+            position: None,
+            tilt: Some(tilt),
+        });
+        true
+    }
+
+    pub async fn snapshot(&self) -> HashMap<String, BlindState> {
+        self.states.read().await.clone()
+    }
+}
+
+impl Default for BlindStateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for BlindStateStore {
+    fn clone(&self) -> Self {
+        Self {
+            states: Arc::clone(&self.states),
+            updates: self.updates.clone(),
+        }
+    }
+}
+
+/// Contenido interpretado de un mensaje de estado
+#[derive(Debug, Clone, PartialEq)]
+struct StatusReport {
+    state: Option<String>,
+    position: Option<u8>,
+    tilt: Option<u8>,
+}
+
+impl StatusReport {
+    /// Acepta JSON (`{"state": "open", "position": 50, "tilt": 20}`), un número con la
+    /// posición (`50`) o un estado en texto plano (`OPEN`, `closed`, ...), según `format`
+    fn parse(payload: &str, format: StatusFormat) -> Option<Self> {
+        let payload = payload.trim();
+        if payload.is_empty() {
+            return None;
+        }
+
+        match format {
+            StatusFormat::Auto => {}
+            StatusFormat::Json => {
+                return match serde_json::from_str::<serde_json::Value>(payload) {
+                    Ok(serde_json::Value::Object(_)) => Self::parse(payload, StatusFormat::Auto),
+                    _ => None,
+                };
+            }
+            StatusFormat::Position => {
+                let position = serde_json::from_str::<serde_json::Value>(payload).ok()?;
+                return parse_percent(&position).map(|position| Self {
+                    state: None,
+                    position: Some(position),
+                    tilt: None,
+                });
+            }
+            StatusFormat::Text => {
+                return Some(Self {
+                    state: Some(normalize_state(payload.trim_matches('"'))),
+                    position: None,
+                    tilt: None,
+                });
+            }
+        }
+
+        match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(serde_json::Value::Object(object)) => {
+                // Un campo `error` sin `state` se interpreta como estado de error
+                let state = object
+                    .get("state")
+                    .and_then(|value| value.as_str())
+                    .map(normalize_state)
+                    .or_else(|| object.get("error").map(|_| "error".to_string()));
+                let position = object.get("position").and_then(parse_percent);
+                let tilt = object.get("tilt").and_then(parse_percent);
+                if state.is_none() && position.is_none() && tilt.is_none() {
+                    return None;
+                }
+                Some(Self {
+                    state,
+                    position,
+                    tilt,
+                })
+            }
+            Ok(value @ serde_json::Value::Number(_)) => {
+                parse_percent(&value).map(|position| Self {
+                    state: None,
+                    position: Some(position),
+                    tilt: None,
+                })
+            }
+            Ok(serde_json::Value::String(state)) => Some(Self {
+                state: Some(normalize_state(&state)),
+                position: None,
+                tilt: None,
+            }),
+            Ok(_) => None,
+            Err(_) => Some(Self {
+                state: Some(normalize_state(payload)),
+                position: None,
+                tilt: None,
+            }),
+        }
+    }
+}
+
+fn parse_percent(value: &serde_json::Value) -> Option<u8> {
+    value
+        .as_f64()
+        .filter(|percent| (0.0..=100.0).contains(percent))
+        .map(|percent| percent.round() as u8)
+}
+
+fn normalize_state(state: &str) -> String {
+    let state = state.trim().to_lowercase();
+    match state.as_str() {
+        "open" | "opened" | "on" | "up" => "open".to_string(),
+        "close" | "closed" | "off" | "down" => "closed".to_string(),
+        "stop" | "stopped" => "stopped".to_string(),
+        _ => state,
+    }
+}
+
+fn state_from_position(position: u8) -> String {
+    if position == 0 {
+        "closed".to_string()
+    } else {
+        "open".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status_report() {
+        assert_eq!(
+            StatusReport::parse("OPEN", StatusFormat::Auto),
+            Some(StatusReport {
+                state: Some("open".to_string()),
+                position: None,
+                tilt: None
+            })
+        );
+        assert_eq!(
+            StatusReport::parse("42", StatusFormat::Auto),
+            Some(StatusReport {
+                state: None,
+                position: Some(42),
+                tilt: None
+            })
+        );
+        assert_eq!(
+            StatusReport::parse(
+                r#"{"state":"CLOSING","position":30,"tilt":10}"#,
+                StatusFormat::Auto
+            ),
+            Some(StatusReport {
+                state: Some("closing".to_string()),
+                position: Some(30),
+                tilt: Some(10)
+            })
+        );
+        assert_eq!(StatusReport::parse("150", StatusFormat::Auto), None);
+        assert_eq!(
+            StatusReport::parse(r#"{"foo":1}"#, StatusFormat::Auto),
+            None
+        );
+        assert_eq!(StatusReport::parse("  ", StatusFormat::Auto), None);
+
+        // Con formato explícito no se adivina
+        assert_eq!(StatusReport::parse("42", StatusFormat::Json), None);
+        assert_eq!(StatusReport::parse("OPEN", StatusFormat::Position), None);
+        assert_eq!(
+            StatusReport::parse("42", StatusFormat::Text),
+            Some(StatusReport {
+                state: Some("42".to_string()),
+                position: None,
+                tilt: None
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_status_report_and_command() {
+        let store = BlindStateStore::new();
+
+        assert!(
+            store
+                .apply_status_report("blind_001", "0", StatusFormat::Auto)
+                .await
+        );
+        store.record_command("blind_001", &BlindCommand::Open).await;
+
+        let states = store.snapshot().await;
+        let state = &states["blind_001"];
+        assert_eq!(state.state.as_deref(), Some("closed"));
+        assert_eq!(state.position, Some(0));
+        assert_eq!(state.last_command, Some(BlindCommand::Open));
+        assert!(state.last_report_at.is_some());
+        assert!(state.last_command_at.is_some());
+
+        assert!(
+            store
+                .apply_tilt_report("blind_001", r#"{"tilt": 45}"#)
+                .await
+        );
+        assert!(!store.apply_tilt_report("blind_001", "sideways").await);
+        let states = store.snapshot().await;
+        assert_eq!(states["blind_001"].tilt, Some(45));
+        assert_eq!(states["blind_001"].state.as_deref(), Some("closed"));
+
+        assert!(
+            !store
+                .apply_status_report("blind_001", "[]", StatusFormat::Auto)
+                .await
+        );
+        assert!(!store.snapshot().await.contains_key("unknown"));
+    }
+}
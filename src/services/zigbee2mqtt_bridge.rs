@@ -0,0 +1,387 @@
+use crate::config::{BlindConfig, PayloadFormat, SharedConfig, TiltConfig};
+use crate::errors::AppError;
+use crate::services::mqtt_service::{MqttMessage, MqttService};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+
+/// Habitación asignada a las persianas adoptadas sin habitación
+const UNASSIGNED_ROOM: &str = "unassigned";
+
+/// Cortina anunciada por el puente Zigbee2MQTT en `<base_topic>/bridge/devices`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BridgeCover {
+    pub ieee_address: String,
+    pub friendly_name: String,
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub position: bool,
+    pub tilt: bool,
+    /// Propiedad del estado que reporta la batería (`battery` o `voltage`)
+    pub battery: Option<String>,
+}
+
+/// Persiana candidata a añadir a la configuración
+#[derive(Debug, Clone, Serialize)]
+pub struct Zigbee2MqttCandidate {
+    #[serde(flatten)]
+    pub device: BridgeCover,
+    pub blind: BlindConfig,
+}
+
+/// Datos opcionales al adoptar una candidata; por defecto se usan los prefijados
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AdoptRequest {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub room: Option<String>,
+}
+
+/// Detecta las cortinas del puente Zigbee2MQTT y permite adoptarlas como persianas
+pub struct Zigbee2MqttBridge {
+    mqtt_service: MqttService,
+    config: SharedConfig,
+    covers: Arc<RwLock<Vec<BridgeCover>>>,
+}
+
+impl Zigbee2MqttBridge {
+    pub fn new(mqtt_service: MqttService, config: impl Into<SharedConfig>) -> Self {
+        Self {
+            mqtt_service,
+            config: config.into(),
+            covers: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Se suscribe a la lista de dispositivos (retenida) del puente
+    pub async fn start(&self) -> Result<(), AppError> {
+        let mut receiver = self.mqtt_service.subscribe_messages();
+        self.mqtt_service
+            .subscribe_to_topic(&self.devices_topic())
+            .await?;
+
+        let bridge = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => bridge.handle_message(&message).await,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!(
+                            "Zigbee2MQTT bridge lagged, {} MQTT messages skipped",
+                            skipped
+                        );
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn handle_message(&self, message: &MqttMessage) {
+        if message.topic != self.devices_topic() {
+            return;
+        }
+
+        match serde_json::from_slice::<Value>(&message.payload) {
+            Ok(devices) => {
+                let covers = parse_bridge_devices(&devices);
+                log::info!("Zigbee2MQTT bridge reports {} cover devices", covers.len());
+                *self.covers.write().await = covers;
+            }
+            Err(e) => log::warn!("Invalid Zigbee2MQTT device list: {}", e),
+        }
+    }
+
+    /// Cortinas detectadas que todavía no están configuradas
+    pub async fn candidates(&self) -> Vec<Zigbee2MqttCandidate> {
+        let config = self.config.snapshot();
+        let base_topic = &config.zigbee2mqtt.base_topic;
+
+        self.covers
+            .read()
+            .await
+            .iter()
+            .map(|device| Zigbee2MqttCandidate {
+                blind: candidate_blind(base_topic, device),
+                device: device.clone(),
+            })
+            .filter(|candidate| {
+                !config
+                    .blinds
+                    .iter()
+                    .any(|blind| blind.mqtt_topic == candidate.blind.mqtt_topic)
+            })
+            .collect()
+    }
+
+    /// Añade la candidata con esa dirección IEEE a la configuración y la guarda
+    pub async fn adopt(
+        &self,
+        ieee_address: &str,
+        request: AdoptRequest,
+    ) -> Result<BlindConfig, AppError> {
+        let candidate = self
+            .candidates()
+            .await
+            .into_iter()
+            .find(|candidate| candidate.device.ieee_address == ieee_address)
+            .ok_or_else(|| AppError::DeviceNotFound(ieee_address.to_string()))?;
+
+        let mut blind = candidate.blind;
+        if let Some(id) = request.id {
+            blind.id = id;
+        }
+        if let Some(name) = request.name {
+            blind.name = name;
+        }
+        if let Some(room) = request.room {
+            blind.room = room;
+        }
+
+        let adopted = blind.clone();
+        self.config
+            .update(|config| config.add_blind(blind))
+            .map_err(AppError::ValidationError)?;
+
+        log::info!(
+            "Adopted Zigbee2MQTT device {} as blind {}",
+            ieee_address,
+            adopted.id
+        );
+        Ok(adopted)
+    }
+
+    fn devices_topic(&self) -> String {
+        format!(
+            "{}/bridge/devices",
+            self.config.snapshot().zigbee2mqtt.base_topic
+        )
+    }
+}
+
+impl Clone for Zigbee2MqttBridge {
+    fn clone(&self) -> Self {
+        Self {
+            mqtt_service: self.mqtt_service.clone(),
+            config: self.config.clone(),
+            covers: Arc::clone(&self.covers),
+        }
+    }
+}
+
+/// Extrae las cortinas de la lista de dispositivos del puente
+pub fn parse_bridge_devices(devices: &Value) -> Vec<BridgeCover> {
+    let Some(devices) = devices.as_array() else {
+        return Vec::new();
+    };
+
+    devices.iter().filter_map(parse_cover).collect()
+}
+
+fn parse_cover(device: &Value) -> Option<BridgeCover> {
+    let definition = device.get("definition")?;
+    let exposes = definition.get("exposes")?.as_array()?;
+    let cover = exposes
+        .iter()
+        .find(|expose| expose.get("type").and_then(Value::as_str) == Some("cover"))?;
+
+    let features: Vec<&str> = cover
+        .get("features")
+        .and_then(Value::as_array)
+        .map(|features| {
+            features
+                .iter()
+                .filter_map(|feature| feature.get("property").and_then(Value::as_str))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // Batería en porcentaje si existe; si no, el voltaje
+    let battery = ["battery", "voltage"]
+        .into_iter()
+        .find(|property| {
+            exposes
+                .iter()
+                .any(|expose| expose.get("property").and_then(Value::as_str) == Some(*property))
+        })
+        .map(str::to_string);
+
+    let text =
+        |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+    Some(BridgeCover {
+        ieee_address: text(device, "ieee_address")?,
+        friendly_name: text(device, "friendly_name")?,
+        vendor: text(definition, "vendor"),
+        model: text(definition, "model"),
+        position: features.contains(&"position"),
+        tilt: features.contains(&"tilt"),
+        battery,
+    })
+}
+
+/// Configuración prefijada para una cortina del puente
+pub fn candidate_blind(base_topic: &str, device: &BridgeCover) -> BlindConfig {
+    let topic = format!("{}/{}", base_topic, device.friendly_name);
+    let device_type = if device.position {
+        "zigbee_cover"
+    } else {
+        "zigbee_relay_cover"
+    };
+
+    BlindConfig {
+        id: blind_id(&device.friendly_name),
+        name: device.friendly_name.clone(),
+        room: UNASSIGNED_ROOM.to_string(),
+        mqtt_topic: topic.clone(),
+        device_type: device_type.to_string(),
+        enabled: true,
+        battery_topic: device.battery.as_ref().map(|_| topic.clone()),
+        status_topic: Some(topic),
+        payload_format: Some(PayloadFormat::Zigbee2mqtt),
+        tilt: device.tilt.then_some(TiltConfig {
+            command_topic: None,
+            status_topic: None,
+            payload_format: PayloadFormat::Zigbee2mqtt,
+            open_value: 50,
+            closed_value: 0,
+        }),
+        travel_time_open_secs: None,
+        travel_time_close_secs: None,
+        qos: None,
+        retain: None,
+        power_circuit: None,
+    }
+}
+
+/// Identificador derivado del `friendly_name` (que puede contener `/` y espacios)
+fn blind_id(friendly_name: &str) -> String {
+    friendly_name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use serde_json::json;
+
+    fn bridge_devices() -> Value {
+        json!([
+            {
+                "ieee_address": "0x00124b0001",
+                "friendly_name": "Coordinator",
+                "type": "Coordinator",
+                "definition": null
+            },
+            {
+                "ieee_address": "0x00158d0002",
+                "friendly_name": "Living Room/Blind",
+                "type": "EndDevice",
+                "definition": {
+                    "vendor": "IKEA",
+                    "model": "E1757",
+                    "exposes": [
+                        {
+                            "type": "cover",
+                            "features": [
+                                {"name": "state", "property": "state"},
+                                {"name": "position", "property": "position"},
+                                {"name": "tilt", "property": "tilt"}
+                            ]
+                        },
+                        {"type": "numeric", "name": "battery", "property": "battery"}
+                    ]
+                }
+            },
+            {
+                "ieee_address": "0x00158d0003",
+                "friendly_name": "garage_door",
+                "type": "Router",
+                "definition": {
+                    "exposes": [
+                        {"type": "cover", "features": [{"name": "state", "property": "state"}]},
+                        {"type": "numeric", "name": "voltage", "property": "voltage"}
+                    ]
+                }
+            },
+            {
+                "ieee_address": "0x00158d0004",
+                "friendly_name": "lamp",
+                "definition": {"exposes": [{"type": "light"}]}
+            }
+        ])
+    }
+
+    #[test]
+    fn test_parse_bridge_devices() {
+        let covers = parse_bridge_devices(&bridge_devices());
+        assert_eq!(covers.len(), 2);
+
+        assert_eq!(covers[0].friendly_name, "Living Room/Blind");
+        assert_eq!(covers[0].model.as_deref(), Some("E1757"));
+        assert!(covers[0].position && covers[0].tilt);
+        assert_eq!(covers[0].battery.as_deref(), Some("battery"));
+
+        assert!(!covers[1].position && !covers[1].tilt);
+        assert_eq!(covers[1].battery.as_deref(), Some("voltage"));
+    }
+
+    #[test]
+    fn test_candidate_blind() {
+        let covers = parse_bridge_devices(&bridge_devices());
+
+        let blind = candidate_blind("zigbee2mqtt", &covers[0]);
+        assert_eq!(blind.id, "living_room_blind");
+        assert_eq!(blind.mqtt_topic, "zigbee2mqtt/Living Room/Blind");
+        assert_eq!(
+            blind.battery_topic.as_deref(),
+            Some(blind.mqtt_topic.as_str())
+        );
+        assert_eq!(blind.device_type, "zigbee_cover");
+        assert!(blind.tilt.is_some());
+
+        let blind = candidate_blind("zigbee2mqtt", &covers[1]);
+        assert_eq!(blind.device_type, "zigbee_relay_cover");
+        assert!(blind.tilt.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_adopt_candidate() {
+        let (client, _eventloop) =
+            rumqttc::AsyncClient::new(rumqttc::MqttOptions::new("test", "localhost", 1883), 10);
+        let config = SharedConfig::new(AppConfig::default());
+        let bridge = Zigbee2MqttBridge::new(MqttService::new(client), config.clone());
+
+        bridge
+            .handle_message(&MqttMessage {
+                topic: "zigbee2mqtt/bridge/devices".to_string(),
+                payload: bridge_devices().to_string().into_bytes(),
+                ..MqttMessage::default()
+            })
+            .await;
+        assert_eq!(bridge.candidates().await.len(), 2);
+
+        let result = bridge.adopt("0x00000000", AdoptRequest::default()).await;
+        assert!(matches!(result, Err(AppError::DeviceNotFound(_))));
+
+        let request = AdoptRequest {
+            room: Some("garage".to_string()),
+            ..Default::default()
+        };
+        let blind = bridge.adopt("0x00158d0003", request).await.unwrap();
+        assert_eq!(blind.room, "garage");
+        assert!(config.snapshot().get_blind_by_id("garage_door").is_some());
+
+        // Ya configurada: deja de ser candidata
+        let candidates = bridge.candidates().await;
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].device.ieee_address, "0x00158d0002");
+    }
+}